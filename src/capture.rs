@@ -0,0 +1,75 @@
+use crate::frame;
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::time::Instant;
+use tokio_modbus::prelude::SlaveId;
+
+/// Single request handled by the server together with its response
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub timestamp: String,
+    /// Milliseconds since the start of the capture
+    pub elapsed_ms: u64,
+    pub slave: SlaveId,
+    pub function: u8,
+    /// Address range `[start, end)` of register and coil accesses
+    pub range: Option<(usize, usize)>,
+    /// Request protocol data unit as hex string
+    pub request: String,
+    /// Response protocol data unit as hex string, `None` if the request was left unanswered
+    pub response: Option<String>,
+}
+
+impl Entry {
+    pub fn request_pdu(&self) -> anyhow::Result<Vec<u8>> {
+        frame::from_hex(&self.request)
+    }
+}
+
+/// Writes every request and response handled by the server to a JSON Lines file
+pub struct Capture {
+    file: BufWriter<File>,
+    start: Instant,
+}
+
+impl Capture {
+    pub fn new(path: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            file: BufWriter::new(File::create(path)?),
+            start: Instant::now(),
+        })
+    }
+
+    pub fn write(
+        &mut self,
+        slave: SlaveId,
+        request: &[u8],
+        range: Option<(usize, usize)>,
+        response: Option<&[u8]>,
+    ) -> anyhow::Result<()> {
+        let entry = Entry {
+            timestamp: Local::now().to_rfc3339(),
+            elapsed_ms: self.start.elapsed().as_millis() as u64,
+            slave,
+            function: request.first().copied().unwrap_or_default(),
+            range,
+            request: frame::to_hex(request),
+            response: response.map(frame::to_hex),
+        };
+        writeln!(self.file, "{}", serde_json::to_string(&entry)?)?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// Read all entries of a capture file
+pub fn load(path: &str) -> anyhow::Result<Vec<Entry>> {
+    BufReader::new(File::open(path)?)
+        .lines()
+        .filter(|line| !line.as_ref().is_ok_and(|l| l.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
@@ -0,0 +1,372 @@
+use crate::mem::memory::Memory;
+use crate::msg::{Command, LogMsg, Status};
+use crate::record::Recorder;
+use crate::rtu::client::Client as RtuClient;
+use crate::rtu::server::Server as RtuServer;
+use crate::rtu::{RtuConfig, SerialConfig};
+use crate::service::Service;
+use crate::tcp::client::Client as TcpClient;
+use crate::tcp::server::Server as TcpServer;
+use crate::tcp::tls::TlsConfig;
+use crate::tcp::{TcpConfig, Transport};
+use crate::udp::server::Server as UdpServer;
+use crate::udp::UdpConfig;
+use crate::{AppConfig, Commands, ContiguousMemory};
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{Receiver, Sender};
+
+fn default_port() -> u16 {
+    502
+}
+
+fn default_retries() -> u8 {
+    2
+}
+
+fn default_baud_rate() -> u32 {
+    115200
+}
+
+/// Certificates of a Modbus/TCP Security connection
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Tls {
+    ca: Option<String>,
+    cert: Option<String>,
+    key: Option<String>,
+    server_name: Option<String>,
+    client_auth: Option<bool>,
+}
+
+/// Device a named connection talks to (client mode) or interface a server endpoint listens on
+/// (server mode), selected by `transport`
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "transport", rename_all = "snake_case")]
+pub enum Endpoint {
+    Tcp {
+        ip: String,
+        #[serde(default = "default_port")]
+        port: u16,
+        tls: Option<Tls>,
+    },
+    RtuOverTcp {
+        ip: String,
+        #[serde(default = "default_port")]
+        port: u16,
+        tls: Option<Tls>,
+    },
+    Udp {
+        ip: String,
+        #[serde(default = "default_port")]
+        port: u16,
+        #[serde(default = "default_retries")]
+        retries: u8,
+    },
+    Rtu {
+        path: String,
+        #[serde(default = "default_baud_rate")]
+        baud_rate: u32,
+        parity: Option<String>,
+        data_bits: Option<u8>,
+        stop_bits: Option<u8>,
+        #[serde(default)]
+        ascii: bool,
+    },
+}
+
+/// Client connection of a session with several devices. Definitions reference the connection
+/// by name, the timing falls back to the global settings.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Connection {
+    #[serde(flatten)]
+    endpoint: Endpoint,
+    interval_ms: Option<u64>,
+    delay_after_connect_ms: Option<u64>,
+    timeout_ms: Option<u64>,
+    contiguous_memory: Option<Vec<ContiguousMemory>>,
+}
+
+impl Endpoint {
+    /// Command line equivalent of the endpoint
+    pub fn command(&self) -> Commands {
+        let tcp = |ip: &String, port: u16, tls: &Option<Tls>, transport| TcpConfig {
+            ip: ip.clone(),
+            port,
+            transport,
+            tls: tls
+                .as_ref()
+                .map(|tls| TlsConfig {
+                    tls: true,
+                    ca: tls.ca.clone(),
+                    cert: tls.cert.clone(),
+                    key: tls.key.clone(),
+                    client_auth: tls.client_auth.unwrap_or(false),
+                    server_name: tls.server_name.clone(),
+                })
+                .unwrap_or_default(),
+            oneshot: None,
+        };
+        match *self {
+            Endpoint::Tcp {
+                ref ip,
+                port,
+                ref tls,
+            } => Commands::Tcp(tcp(ip, port, tls, Transport::Tcp)),
+            Endpoint::RtuOverTcp {
+                ref ip,
+                port,
+                ref tls,
+            } => Commands::RtuOverTcp(tcp(ip, port, tls, Transport::RtuOverTcp)),
+            Endpoint::Udp {
+                ref ip,
+                port,
+                retries,
+            } => Commands::Udp(UdpConfig {
+                ip: ip.clone(),
+                port,
+                retries,
+                oneshot: None,
+            }),
+            Endpoint::Rtu {
+                ref path,
+                baud_rate,
+                ref parity,
+                data_bits,
+                stop_bits,
+                ascii,
+            } => Commands::Rtu(RtuConfig {
+                serial: SerialConfig {
+                    path: path.clone(),
+                    baud_rate,
+                    parity: parity.clone(),
+                    data_bits,
+                    stop_bits,
+                    flow_control: None,
+                    ascii,
+                },
+                client_id: 1,
+                oneshot: None,
+            }),
+        }
+    }
+}
+
+impl Connection {
+    pub fn contiguous_memory(&self) -> Option<&Vec<ContiguousMemory>> {
+        self.contiguous_memory.as_ref()
+    }
+
+    /// Command line equivalent of the connection
+    pub fn command(&self) -> Commands {
+        self.endpoint.command()
+    }
+}
+
+/// Timing of the polling of a connection
+#[derive(Copy, Clone, Debug)]
+pub struct Timing {
+    pub delay_after_connect_ms: u64,
+    pub interval_ms: u64,
+    pub timeout_ms: u64,
+}
+
+/// Connections polled by the client with their index and timing. The connection of the command
+/// line only polls the definitions without connection and is skipped if there are none.
+pub fn plan(
+    config: &AppConfig,
+    command: Commands,
+    timing: Timing,
+) -> Vec<(usize, Commands, Timing)> {
+    let connections = config.connections();
+    let is_used = |index: usize| {
+        config
+            .definitions
+            .values()
+            .any(|def| def.unit().connection == index)
+    };
+    let mut plan = Vec::new();
+    if connections.is_empty() || is_used(0) {
+        plan.push((0, command, timing));
+    }
+    for (index, (_, connection)) in connections.into_iter().enumerate() {
+        if is_used(index + 1) {
+            plan.push((
+                index + 1,
+                connection.command(),
+                Timing {
+                    delay_after_connect_ms: connection
+                        .delay_after_connect_ms
+                        .unwrap_or(timing.delay_after_connect_ms),
+                    interval_ms: connection.interval_ms.unwrap_or(timing.interval_ms),
+                    timeout_ms: connection.timeout_ms.unwrap_or(timing.timeout_ms),
+                },
+            ));
+        }
+    }
+    plan
+}
+
+/// Prefix the status message with the name of the connection, or its index for the connection of
+/// the command line. Without named connections the message is unchanged.
+pub fn status(config: &AppConfig, index: usize, msg: String) -> Status {
+    let connections = config.connections();
+    if connections.is_empty() {
+        return Status::String(msg);
+    }
+    let label = match index {
+        0 => index.to_string(),
+        i => connections
+            .get(i - 1)
+            .map(|(name, _)| (*name).clone())
+            .unwrap_or_else(|| i.to_string()),
+    };
+    Status::String(format!("[{label}] {msg}"))
+}
+
+/// Forward every command to the client of its connection, connect and disconnect to all clients
+pub async fn dispatch(mut receiver: Receiver<Command>, senders: HashMap<usize, Sender<Command>>) {
+    while let Some(command) = receiver.recv().await {
+        match command.connection() {
+            Some(index) => {
+                if let Some(sender) = senders.get(&index) {
+                    let _ = sender.send(command).await;
+                }
+            }
+            None => {
+                for sender in senders.values() {
+                    let _ = sender.send(command.clone()).await;
+                }
+            }
+        }
+    }
+}
+
+/// Client of a single connection
+pub enum Client {
+    Tcp(TcpClient),
+    Rtu(RtuClient),
+}
+
+impl Client {
+    pub fn new(
+        command: Commands,
+        app_config: Arc<Mutex<AppConfig>>,
+        memory: Arc<Mutex<Memory>>,
+        status_sender: Sender<Status>,
+        cmd_receiver: Receiver<Command>,
+        log_sender: Sender<LogMsg>,
+        recorder: Option<Arc<Mutex<Recorder>>>,
+    ) -> Self {
+        match command {
+            Commands::Tcp(config) | Commands::RtuOverTcp(config) => Client::Tcp(TcpClient::new(
+                app_config,
+                config,
+                memory,
+                status_sender,
+                cmd_receiver,
+                log_sender,
+                recorder,
+            )),
+            Commands::Udp(config) => Client::Tcp(TcpClient::new(
+                app_config,
+                config.connection(),
+                memory,
+                status_sender,
+                cmd_receiver,
+                log_sender,
+                recorder,
+            )),
+            Commands::Rtu(config) => Client::Rtu(RtuClient::new(
+                app_config,
+                config,
+                memory,
+                status_sender,
+                cmd_receiver,
+                log_sender,
+                recorder,
+            )),
+            Commands::Gateway(_) | Commands::Convert(_) => {
+                unreachable!("No client available for the command.")
+            }
+        }
+    }
+
+    pub fn with_connection(self, connection: usize) -> Self {
+        match self {
+            Client::Tcp(client) => Client::Tcp(client.with_connection(connection)),
+            Client::Rtu(client) => Client::Rtu(client.with_connection(connection)),
+        }
+    }
+
+    pub async fn run(&mut self, timing: Timing) {
+        match self {
+            Client::Tcp(client) => {
+                client
+                    .run(
+                        timing.delay_after_connect_ms,
+                        timing.interval_ms,
+                        timing.timeout_ms,
+                    )
+                    .await
+            }
+            Client::Rtu(client) => {
+                client
+                    .run(
+                        timing.delay_after_connect_ms,
+                        timing.interval_ms,
+                        timing.timeout_ms,
+                    )
+                    .await
+            }
+        }
+    }
+}
+
+/// Server of a single endpoint
+pub enum Server {
+    Tcp(TcpServer),
+    Udp(UdpServer),
+    Rtu(RtuServer),
+}
+
+impl Server {
+    pub fn new(
+        command: Commands,
+        service: Service,
+        status_sender: Sender<Status>,
+        log_sender: Sender<LogMsg>,
+    ) -> Self {
+        // Every endpoint is a bus of its own
+        let service = service.with_counters(Arc::default());
+        match command {
+            Commands::Tcp(config) | Commands::RtuOverTcp(config) => {
+                Server::Tcp(TcpServer::new(config, service, status_sender, log_sender))
+            }
+            Commands::Udp(config) => Server::Udp(UdpServer::new(
+                config.connection(),
+                service,
+                status_sender,
+                log_sender,
+            )),
+            Commands::Rtu(config) => Server::Rtu(RtuServer::new(
+                config.serial,
+                service,
+                status_sender,
+                log_sender,
+            )),
+            Commands::Gateway(_) | Commands::Convert(_) => {
+                unreachable!("No server available for the command.")
+            }
+        }
+    }
+
+    pub async fn run(&self) {
+        match self {
+            Server::Tcp(server) => server.run().await,
+            Server::Udp(server) => server.run().await,
+            Server::Rtu(server) => server.run().await,
+        }
+    }
+}
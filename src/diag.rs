@@ -0,0 +1,265 @@
+use crate::frame;
+use crate::util::str;
+
+use clap::ValueEnum;
+use std::io::{Error, ErrorKind};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::Duration;
+use tokio_modbus::bytes::Bytes;
+use tokio_modbus::client::Context;
+use tokio_modbus::prelude::{ExceptionCode, Request, Response};
+
+/// Function code of Diagnostics
+pub const DIAGNOSTICS: u8 = 0x08;
+
+/// Function code of Report Server ID
+pub const REPORT_SERVER_ID: u8 = 0x11;
+
+/// Sub-functions of Diagnostics (FC 08) supported by the server
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SubFunction {
+    /// Echo the data of the request
+    ReturnQueryData = 0x00,
+    /// Clear all counters (the serial port is never reinitialized)
+    RestartCommunications = 0x01,
+    /// Diagnostic register (always zero)
+    DiagnosticRegister = 0x02,
+    /// Clear all counters and the diagnostic register
+    ClearCounters = 0x0A,
+    /// Messages detected on the bus
+    BusMessages = 0x0B,
+    /// Frames with an invalid checksum or incomplete frames
+    BusCommunicationErrors = 0x0C,
+    /// Exception responses sent
+    BusExceptionErrors = 0x0D,
+    /// Messages addressed to the server
+    ServerMessages = 0x0E,
+    /// Messages addressed to the server left unanswered
+    ServerNoResponses = 0x0F,
+    /// Negative acknowledge responses sent (always zero)
+    ServerNaks = 0x10,
+    /// Server device busy responses sent (always zero)
+    ServerBusy = 0x11,
+    /// Characters lost due to a character overrun (always zero)
+    BusCharacterOverruns = 0x12,
+    /// Clear the overrun counter and flag
+    ClearOverrunCounter = 0x14,
+}
+
+impl SubFunction {
+    /// Sub-functions returning a counter
+    pub const COUNTERS: [SubFunction; 8] = [
+        SubFunction::BusMessages,
+        SubFunction::BusCommunicationErrors,
+        SubFunction::BusExceptionErrors,
+        SubFunction::ServerMessages,
+        SubFunction::ServerNoResponses,
+        SubFunction::ServerNaks,
+        SubFunction::ServerBusy,
+        SubFunction::BusCharacterOverruns,
+    ];
+
+    pub fn value(&self) -> u16 {
+        *self as u16
+    }
+
+    fn from_value(value: u16) -> Option<Self> {
+        SubFunction::value_variants()
+            .iter()
+            .find(|sub| sub.value() == value)
+            .copied()
+    }
+}
+
+/// Counters kept by the server
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Counter {
+    BusMessages,
+    BusCommunicationErrors,
+    BusExceptionErrors,
+    ServerMessages,
+    ServerNoResponses,
+}
+
+/// Diagnostic counters of a server endpoint. All counters roll over at 65535 like specified.
+#[derive(Debug, Default)]
+pub struct Counters {
+    bus_messages: AtomicU16,
+    bus_communication_errors: AtomicU16,
+    bus_exception_errors: AtomicU16,
+    server_messages: AtomicU16,
+    server_no_responses: AtomicU16,
+}
+
+impl Counters {
+    fn counter(&self, counter: Counter) -> &AtomicU16 {
+        match counter {
+            Counter::BusMessages => &self.bus_messages,
+            Counter::BusCommunicationErrors => &self.bus_communication_errors,
+            Counter::BusExceptionErrors => &self.bus_exception_errors,
+            Counter::ServerMessages => &self.server_messages,
+            Counter::ServerNoResponses => &self.server_no_responses,
+        }
+    }
+
+    pub fn increment(&self, counter: Counter) {
+        self.counter(counter).fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self, counter: Counter) -> u16 {
+        self.counter(counter).load(Ordering::Relaxed)
+    }
+
+    pub fn clear(&self) {
+        for counter in [
+            Counter::BusMessages,
+            Counter::BusCommunicationErrors,
+            Counter::BusExceptionErrors,
+            Counter::ServerMessages,
+            Counter::ServerNoResponses,
+        ] {
+            self.counter(counter).store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Answer a Diagnostics (FC 08) request with the given data (sub-function and data field)
+    pub fn respond(&self, data: &[u8]) -> Result<(SubFunction, Response), ExceptionCode> {
+        let [hi, lo, field @ ..] = data else {
+            return Err(ExceptionCode::IllegalDataValue);
+        };
+        let sub = SubFunction::from_value(u16::from_be_bytes([*hi, *lo]))
+            .ok_or(ExceptionCode::IllegalFunction)?;
+        let valid = match sub {
+            SubFunction::ReturnQueryData => true,
+            SubFunction::RestartCommunications => field == [0x00, 0x00] || field == [0xFF, 0x00],
+            _ => field == [0x00, 0x00],
+        };
+        if !valid {
+            return Err(ExceptionCode::IllegalDataValue);
+        }
+        let value = match sub {
+            SubFunction::ReturnQueryData | SubFunction::ClearOverrunCounter => None,
+            SubFunction::RestartCommunications | SubFunction::ClearCounters => {
+                self.clear();
+                None
+            }
+            SubFunction::BusMessages => Some(self.get(Counter::BusMessages)),
+            SubFunction::BusCommunicationErrors => Some(self.get(Counter::BusCommunicationErrors)),
+            SubFunction::BusExceptionErrors => Some(self.get(Counter::BusExceptionErrors)),
+            SubFunction::ServerMessages => Some(self.get(Counter::ServerMessages)),
+            SubFunction::ServerNoResponses => Some(self.get(Counter::ServerNoResponses)),
+            SubFunction::DiagnosticRegister
+            | SubFunction::ServerNaks
+            | SubFunction::ServerBusy
+            | SubFunction::BusCharacterOverruns => Some(0),
+        };
+        let response = match value {
+            Some(value) => [[*hi, *lo], value.to_be_bytes()].concat(),
+            None => data.to_vec(),
+        };
+        Ok((sub, Response::Custom(DIAGNOSTICS, Bytes::from(response))))
+    }
+}
+
+/// Send a Diagnostics (FC 08) request and return the data field of the response
+pub async fn query(
+    ctx: &mut Context,
+    sub: SubFunction,
+    data: u16,
+    timeout: Duration,
+) -> tokio_modbus::Result<u16> {
+    let request = [sub.value().to_be_bytes(), data.to_be_bytes()].concat();
+    match frame::call_custom(ctx, DIAGNOSTICS, &request, timeout).await? {
+        Ok(rsp) if rsp.len() == 4 && rsp[..2] == request[..2] => {
+            Ok(Ok(u16::from_be_bytes([rsp[2], rsp[3]])))
+        }
+        Ok(rsp) => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Unexpected response {}", frame::to_hex(&rsp)),
+        )
+        .into()),
+        Err(exception) => Ok(Err(exception)),
+    }
+}
+
+/// Read all counters of the server
+pub async fn read_counters(
+    ctx: &mut Context,
+    timeout: Duration,
+) -> tokio_modbus::Result<Vec<(SubFunction, u16)>> {
+    let mut counters = Vec::new();
+    for sub in SubFunction::COUNTERS {
+        match query(ctx, sub, 0, timeout).await? {
+            Ok(value) => counters.push((sub, value)),
+            Err(exception) => return Ok(Err(exception)),
+        }
+    }
+    Ok(Ok(counters))
+}
+
+/// Human readable representation of a sub-function and the returned data (e.g. `BusMessages: 12`)
+pub fn describe(sub: SubFunction, value: u16) -> String {
+    match sub {
+        SubFunction::ReturnQueryData => format!("{sub:?}: {value:#06X}"),
+        _ if SubFunction::COUNTERS.contains(&sub) => format!("{sub:?}: {value}"),
+        _ => format!("{sub:?}: OK"),
+    }
+}
+
+/// Send a Report Server ID (FC 17) request and return the server id, run indicator and
+/// additional data
+pub async fn report_server_id(
+    ctx: &mut Context,
+    timeout: Duration,
+) -> tokio_modbus::Result<(u8, bool, Vec<u8>)> {
+    match frame::call(ctx, Request::ReportServerId, timeout).await? {
+        Ok(Response::ReportServerId(id, running, data)) => Ok(Ok((id, running, data))),
+        Ok(rsp) => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Unexpected response {rsp:?}"),
+        )
+        .into()),
+        Err(exception) => Ok(Err(exception)),
+    }
+}
+
+/// Report Server ID (FC 17) response. It is encoded by hand as tokio-modbus miscalculates the
+/// length of the native response in the MBAP header.
+pub fn server_id_response(id: u8, running: bool, data: &[u8]) -> Response {
+    let mut pdu = vec![2 + data.len() as u8, id, if running { 0xFF } else { 0x00 }];
+    pdu.extend_from_slice(data);
+    Response::Custom(REPORT_SERVER_ID, Bytes::from(pdu))
+}
+
+/// Human readable representation of a Report Server ID response
+pub fn describe_server_id(id: u8, running: bool, data: &[u8]) -> String {
+    let data = match std::str::from_utf8(data) {
+        Ok(s) if !s.is_empty() => str!(s),
+        _ => frame::to_hex(data),
+    };
+    format!(
+        "ServerId: {:#04X}, Run: {}, Data: {}",
+        id,
+        if running { "ON" } else { "OFF" },
+        data
+    )
+}
+
+/// Server id and all counters of the server in human readable form
+pub async fn report(ctx: &mut Context, timeout: Duration) -> tokio_modbus::Result<Vec<String>> {
+    let (id, running, data) = match report_server_id(ctx, timeout).await? {
+        Ok(server_id) => server_id,
+        Err(exception) => return Ok(Err(exception)),
+    };
+    let counters = match read_counters(ctx, timeout).await? {
+        Ok(counters) => counters,
+        Err(exception) => return Ok(Err(exception)),
+    };
+    Ok(Ok(std::iter::once(describe_server_id(id, running, &data))
+        .chain(
+            counters
+                .into_iter()
+                .map(|(sub, value)| describe(sub, value)),
+        )
+        .collect()))
+}
@@ -0,0 +1,67 @@
+use super::{from_hex, to_hex};
+
+use tokio_modbus::prelude::SlaveId;
+
+/// Largest possible ASCII frame (colon, slave, 253 bytes PDU and checksum as hex, CR LF)
+pub const MAX_ADU_SIZE: usize = 1 + 2 * 255 + 2;
+
+/// Longitudinal redundancy check (two's complement of the byte sum)
+pub fn lrc(data: &[u8]) -> u8 {
+    data.iter()
+        .fold(0u8, |lrc, byte| lrc.wrapping_add(*byte))
+        .wrapping_neg()
+}
+
+/// Take the next complete frame (`:` up to CR LF) from the receive buffer. Bytes in front of the
+/// start character and frames with invalid hex digits or checksum are discarded.
+pub fn decode(buf: &mut Vec<u8>) -> Option<(SlaveId, Vec<u8>)> {
+    loop {
+        match buf.iter().position(|b| *b == b':') {
+            Some(start) => {
+                buf.drain(..start);
+            }
+            None => {
+                buf.clear();
+                return None;
+            }
+        }
+        let end = match buf.windows(2).position(|w| w == b"\r\n") {
+            Some(end) => end,
+            None if buf.len() < MAX_ADU_SIZE => return None,
+            None => {
+                buf.remove(0);
+                continue;
+            }
+        };
+        let frame: Vec<u8> = buf.drain(..end + 2).collect();
+        // A start character within the frame begins a new frame
+        let start = frame[..end].iter().rposition(|b| *b == b':').unwrap_or(0);
+        let bytes = std::str::from_utf8(&frame[start + 1..end])
+            .ok()
+            .and_then(|hex| from_hex(hex).ok());
+        if let Some(bytes) = bytes {
+            if let [slave, pdu @ .., checksum] = bytes.as_slice() {
+                if !pdu.is_empty() && lrc(&bytes[..bytes.len() - 1]) == *checksum {
+                    return Some((*slave, pdu.to_vec()));
+                }
+            }
+        }
+    }
+}
+
+/// Frame a protocol data unit for the given slave
+pub fn encode(slave: SlaveId, pdu: &[u8], corrupt_lrc: bool) -> Vec<u8> {
+    let mut data = Vec::with_capacity(pdu.len() + 2);
+    data.push(slave);
+    data.extend_from_slice(pdu);
+    let mut checksum = lrc(&data);
+    if corrupt_lrc {
+        checksum = !checksum;
+    }
+    data.push(checksum);
+    let mut frame = Vec::with_capacity(2 * data.len() + 3);
+    frame.push(b':');
+    frame.extend_from_slice(to_hex(&data).as_bytes());
+    frame.extend_from_slice(b"\r\n");
+    frame
+}
@@ -0,0 +1,32 @@
+use tokio_modbus::prelude::SlaveId;
+
+/// Largest possible Modbus TCP frame (7 bytes header, 253 bytes PDU)
+pub const MAX_ADU_SIZE: usize = 260;
+
+const PROTOCOL_ID: u16 = 0;
+
+/// Split a complete frame into transaction id, unit id and protocol data unit. Frames with an
+/// unknown protocol id or a length not matching the header are rejected.
+pub fn decode(adu: &[u8]) -> Option<(u16, SlaveId, Vec<u8>)> {
+    if adu.len() < 8 {
+        return None;
+    }
+    let transaction_id = u16::from_be_bytes([adu[0], adu[1]]);
+    let protocol_id = u16::from_be_bytes([adu[2], adu[3]]);
+    let length = u16::from_be_bytes([adu[4], adu[5]]) as usize;
+    if protocol_id != PROTOCOL_ID || length != adu.len() - 6 {
+        return None;
+    }
+    Some((transaction_id, adu[6], adu[7..].to_vec()))
+}
+
+/// Frame a protocol data unit with the Modbus application protocol header
+pub fn encode(transaction_id: u16, unit_id: SlaveId, pdu: &[u8]) -> Vec<u8> {
+    let mut adu = Vec::with_capacity(pdu.len() + 7);
+    adu.extend_from_slice(&transaction_id.to_be_bytes());
+    adu.extend_from_slice(&PROTOCOL_ID.to_be_bytes());
+    adu.extend_from_slice(&(pdu.len() as u16 + 1).to_be_bytes());
+    adu.push(unit_id);
+    adu.extend_from_slice(pdu);
+    adu
+}
@@ -0,0 +1,275 @@
+pub mod ascii;
+pub mod mbap;
+pub mod rtu;
+
+use std::borrow::Cow;
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+use tokio_modbus::bytes::Bytes;
+use tokio_modbus::client::Context;
+use tokio_modbus::prelude::{Client, ExceptionCode, Request, Response, SlaveId};
+use tokio_modbus::{ExceptionResponse, ProtocolError};
+
+/// Framing of the serial line protocols on a byte stream
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// Binary frames delimited by silence and protected by a CRC
+    #[default]
+    Rtu,
+    /// Hex encoded frames delimited by colon and CR LF and protected by a LRC
+    Ascii,
+}
+
+impl Encoding {
+    /// Take the next complete request frame from the receive buffer
+    pub fn decode(&self, buf: &mut Vec<u8>) -> Option<(SlaveId, Vec<u8>)> {
+        match self {
+            Encoding::Rtu => rtu::decode_request(buf),
+            Encoding::Ascii => ascii::decode(buf),
+        }
+    }
+
+    /// Frame a protocol data unit for the given slave
+    pub fn encode(&self, slave: SlaveId, pdu: &[u8], corrupt_checksum: bool) -> Vec<u8> {
+        match self {
+            Encoding::Rtu => rtu::encode(slave, pdu, corrupt_checksum),
+            Encoding::Ascii => ascii::encode(slave, pdu, corrupt_checksum),
+        }
+    }
+
+    pub fn max_adu_size(&self) -> usize {
+        match self {
+            Encoding::Rtu => rtu::MAX_ADU_SIZE,
+            Encoding::Ascii => ascii::MAX_ADU_SIZE,
+        }
+    }
+
+    /// Silence after which an incomplete frame is discarded
+    pub fn frame_timeout(&self) -> Duration {
+        match self {
+            Encoding::Rtu => Duration::from_millis(100),
+            Encoding::Ascii => Duration::from_secs(1),
+        }
+    }
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_coils(buf: &mut Vec<u8>, coils: &[bool]) {
+    let packed = coils.chunks(8).map(|chunk| {
+        chunk
+            .iter()
+            .enumerate()
+            .fold(0u8, |byte, (i, coil)| byte | ((*coil as u8) << i))
+    });
+    buf.push(coils.len().div_ceil(8) as u8);
+    buf.extend(packed);
+}
+
+/// Encode the request as protocol data unit
+pub fn encode_request(request: &Request<'_>) -> Vec<u8> {
+    let mut buf = vec![request.function_code().value()];
+    match request {
+        Request::ReadCoils(addr, cnt)
+        | Request::ReadDiscreteInputs(addr, cnt)
+        | Request::ReadInputRegisters(addr, cnt)
+        | Request::ReadHoldingRegisters(addr, cnt) => {
+            put_u16(&mut buf, *addr);
+            put_u16(&mut buf, *cnt);
+        }
+        Request::WriteSingleCoil(addr, coil) => {
+            put_u16(&mut buf, *addr);
+            put_u16(&mut buf, if *coil { 0xFF00 } else { 0x0000 });
+        }
+        Request::WriteMultipleCoils(addr, coils) => {
+            put_u16(&mut buf, *addr);
+            put_u16(&mut buf, coils.len() as u16);
+            put_coils(&mut buf, coils);
+        }
+        Request::WriteSingleRegister(addr, value) => {
+            put_u16(&mut buf, *addr);
+            put_u16(&mut buf, *value);
+        }
+        Request::WriteMultipleRegisters(addr, words) => {
+            put_u16(&mut buf, *addr);
+            put_u16(&mut buf, words.len() as u16);
+            buf.push((words.len() * 2) as u8);
+            words.iter().for_each(|w| put_u16(&mut buf, *w));
+        }
+        Request::ReportServerId => {}
+        Request::MaskWriteRegister(addr, and, or) => {
+            put_u16(&mut buf, *addr);
+            put_u16(&mut buf, *and);
+            put_u16(&mut buf, *or);
+        }
+        Request::ReadWriteMultipleRegisters(read_addr, cnt, write_addr, words) => {
+            put_u16(&mut buf, *read_addr);
+            put_u16(&mut buf, *cnt);
+            put_u16(&mut buf, *write_addr);
+            put_u16(&mut buf, words.len() as u16);
+            buf.push((words.len() * 2) as u8);
+            words.iter().for_each(|w| put_u16(&mut buf, *w));
+        }
+        Request::ReadDeviceIdentification(read_code, object_id) => {
+            buf.push(0x0E);
+            buf.push(read_code.value());
+            buf.push(*object_id);
+        }
+        Request::Custom(_, data) => buf.extend_from_slice(data),
+    }
+    buf
+}
+
+/// Encode the response (or exception) to a request with the given function code as protocol
+/// data unit
+pub fn encode_response(function: u8, result: &Result<Response, ExceptionCode>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    let response = match result {
+        Ok(response) => response,
+        Err(exception) => {
+            buf.push(function | 0x80);
+            buf.push((*exception).into());
+            return buf;
+        }
+    };
+    buf.push(response.function_code().value());
+    match response {
+        Response::ReadCoils(coils) | Response::ReadDiscreteInputs(coils) => {
+            put_coils(&mut buf, coils)
+        }
+        Response::ReadInputRegisters(words)
+        | Response::ReadHoldingRegisters(words)
+        | Response::ReadWriteMultipleRegisters(words) => {
+            buf.push((words.len() * 2) as u8);
+            words.iter().for_each(|w| put_u16(&mut buf, *w));
+        }
+        Response::WriteSingleCoil(addr, coil) => {
+            put_u16(&mut buf, *addr);
+            put_u16(&mut buf, if *coil { 0xFF00 } else { 0x0000 });
+        }
+        Response::WriteMultipleCoils(addr, cnt) | Response::WriteMultipleRegisters(addr, cnt) => {
+            put_u16(&mut buf, *addr);
+            put_u16(&mut buf, *cnt);
+        }
+        Response::WriteSingleRegister(addr, value) => {
+            put_u16(&mut buf, *addr);
+            put_u16(&mut buf, *value);
+        }
+        Response::MaskWriteRegister(addr, and, or) => {
+            put_u16(&mut buf, *addr);
+            put_u16(&mut buf, *and);
+            put_u16(&mut buf, *or);
+        }
+        Response::ReportServerId(id, running, data) => {
+            buf.push(2 + data.len() as u8);
+            buf.push(*id);
+            buf.push(if *running { 0xFF } else { 0x00 });
+            buf.extend_from_slice(data);
+        }
+        Response::ReadDeviceIdentification(rsp) => {
+            buf.push(0x0E);
+            buf.push(rsp.read_code.value());
+            buf.push(rsp.conformity_level.value());
+            buf.push(if rsp.more_follows { 0xFF } else { 0x00 });
+            buf.push(rsp.next_object_id);
+            buf.push(rsp.device_id_objects.len() as u8);
+            for object in rsp.device_id_objects.iter() {
+                buf.push(object.id);
+                buf.push(object.value.len() as u8);
+                buf.extend_from_slice(&object.value);
+            }
+        }
+        Response::Custom(_, data) => buf.extend_from_slice(data),
+    }
+    buf
+}
+
+/// Decode the response (or exception) to a request with the given function code
+pub fn decode_response(function: u8, pdu: Vec<u8>) -> tokio_modbus::Result<Response> {
+    match pdu.first() {
+        Some(code) if *code == function => Ok(Ok(Response::try_from(Bytes::from(pdu))?)),
+        Some(code) if *code == function | 0x80 => {
+            Ok(Err(ExceptionResponse::try_from(Bytes::from(pdu))?.exception))
+        }
+        _ => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Unexpected response {}", to_hex(&pdu)),
+        )
+        .into()),
+    }
+}
+
+/// Send the request and wait for the response at most for the given time
+pub async fn call(
+    ctx: &mut Context,
+    request: Request<'_>,
+    timeout: Duration,
+) -> tokio_modbus::Result<Response> {
+    match tokio::time::timeout(timeout, ctx.call(request)).await {
+        // tokio-modbus reports exceptions to custom function codes as mismatching function code
+        Ok(Err(tokio_modbus::Error::Protocol(ProtocolError::FunctionCodeMismatch {
+            result: Err(response),
+            ..
+        }))) => Ok(Err(response.exception)),
+        Ok(result) => result,
+        Err(_) => Err(Error::from(ErrorKind::TimedOut).into()),
+    }
+}
+
+/// Send a request with a function code unknown to tokio-modbus and return the data of the
+/// response (without function code)
+pub async fn call_custom(
+    ctx: &mut Context,
+    function: u8,
+    data: &[u8],
+    timeout: Duration,
+) -> tokio_modbus::Result<Bytes> {
+    match call(ctx, Request::Custom(function, Cow::Borrowed(data)), timeout).await? {
+        Ok(Response::Custom(code, data)) if code == function => Ok(Ok(data)),
+        Ok(rsp) => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Unexpected response {rsp:?}"),
+        )
+        .into()),
+        Err(exception) => Ok(Err(exception)),
+    }
+}
+
+/// Value of a register after a Mask Write Register (FC 22) with the given masks
+pub fn mask(value: u16, and_mask: u16, or_mask: u16) -> u16 {
+    (value & and_mask) | (or_mask & !and_mask)
+}
+
+/// Masks of a Mask Write Register (FC 22) changing only the given bit of a register
+pub fn bit_masks(bit: u16, set: bool) -> anyhow::Result<(u16, u16)> {
+    if bit >= 16 {
+        return Err(anyhow::anyhow!(
+            "Bit {bit} exceeds the 16 bits of a register"
+        ));
+    }
+    Ok((!(1 << bit), (set as u16) << bit))
+}
+
+/// Hex representation without separators (e.g. `0304ABCD`)
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+/// Parse a hex representation created by [`to_hex`]
+pub fn from_hex(s: &str) -> anyhow::Result<Vec<u8>> {
+    if !s.is_ascii() {
+        return Err(anyhow::anyhow!("Invalid hex digits"));
+    }
+    if !s.len().is_multiple_of(2) {
+        return Err(anyhow::anyhow!("Odd number of hex digits"));
+    }
+    s.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            // Both bytes are ASCII, the pair is valid UTF-8
+            u8::from_str_radix(std::str::from_utf8(pair)?, 16).map_err(|e| e.into())
+        })
+        .collect()
+}
@@ -0,0 +1,82 @@
+use tokio_modbus::prelude::SlaveId;
+
+/// Largest possible serial frame (slave, 253 bytes PDU, checksum)
+pub const MAX_ADU_SIZE: usize = 256;
+
+enum Length {
+    Incomplete,
+    Known(usize),
+    Unknown,
+}
+
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xFFFF, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u16, |crc, _| {
+            if crc & 0x0001 != 0 {
+                (crc >> 1) ^ 0xA001
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+/// Returns the frame length of a request derived from its function code
+fn request_length(buf: &[u8]) -> Length {
+    let with_count = |idx: usize, fixed: usize| {
+        buf.get(idx)
+            .map(|cnt| Length::Known(fixed + *cnt as usize))
+            .unwrap_or(Length::Incomplete)
+    };
+    match buf.get(1) {
+        None => Length::Incomplete,
+        Some(0x01..=0x06) | Some(0x08) => Length::Known(8),
+        Some(0x07) | Some(0x0B) | Some(0x0C) | Some(0x11) => Length::Known(4),
+        Some(0x0F) | Some(0x10) => with_count(6, 9),
+        Some(0x14) | Some(0x15) => with_count(2, 5),
+        Some(0x16) => Length::Known(10),
+        Some(0x17) => with_count(10, 13),
+        Some(0x18) => Length::Known(6),
+        Some(0x2B) => Length::Known(7),
+        Some(_) => Length::Unknown,
+    }
+}
+
+fn is_valid(frame: &[u8]) -> bool {
+    frame.len() >= 4 && crc16(&frame[..frame.len() - 2]).to_le_bytes() == frame[frame.len() - 2..]
+}
+
+/// Take the next complete request frame from the receive buffer. Leading bytes that do not
+/// start a valid frame are discarded. Unknown function codes are delimited by their checksum.
+pub fn decode_request(buf: &mut Vec<u8>) -> Option<(SlaveId, Vec<u8>)> {
+    loop {
+        let len = match request_length(buf) {
+            Length::Incomplete => return None,
+            Length::Known(len) if len > buf.len() => return None,
+            Length::Known(len) => len,
+            Length::Unknown => match (4..=buf.len()).find(|len| is_valid(&buf[..*len])) {
+                Some(len) => len,
+                None if buf.len() < MAX_ADU_SIZE => return None,
+                None => buf.len(),
+            },
+        };
+        if is_valid(&buf[..len]) {
+            let frame: Vec<u8> = buf.drain(..len).collect();
+            return Some((frame[0], frame[1..len - 2].to_vec()));
+        }
+        buf.remove(0);
+    }
+}
+
+/// Frame a protocol data unit for the given slave
+pub fn encode(slave: SlaveId, pdu: &[u8], corrupt_crc: bool) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(pdu.len() + 3);
+    frame.push(slave);
+    frame.extend_from_slice(pdu);
+    let mut crc = crc16(&frame);
+    if corrupt_crc {
+        crc = !crc;
+    }
+    frame.extend_from_slice(&crc.to_le_bytes());
+    frame
+}
@@ -0,0 +1,123 @@
+use crate::rtu::SerialConfig;
+use crate::util::str;
+
+use clap::{Args, Subcommand};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio_modbus::client::Context;
+use tokio_modbus::prelude::{Client, ExceptionCode, Request, Response, Slave, SlaveContext};
+use tokio_modbus::SlaveId;
+
+#[derive(Clone, Debug, Args)]
+pub struct GatewayConfig {
+    #[command(subcommand)]
+    pub direction: Direction,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum Direction {
+    /// Serve Modbus TCP and forward all requests to a serial RTU device
+    TcpToRtu(GatewayArgs),
+
+    /// Serve Modbus RTU on a serial port and forward all requests to a Modbus TCP server
+    RtuToTcp(GatewayArgs),
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct GatewayArgs {
+    /// The interface to listen on (tcp-to-rtu) or the ip of the Modbus TCP server (rtu-to-tcp).
+    #[arg(short, long, default_value_t = str!("127.0.0.1"))]
+    pub ip: String,
+
+    /// The port to listen on (tcp-to-rtu) or the port of the Modbus TCP server (rtu-to-tcp).
+    #[arg(long, default_value_t = 502)]
+    pub port: u16,
+
+    #[command(flatten)]
+    pub serial: SerialConfig,
+}
+
+/// Device all requests are forwarded to
+pub enum Target {
+    Tcp(SocketAddr),
+    Rtu(SerialConfig),
+}
+
+impl Target {
+    pub fn as_str(&self) -> String {
+        match self {
+            Target::Tcp(addr) => addr.to_string(),
+            Target::Rtu(config) => config.as_str(),
+        }
+    }
+}
+
+/// Client side of the gateway
+///
+/// The connection is established on the first request and re-established after every
+/// transport error or timeout.
+pub struct Upstream {
+    target: Target,
+    timeout: Duration,
+    ctx: Mutex<Option<Context>>,
+}
+
+impl Upstream {
+    pub fn new(target: Target, timeout: Duration) -> Self {
+        Self {
+            target,
+            timeout,
+            ctx: Mutex::new(None),
+        }
+    }
+
+    pub fn target(&self) -> &Target {
+        &self.target
+    }
+
+    async fn connect(&self) -> anyhow::Result<Context> {
+        match self.target {
+            Target::Tcp(addr) => Ok(tokio::time::timeout(
+                self.timeout,
+                tokio_modbus::client::tcp::connect(addr),
+            )
+            .await
+            .map_err(|_| anyhow::anyhow!("Timeout"))??),
+            Target::Rtu(ref config) => {
+                let port = tokio_serial::SerialStream::open(&config.create_serial_builder())?;
+                Ok(config.attach_slave(port, Slave(0)))
+            }
+        }
+    }
+
+    /// Forward the request and return the response of the device. Fails with the exception the
+    /// gateway has to answer with if the device is not reachable.
+    pub async fn forward(
+        &self,
+        slave: SlaveId,
+        request: Request<'static>,
+    ) -> Result<Result<Response, ExceptionCode>, (ExceptionCode, String)> {
+        let mut ctx = self.ctx.lock().await;
+        let mut client = match ctx.take() {
+            Some(client) => client,
+            None => self.connect().await.map_err(|e| {
+                (
+                    ExceptionCode::GatewayPathUnavailable,
+                    format!("Failed to connect to {} ({e})", self.target.as_str()),
+                )
+            })?,
+        };
+        client.set_slave(Slave(slave));
+        let failure = match tokio::time::timeout(self.timeout, client.call(request)).await {
+            Ok(Ok(result)) => {
+                *ctx = Some(client);
+                return Ok(result);
+            }
+            Ok(Err(e)) => format!("Modbus error: {e}"),
+            Err(_) => str!("Timeout"),
+        };
+        let _ = client.disconnect().await;
+        Err((ExceptionCode::GatewayTargetDevice, failure))
+    }
+}
@@ -0,0 +1,94 @@
+use crate::{lua, LogMsg, Status};
+
+use clap::ValueEnum;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum LogFormat {
+    /// One line per message (`<timestamp> - <message>`)
+    Plain,
+    /// One JSON object per line
+    Json,
+}
+
+/// Replacement of the terminal UI for unattended runs (CI, containers, services)
+///
+/// Log messages are written to stdout, errors to stderr. The loop ends on SIGINT or SIGTERM.
+pub struct Headless {
+    format: LogFormat,
+}
+
+impl Headless {
+    pub fn new(format: LogFormat) -> Self {
+        Self { format }
+    }
+
+    fn print(&self, msg: &LogMsg) {
+        self.write(msg, &mut std::io::stdout(), &mut std::io::stderr());
+    }
+
+    /// Write the message as single line to `out`, errors to `err`
+    pub fn write(&self, msg: &LogMsg, out: &mut impl Write, err: &mut impl Write) {
+        let (level, m) = match msg {
+            LogMsg::Ok(m) => ("ok", m),
+            LogMsg::Info(m) => ("info", m),
+            LogMsg::Err(m) => ("err", m),
+        };
+        let line = match self.format {
+            LogFormat::Plain => format!("{}", m),
+            LogFormat::Json => serde_json::json!({
+                "timestamp": m.timestamp,
+                "level": level,
+                "message": m.message,
+            })
+            .to_string(),
+        };
+        if let LogMsg::Err(_) = msg {
+            let _ = writeln!(err, "{}", line);
+        } else {
+            let _ = writeln!(out, "{}", line);
+        }
+    }
+
+    pub fn run(
+        self,
+        mut status_recv: Receiver<Status>,
+        mut log_recv: Receiver<LogMsg>,
+        mut lua_runtime: lua::Runtime,
+    ) -> anyhow::Result<()> {
+        let terminate = Arc::new(AtomicBool::new(false));
+        signal_hook::flag::register(signal_hook::consts::SIGINT, terminate.clone())?;
+        signal_hook::flag::register(signal_hook::consts::SIGTERM, terminate.clone())?;
+
+        let mut last_update = std::time::SystemTime::now();
+
+        while !terminate.load(Ordering::Relaxed) {
+            let now = std::time::SystemTime::now();
+            let diff = now.duration_since(last_update);
+            if diff.is_err() || diff.unwrap().as_secs() >= 1 {
+                lua_runtime.execute();
+                last_update = now;
+            }
+
+            while let Ok(Status::String(status)) = status_recv.try_recv() {
+                self.print(&LogMsg::info(&status));
+            }
+            while let Ok(msg) = log_recv.try_recv() {
+                self.print(&msg);
+            }
+
+            std::thread::sleep(Duration::from_millis(200));
+        }
+
+        while let Ok(msg) = log_recv.try_recv() {
+            self.print(&msg);
+        }
+        self.print(&LogMsg::info("Shutting down."));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,231 @@
+use crate::util::str;
+
+use clap::ValueEnum;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+use tokio_modbus::bytes::Bytes;
+use tokio_modbus::client::Context;
+use tokio_modbus::prelude::{
+    ConformityLevel, DeviceIdObject, ExceptionCode, ObjectId, ReadCode,
+    ReadDeviceIdentificationResponse, Reader, Response,
+};
+
+/// Names of the objects `0x00` to `0x06` defined by the Modbus specification
+const NAMES: [&str; 7] = [
+    "VendorName",
+    "ProductCode",
+    "MajorMinorRevision",
+    "VendorUrl",
+    "ProductName",
+    "ModelName",
+    "UserApplicationName",
+];
+
+/// Maximum size of a response PDU
+const MAX_PDU_SIZE: usize = 253;
+
+/// Size of a response PDU without any object
+const HEADER_SIZE: usize = 7;
+
+/// Category of objects requested by a client
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Category {
+    /// Vendor name, product code and revision (stream access)
+    #[default]
+    Basic,
+    /// Basic and all regular objects (stream access)
+    Regular,
+    /// Basic, regular and all extended objects (stream access)
+    Extended,
+    /// The single object given by the object id
+    Specific,
+}
+
+impl From<Category> for ReadCode {
+    fn from(category: Category) -> Self {
+        match category {
+            Category::Basic => ReadCode::Basic,
+            Category::Regular => ReadCode::Regular,
+            Category::Extended => ReadCode::Extended,
+            Category::Specific => ReadCode::Specific,
+        }
+    }
+}
+
+/// Private object of the extended device identification (`0x80` to `0xFF`)
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ExtendedObject {
+    id: ObjectId,
+    value: String,
+}
+
+/// Objects returned for Read Device Identification (FC 43 / MEI 14) in server mode
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct DeviceIdentification {
+    vendor_name: Option<String>,
+    product_code: Option<String>,
+    major_minor_revision: Option<String>,
+    vendor_url: Option<String>,
+    product_name: Option<String>,
+    model_name: Option<String>,
+    user_application_name: Option<String>,
+    extended: Option<Vec<ExtendedObject>>,
+}
+
+impl DeviceIdentification {
+    /// All configured objects ordered by id. The mandatory basic objects default to the
+    /// application itself.
+    fn objects(&self) -> Vec<(ObjectId, &str)> {
+        let mut objects = vec![
+            (0x00, self.vendor_name.as_deref().unwrap_or("TumbleOwlee")),
+            (
+                0x01,
+                self.product_code
+                    .as_deref()
+                    .unwrap_or(env!("CARGO_PKG_NAME")),
+            ),
+            (
+                0x02,
+                self.major_minor_revision
+                    .as_deref()
+                    .unwrap_or(env!("CARGO_PKG_VERSION")),
+            ),
+        ];
+        let regular = [
+            &self.vendor_url,
+            &self.product_name,
+            &self.model_name,
+            &self.user_application_name,
+        ];
+        for (id, value) in regular.into_iter().enumerate() {
+            if let Some(value) = value {
+                objects.push((id as ObjectId + 0x03, value.as_str()));
+            }
+        }
+        objects.extend(
+            self.extended
+                .iter()
+                .flatten()
+                .filter(|o| o.id >= 0x80)
+                .sorted_by_key(|o| o.id)
+                .map(|o| (o.id, o.value.as_str())),
+        );
+        objects
+    }
+
+    /// Basic objects separated by space (e.g. `ACME PV-100 1.2`)
+    pub fn summary(&self) -> String {
+        self.objects()
+            .into_iter()
+            .take_while(|(id, _)| *id <= 0x02)
+            .map(|(_, value)| value)
+            .join(" ")
+    }
+
+    /// Answer a request with the objects of the category starting at the given object
+    pub fn respond(
+        &self,
+        read_code: ReadCode,
+        object_id: ObjectId,
+    ) -> Result<Response, ExceptionCode> {
+        let objects = self.objects();
+        let conformity_level = match objects.last().map(|(id, _)| *id) {
+            Some(0x80..) => ConformityLevel::ExtendedIdentification,
+            Some(0x03..) => ConformityLevel::RegularIdentification,
+            _ => ConformityLevel::BasicIdentification,
+        };
+        let last = match read_code {
+            ReadCode::Basic => 0x02,
+            ReadCode::Regular => 0x7F,
+            ReadCode::Extended => 0xFF,
+            ReadCode::Specific => object_id,
+        };
+        // Streams pointing to an unknown object restart at the first object
+        let first = match read_code {
+            ReadCode::Specific => object_id,
+            _ if objects.iter().any(|(id, _)| *id == object_id) && object_id <= last => object_id,
+            _ => 0x00,
+        };
+        let selected: Vec<_> = objects
+            .into_iter()
+            .filter(|(id, _)| first <= *id && *id <= last)
+            .collect();
+        if selected.is_empty() {
+            return Err(ExceptionCode::IllegalDataAddress);
+        }
+
+        let mut size = HEADER_SIZE;
+        let mut next_object_id = None;
+        let mut device_id_objects = Vec::new();
+        for (id, value) in selected {
+            let value = &value.as_bytes()[..value.len().min(MAX_PDU_SIZE - HEADER_SIZE - 2)];
+            if size + 2 + value.len() > MAX_PDU_SIZE {
+                next_object_id = Some(id);
+                break;
+            }
+            size += 2 + value.len();
+            device_id_objects.push(DeviceIdObject {
+                id,
+                value: Bytes::copy_from_slice(value),
+            });
+        }
+        Ok(Response::ReadDeviceIdentification(
+            ReadDeviceIdentificationResponse {
+                read_code,
+                conformity_level,
+                more_follows: next_object_id.is_some(),
+                next_object_id: next_object_id.unwrap_or_default(),
+                device_id_objects,
+            },
+        ))
+    }
+}
+
+/// Name of the object as defined by the specification or its id
+pub fn object_name(id: ObjectId) -> String {
+    NAMES
+        .get(id as usize)
+        .map(|name| str!(*name))
+        .unwrap_or(format!("{id:#04X}"))
+}
+
+/// Human readable representation of the object (e.g. `VendorName: ACME`)
+pub fn describe(object: &DeviceIdObject) -> String {
+    let value = object
+        .value_as_str()
+        .map(|v| str!(v))
+        .unwrap_or_else(|| crate::frame::to_hex(&object.value));
+    format!("{}: {}", object_name(object.id), value)
+}
+
+/// Read all objects of the category, following up as long as the device reports more objects
+pub async fn read(
+    ctx: &mut Context,
+    read_code: ReadCode,
+    object_id: ObjectId,
+    timeout: Duration,
+) -> tokio_modbus::Result<Vec<DeviceIdObject>> {
+    let mut objects = Vec::new();
+    let mut next = object_id;
+    loop {
+        let response =
+            match tokio::time::timeout(timeout, ctx.read_device_identification(read_code, next))
+                .await
+            {
+                Ok(Ok(Ok(response))) => response,
+                Ok(Ok(Err(exception))) => return Ok(Err(exception)),
+                Ok(Err(e)) => return Err(e),
+                Err(_) => return Err(Error::from(ErrorKind::TimedOut).into()),
+            };
+        let done = read_code == ReadCode::Specific
+            || !response.more_follows
+            || response.next_object_id <= next;
+        next = response.next_object_id;
+        objects.extend(response.device_id_objects);
+        if done {
+            return Ok(Ok(objects));
+        }
+    }
+}
@@ -88,6 +88,15 @@ impl Context {
         res
     }
 
+    /// Compile the code into a function that is called on demand
+    pub fn compile(&self, code: &str) -> Result<LuaFunction, mlua::Error> {
+        self.lua.load(code).into_function()
+    }
+
+    pub fn create_string(&self, bytes: &[u8]) -> Result<mlua::String, mlua::Error> {
+        self.lua.create_string(bytes)
+    }
+
     pub fn load(&mut self, id: &str, code: &str) -> Result<(), mlua::Error> {
         let func = self.lua.load(code).into_function()?;
         let _ = self.funcs.insert(id.to_string(), Function::init(func));
@@ -0,0 +1,158 @@
+use crate::lua::context::Context;
+use crate::lua::module;
+use crate::{mem::memory::Memory, msg::LogMsg, AppConfig};
+
+use mlua::{Function as LuaFunction, Value as LuaValue};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
+use tokio_modbus::prelude::{ExceptionCode, SlaveId};
+
+/// Lua handler answering a user-defined function code in server mode
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CustomFunction {
+    function_code: u8,
+    /// Lua chunk called with the request PDU and the slave id
+    handler: String,
+}
+
+/// Returns `true` for the function codes reserved for user-defined functions (65 to 72 and
+/// 100 to 110)
+pub fn is_user_defined(function: u8) -> bool {
+    matches!(function, 65..=72 | 100..=110)
+}
+
+/// Answer of a handler: the response PDU, an exception or `None` to leave the request unanswered
+pub type Answer = Option<Result<Vec<u8>, ExceptionCode>>;
+
+struct Call {
+    function: u8,
+    slave: SlaveId,
+    pdu: Vec<u8>,
+    answer: oneshot::Sender<anyhow::Result<Answer>>,
+}
+
+/// Handlers of custom function codes. The Lua state is not thread safe, therefore all handlers
+/// run on a thread of their own and the service waits for their answer.
+pub struct Functions {
+    codes: Vec<u8>,
+    sender: mpsc::Sender<Call>,
+}
+
+impl Functions {
+    /// Compile all handlers and start the thread executing them
+    pub fn spawn(
+        functions: Vec<CustomFunction>,
+        memory: Arc<Mutex<Memory>>,
+        app_config: Arc<Mutex<AppConfig>>,
+        log_sender: Sender<LogMsg>,
+    ) -> anyhow::Result<Self> {
+        if let Some(function) = functions.iter().find(|f| !is_user_defined(f.function_code)) {
+            return Err(anyhow::anyhow!(
+                "Function code {} is not a user-defined function code",
+                function.function_code
+            ));
+        }
+        let codes = functions.iter().map(|f| f.function_code).collect();
+        let (sender, receiver) = mpsc::channel::<Call>();
+        let (ready_sender, ready_receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (context, handlers) = match Self::init(&functions, memory, app_config, log_sender) {
+                Ok(init) => {
+                    let _ = ready_sender.send(Ok(()));
+                    init
+                }
+                Err(e) => {
+                    let _ = ready_sender.send(Err(e));
+                    return;
+                }
+            };
+            // Runs until the service and all its clones are dropped
+            while let Ok(call) = receiver.recv() {
+                let answer = match handlers.get(&call.function) {
+                    Some(handler) => Self::execute(&context, handler, &call),
+                    None => Ok(Some(Err(ExceptionCode::IllegalFunction))),
+                };
+                let _ = call.answer.send(answer);
+            }
+        });
+        ready_receiver.recv()??;
+        Ok(Self { codes, sender })
+    }
+
+    fn init(
+        functions: &[CustomFunction],
+        memory: Arc<Mutex<Memory>>,
+        app_config: Arc<Mutex<AppConfig>>,
+        log_sender: Sender<LogMsg>,
+    ) -> anyhow::Result<(Context, HashMap<u8, LuaFunction>)> {
+        let mut lua = Context::default();
+        lua.enable_stdlib()?;
+        lua.add_module(module::Time::default())?;
+        lua.add_module(module::Register::init(memory, app_config, log_sender))?;
+        let mut handlers = HashMap::new();
+        for function in functions.iter() {
+            let handler = lua.compile(&function.handler).map_err(|e| {
+                anyhow::anyhow!(
+                    "Invalid handler of function code {} ({e})",
+                    function.function_code
+                )
+            })?;
+            handlers.insert(function.function_code, handler);
+        }
+        Ok((lua, handlers))
+    }
+
+    /// Call the handler and convert its return value. Strings and tables of bytes are returned as
+    /// response PDU, integers as exception code and `nil` leaves the request unanswered.
+    fn execute(context: &Context, handler: &LuaFunction, call: &Call) -> anyhow::Result<Answer> {
+        let pdu = context.create_string(&call.pdu)?;
+        let pdu = match handler.call::<LuaValue>((pdu, call.slave))? {
+            LuaValue::Nil => return Ok(None),
+            LuaValue::Integer(code) => {
+                let code = u8::try_from(code)
+                    .map_err(|_| anyhow::anyhow!("Invalid exception code {code}"))?;
+                return Ok(Some(Err(ExceptionCode::new(code))));
+            }
+            LuaValue::String(s) => s.as_bytes().to_vec(),
+            LuaValue::Table(t) => t.sequence_values::<u8>().collect::<Result<Vec<_>, _>>()?,
+            value => {
+                return Err(anyhow::anyhow!(
+                    "Invalid return value of type {}",
+                    value.type_name()
+                ))
+            }
+        };
+        match pdu.as_slice() {
+            [code, ..] if *code == call.function => Ok(Some(Ok(pdu))),
+            [code, exception] if *code == call.function | 0x80 => {
+                Ok(Some(Err(ExceptionCode::new(*exception))))
+            }
+            _ => Err(anyhow::anyhow!(
+                "Response {} does not match the function code",
+                crate::frame::to_hex(&pdu)
+            )),
+        }
+    }
+
+    /// Returns `true` if a handler is bound to the function code
+    pub fn handles(&self, function: u8) -> bool {
+        self.codes.contains(&function)
+    }
+
+    /// Pass the request PDU (including function code) to the handler of its function code
+    pub async fn call(&self, slave: SlaveId, pdu: Vec<u8>) -> anyhow::Result<Answer> {
+        let (answer, receiver) = oneshot::channel();
+        let function = *pdu.first().ok_or(anyhow::anyhow!("Empty request"))?;
+        self.sender.send(Call {
+            function,
+            slave,
+            pdu,
+            answer,
+        })?;
+        receiver.await?
+    }
+}
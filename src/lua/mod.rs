@@ -1,6 +1,9 @@
 mod context;
+mod function;
 mod module;
 
+pub use function::{CustomFunction, Functions};
+
 use crate::lua::context::Context;
 use crate::{mem::memory::Memory, msg::LogMsg, AppConfig};
 use std::sync::{Arc, Mutex};
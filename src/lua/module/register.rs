@@ -1,5 +1,5 @@
 use crate::lua::module::Module;
-use crate::mem::memory::Range;
+use crate::mem::bitfield;
 use crate::{mem::memory::Memory, msg::LogMsg, AppConfig};
 use mlua::{Result as LuaResult, UserData};
 use std::sync::{Arc, Mutex};
@@ -37,7 +37,9 @@ impl UserData for Register {
         methods.add_method("GetFloat", Self::get_float);
         methods.add_method("GetString", Self::get_string);
         methods.add_method("GetBool", Self::get_bool);
+        methods.add_method("GetField", Self::get_field);
         methods.add_method("Set", Self::set);
+        methods.add_method("Push", Self::push);
     }
 }
 
@@ -68,7 +70,8 @@ impl Register {
                     .lock()
                     .expect("Unable to lock memory")
                     .read(
-                        def_by_name[0].1.get_slave_id().unwrap_or(0),
+                        def_by_name[0].1.unit(),
+                        def_by_name[0].1.table(),
                         &def_by_name[0].1.get_range(),
                     )
                     .unwrap_or(vec![&0, &0, &0, &0, &0, &0, &0, &0])
@@ -78,7 +81,7 @@ impl Register {
                 let value = def_by_name[0]
                     .1
                     .get_type()
-                    .as_plain_str(&bytes)
+                    .as_plain_str(&def_by_name[0].1.scope(&bytes))
                     .map_err(|_| mlua::Error::UserDataTypeMismatch)?;
 
                 value
@@ -91,7 +94,8 @@ impl Register {
                     .lock()
                     .expect("Unable to lock memory")
                     .read(
-                        def_by_id[0].1.get_slave_id().unwrap_or(0),
+                        def_by_id[0].1.unit(),
+                        def_by_id[0].1.table(),
                         &def_by_id[0].1.get_range(),
                     )
                     .unwrap_or(vec![&0, &0, &0, &0, &0, &0, &0, &0])
@@ -101,7 +105,7 @@ impl Register {
                 let value = def_by_id[0]
                     .1
                     .get_type()
-                    .as_plain_str(&bytes)
+                    .as_plain_str(&def_by_id[0].1.scope(&bytes))
                     .map_err(|_| mlua::Error::UserDataTypeMismatch)?;
 
                 value
@@ -123,10 +127,7 @@ impl Register {
                 .memory
                 .lock()
                 .expect("Unable to lock memory")
-                .read(
-                    regs[0].1.get_slave_id().unwrap_or(0),
-                    &regs[0].1.get_range(),
-                )
+                .read(regs[0].1.unit(), regs[0].1.table(), &regs[0].1.get_range())
                 .unwrap_or(vec![&0, &0, &0, &0, &0, &0, &0, &0])
                 .into_iter()
                 .copied()
@@ -134,7 +135,7 @@ impl Register {
             let value = regs[0]
                 .1
                 .get_type()
-                .as_plain_str(&bytes)
+                .as_plain_str(&regs[0].1.scope(&bytes))
                 .map_err(|_| mlua::Error::UserDataTypeMismatch)?;
 
             value
@@ -156,10 +157,7 @@ impl Register {
                 .memory
                 .lock()
                 .expect("Unable to lock memory")
-                .read(
-                    regs[0].1.get_slave_id().unwrap_or(0),
-                    &regs[0].1.get_range(),
-                )
+                .read(regs[0].1.unit(), regs[0].1.table(), &regs[0].1.get_range())
                 .unwrap_or(vec![&0, &0, &0, &0, &0, &0, &0, &0])
                 .into_iter()
                 .copied()
@@ -167,7 +165,7 @@ impl Register {
             regs[0]
                 .1
                 .get_type()
-                .as_plain_str(&bytes)
+                .as_plain_str(&regs[0].1.scope(&bytes))
                 .map_err(|_| mlua::Error::UserDataTypeMismatch)
         } else {
             Err(mlua::Error::RuntimeError(String::new()))
@@ -185,14 +183,14 @@ impl Register {
                 .memory
                 .lock()
                 .expect("Unable to lock memory")
-                .read(
-                    regs[0].1.get_slave_id().unwrap_or(0),
-                    &regs[0].1.get_range(),
-                )
+                .read(regs[0].1.unit(), regs[0].1.table(), &regs[0].1.get_range())
                 .unwrap_or(vec![&0, &0, &0, &0, &0, &0, &0, &0])
                 .into_iter()
                 .copied()
                 .collect();
+            if let Some(bit) = regs[0].1.bit() {
+                return Ok(bitfield::bit(&bytes, bit).unwrap_or_default());
+            }
             let value = regs[0]
                 .1
                 .get_type()
@@ -207,6 +205,37 @@ impl Register {
         }
     }
 
+    fn get_field(
+        _: &mlua::Lua,
+        this: &Register,
+        (name, field): (String, String),
+    ) -> LuaResult<u64> {
+        let config = this
+            .config
+            .lock()
+            .map_err(|_| mlua::Error::UserDataBorrowError)?;
+        let regs: Vec<_> = config.definitions.iter().filter(|r| *r.0 == name).collect();
+        if regs.len() == 1 {
+            let bytes: Vec<u16> = this
+                .memory
+                .lock()
+                .expect("Unable to lock memory")
+                .read(regs[0].1.unit(), regs[0].1.table(), &regs[0].1.get_range())
+                .unwrap_or(vec![&0, &0, &0, &0, &0, &0, &0, &0])
+                .into_iter()
+                .copied()
+                .collect();
+            regs[0]
+                .1
+                .get_type()
+                .field(&bytes, &field)
+                .and_then(|value| u64::try_from(value).ok())
+                .ok_or(mlua::Error::UserDataTypeMismatch)
+        } else {
+            Err(mlua::Error::RuntimeError(String::new()))
+        }
+    }
+
     fn set(_: &mlua::Lua, this: &Register, (name, value): (String, String)) -> LuaResult<()> {
         if let Some(register) = this
             .config
@@ -223,13 +252,7 @@ impl Register {
                         ));
                     } else {
                         let mut memory = this.memory.lock().expect("Unable to lock memory");
-                        let addr = register.get_address();
-                        let slave = register.get_slave_id().unwrap_or(0);
-
-                        if let Err(e) = memory
-                            .write(slave, Range::new(addr, addr + values.len() as u16), &values)
-                            .map(|_| ())
-                        {
+                        if let Err(e) = register.write(&mut memory, &values) {
                             let _ = this
                                 .logger
                                 .try_send(LogMsg::err(&format!("{} = {}", value, e)));
@@ -245,4 +268,45 @@ impl Register {
         }
         Ok(())
     }
+
+    fn push(_: &mlua::Lua, this: &Register, (name, value): (String, String)) -> LuaResult<()> {
+        if let Some(register) = this
+            .config
+            .lock()
+            .map_err(|_| mlua::Error::UserDataBorrowError)?
+            .definitions
+            .get(&name)
+        {
+            if !register.is_fifo() {
+                let _ = this
+                    .logger
+                    .try_send(LogMsg::err(&format!("Register {} is no FIFO queue.", name)));
+                return Ok(());
+            }
+            match register.get_type().encode(&value) {
+                Ok(mut values) => {
+                    if values.len() > register.length() as usize {
+                        let _ = this.logger.try_send(LogMsg::err(
+                            "Provided input requires a longer register as available.",
+                        ));
+                    } else {
+                        values.resize(register.length() as usize, 0);
+                        let mut memory = this.memory.lock().expect("Unable to lock memory");
+                        if !memory.push_fifo(register.unit(), register.get_address(), &values) {
+                            let _ = this.logger.try_send(LogMsg::err(&format!(
+                                "{} = {} (FIFO queue is full)",
+                                name, value
+                            )));
+                        }
+                    }
+                }
+                Err(e) => {
+                    let _ = this
+                        .logger
+                        .try_send(LogMsg::err(&format!("{} = {}", value, e)));
+                }
+            }
+        }
+        Ok(())
+    }
 }
@@ -1,24 +1,41 @@
 #![feature(f128)]
 
+mod capture;
+mod connection;
+mod diag;
+mod frame;
+mod gateway;
+mod headless;
+mod ident;
 mod lua;
 mod mem;
 mod msg;
+mod oneshot;
+mod record;
 mod rtu;
+mod service;
 mod tcp;
 mod test;
+mod udp;
 mod ui;
 mod util;
 mod widgets;
 
-use crate::mem::memory::{Memory, Range};
+use crate::capture::Capture;
+use crate::connection::{Client, Connection, Server, Timing};
+use crate::gateway::{Direction, GatewayConfig, Target, Upstream};
+use crate::headless::{Headless, LogFormat};
+use crate::mem::fifo;
+use crate::mem::memory::{Memory, Range, Table, Unit};
 use crate::mem::register::{Address, Definition, Handler, Value};
 use crate::msg::{Command, LogMsg, Status};
-use crate::rtu::client::Client as RtuClient;
+use crate::record::{RecordFormat, Recorder};
 use crate::rtu::server::Server as RtuServer;
 use crate::rtu::RtuConfig;
-use crate::tcp::client::Client as TcpClient;
+use crate::service::{ServerConfig, Service};
 use crate::tcp::server::Server as TcpServer;
-use crate::tcp::TcpConfig;
+use crate::tcp::{TcpConfig, Transport};
+use crate::udp::UdpConfig;
 use crate::ui::{App, AppAction, Mode};
 use crate::util::tokio::spawn_detach;
 use crate::util::{async_cloned, str, Expect};
@@ -50,13 +67,22 @@ pub struct Format {
 }
 
 #[derive(Subcommand, Clone)]
-enum Commands {
+pub enum Commands {
     /// Use TCP connection
     Tcp(TcpConfig),
 
     /// Use RTU connection
     Rtu(RtuConfig),
 
+    /// Use RTU framing over a TCP connection (e.g. serial to ethernet converters)
+    RtuOverTcp(TcpConfig),
+
+    /// Use Modbus TCP framing over UDP
+    Udp(UdpConfig),
+
+    /// Forward requests between Modbus TCP and RTU (only in server mode)
+    Gateway(GatewayConfig),
+
     /// Convert configuration file to other type
     Convert(Format),
 }
@@ -76,6 +102,26 @@ struct Args {
     #[arg(long, default_value_t = false)]
     client: bool,
 
+    /// Run without the terminal UI and print the log to stdout/stderr.
+    #[arg(long, default_value_t = false)]
+    headless: bool,
+
+    /// Output format of the log in headless mode.
+    #[arg(long, value_enum, default_value_t = LogFormat::Plain)]
+    log_format: LogFormat,
+
+    /// Append the values of all registers to the file after each poll cycle (only in client mode).
+    #[arg(long)]
+    record: Option<String>,
+
+    /// Format of the record file. Derived from the file extension if omitted.
+    #[arg(long, value_enum)]
+    record_format: Option<RecordFormat>,
+
+    /// Write every request and response handled by the server to the file (only in server mode).
+    #[arg(long)]
+    capture: Option<String>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -95,6 +141,8 @@ pub struct AppConfig {
     timeout_ms: Option<u64>,
     contiguous_memory: Vec<ContiguousMemory>,
     definitions: HashMap<String, Definition>,
+    server: Option<ServerConfig>,
+    connections: Option<HashMap<String, Connection>>,
 }
 
 impl Default for AppConfig {
@@ -106,13 +154,45 @@ impl Default for AppConfig {
             delay_after_connect_ms: None,
             contiguous_memory: Vec::new(),
             definitions: HashMap::new(),
+            server: None,
+            connections: None,
         }
     }
 }
 
 impl AppConfig {
+    /// Options of the server mode, the defaults if the configuration has no server section
+    pub fn server(&self) -> &ServerConfig {
+        self.server.as_ref().unwrap_or(ServerConfig::defaults())
+    }
+
     /// Read register configuration from file
     pub fn read(path: &str) -> anyhow::Result<Self> {
+        let mut config = Self::parse(path)?;
+        if let Some((name, def)) = config.definitions.iter().find(|(_, d)| !d.is_readable()) {
+            return Err(anyhow::anyhow!(
+                "Invalid read code {} of definition {name}",
+                def.read_code()
+            ));
+        }
+        if let Some((name, def)) = config.definitions.iter().find(|(_, d)| !d.is_writable()) {
+            return Err(anyhow::anyhow!(
+                "Invalid write code {} of definition {name} with read code {}",
+                def.write_code(),
+                def.read_code()
+            ));
+        }
+        if let Some((name, def)) = config.definitions.iter().find(|(_, d)| !d.is_valid_bit()) {
+            return Err(anyhow::anyhow!(
+                "Invalid bit {} of definition {name}, bits are limited to a single holding or input register",
+                def.bit().unwrap_or_default()
+            ));
+        }
+        config.resolve_connections()?;
+        Ok(config)
+    }
+
+    fn parse(path: &str) -> anyhow::Result<Self> {
         let file = File::open(path)?;
         let reader = BufReader::new(file);
         let result = serde_json::from_reader(reader);
@@ -132,10 +212,53 @@ impl AppConfig {
             }
         }
     }
+
+    /// Named connections ordered by name. The index of a connection is its position plus one,
+    /// index `0` belongs to the connection given on the command line.
+    pub fn connections(&self) -> Vec<(&String, &Connection)> {
+        self.connections
+            .iter()
+            .flatten()
+            .sorted_by(|a, b| a.0.cmp(b.0))
+            .collect()
+    }
+
+    /// Assign the index of the referenced connection to every definition
+    fn resolve_connections(&mut self) -> anyhow::Result<()> {
+        let names: Vec<String> = self
+            .connections()
+            .into_iter()
+            .map(|(name, _)| name.clone())
+            .collect();
+        for (name, def) in self.definitions.iter_mut() {
+            if let Some(connection) = def.get_connection() {
+                let index = names.iter().position(|n| n == connection).ok_or_else(|| {
+                    anyhow::anyhow!("Unknown connection {connection} of definition {name}")
+                })?;
+                def.set_connection_index(index + 1);
+            }
+        }
+        Ok(())
+    }
+
+    /// Contiguous memory sections of the connection with the given index
+    pub fn contiguous_memory(&self, connection: usize) -> &Vec<ContiguousMemory> {
+        match connection {
+            0 => &self.contiguous_memory,
+            i => self
+                .connections()
+                .get(i - 1)
+                .and_then(|(_, c)| c.contiguous_memory())
+                .unwrap_or(&self.contiguous_memory),
+        }
+    }
 }
 
 fn main() {
-    let args = Args::parse();
+    let mut args = Args::parse();
+    if let Commands::RtuOverTcp(ref mut config) = args.command {
+        config.transport = Transport::RtuOverTcp;
+    }
     let mut terminate = false;
 
     loop {
@@ -148,6 +271,31 @@ fn main() {
                 AppConfig::read(&p).panic(|e| format!("Failed to read configuration file. [{}]", e))
             })
             .unwrap_or(AppConfig::default());
+        // Execute one-shot operations without starting the application
+        let oneshot = match args.command {
+            Commands::Tcp(ref config) | Commands::RtuOverTcp(ref config) => {
+                config.oneshot.as_ref().map(|oneshot| {
+                    Runtime::new()
+                        .panic(|e| format!("Failed to create runtime. [{}]", e))
+                        .block_on(oneshot::run_tcp(config, &app_config, oneshot))
+                })
+            }
+            Commands::Udp(ref config) => config.oneshot.as_ref().map(|oneshot| {
+                Runtime::new()
+                    .panic(|e| format!("Failed to create runtime. [{}]", e))
+                    .block_on(oneshot::run_tcp(&config.connection(), &app_config, oneshot))
+            }),
+            Commands::Rtu(ref config) => config.oneshot.as_ref().map(|oneshot| {
+                Runtime::new()
+                    .panic(|e| format!("Failed to create runtime. [{}]", e))
+                    .block_on(oneshot::run_rtu(config, &app_config, oneshot))
+            }),
+            Commands::Gateway(_) | Commands::Convert(_) => None,
+        };
+        if let Some(status) = oneshot {
+            std::process::exit(status as i32);
+        }
+
         let interval_ms = app_config.interval_ms;
         let delay_after_connect_ms = app_config.delay_after_connect_ms.unwrap_or(500);
         let timeout_ms = app_config.timeout_ms.unwrap_or(3000);
@@ -156,19 +304,48 @@ fn main() {
         let mut memory = Memory::new();
         let map = app_config.definitions.values().fold(
             HashMap::new(),
-            |mut f: HashMap<SlaveId, Vec<Range<_>>>, d| {
-                f.entry(d.get_slave_id().unwrap_or(0))
+            |mut f: HashMap<(Unit, Table), Vec<Range<_>>>, d| {
+                f.entry((d.unit(), d.table()))
                     .or_default()
                     .push(d.get_range());
                 f
             },
         );
-        for (slave, ranges) in map.into_iter() {
-            memory.init(slave, &ranges);
+        for ((slave, table), ranges) in map.into_iter() {
+            memory.init(slave, table, &ranges);
+        }
+        // Preload the files of the file record access (FC 20/21) served in server mode
+        if !args.client {
+            for file in app_config.server().files() {
+                let records = file
+                    .load()
+                    .panic(|e| format!("Failed to load file {} [{e}]", file.path()));
+                memory.load_file(file.slave_id(), file.file(), records);
+            }
+            // Fill the FIFO queues (FC 24) with their initial entries
+            for (name, def) in app_config.definitions.iter().filter(|(_, d)| d.is_fifo()) {
+                let queue = def
+                    .queue()
+                    .panic(|e| format!("Invalid queue of register {name} [{e}]"));
+                if queue.len() > fifo::MAX_COUNT {
+                    panic!(
+                        "Queue of register {name} exceeds {} entries",
+                        fifo::MAX_COUNT
+                    );
+                }
+                memory.init_fifo(def.unit(), def.get_address(), &queue);
+            }
         }
         let memory = Arc::new(Mutex::new(memory));
         let app_config = Arc::new(Mutex::new(app_config));
 
+        // Open the capture once to keep the traffic of all restarts in the same file
+        let capture = args.capture.as_ref().map(|path| {
+            Arc::new(Mutex::new(
+                Capture::new(path).panic(|e| format!("Failed to open capture file {path} [{e}]")),
+            ))
+        });
+
         loop {
             let (status_sender, status_receiver) = channel::<Status>(10);
             let (log_sender, log_receiver) = channel::<LogMsg>(10);
@@ -183,23 +360,51 @@ fn main() {
 
             if args.client {
                 match args.command.clone() {
-                    Commands::Tcp(config) => {
-                        runtime.block_on(async_cloned!(interval_ms, app_config, memory; {
-                        spawn_detach(async move {
-                            let mut client = TcpClient::new(app_config, config.clone(), memory, status_sender, cmd_receiver, log_sender);
-                            client.run(delay_after_connect_ms, interval_ms, timeout_ms).await
-                        })
-                        .await
-                    }));
+                    Commands::Tcp(_)
+                    | Commands::RtuOverTcp(_)
+                    | Commands::Udp(_)
+                    | Commands::Rtu(_) => {
+                        let timing = Timing {
+                            delay_after_connect_ms,
+                            interval_ms,
+                            timeout_ms,
+                        };
+                        let connections = connection::plan(
+                            &app_config.lock().expect("Unable to lock configuration"),
+                            args.command.clone(),
+                            timing,
+                        );
+                        // All connections share the recorder, a row is appended once each of
+                        // them completed a poll cycle
+                        let recorder = args.record.as_ref().map(|path| {
+                            let format = args
+                                .record_format
+                                .unwrap_or_else(|| RecordFormat::from_path(path));
+                            Arc::new(Mutex::new(
+                                Recorder::new(path, format, app_config.clone(), memory.clone())
+                                    .panic(|e| format!("Failed to open record file {path} [{e}]"))
+                                    .with_connections(connections.len()),
+                            ))
+                        });
+                        // Route the commands of the UI to the client of the register
+                        let mut senders = HashMap::new();
+                        for (index, command, timing) in connections {
+                            let (sender, receiver) = channel::<Command>(10);
+                            senders.insert(index, sender);
+                            runtime.block_on(async_cloned!(app_config, memory, status_sender, log_sender, recorder; {
+                                spawn_detach(async move {
+                                    let mut client = Client::new(command, app_config, memory, status_sender, receiver, log_sender, recorder)
+                                        .with_connection(index);
+                                    client.run(timing).await
+                                })
+                                .await
+                            }));
+                        }
+                        runtime.block_on(spawn_detach(connection::dispatch(cmd_receiver, senders)));
                     }
-                    Commands::Rtu(config) => {
-                        runtime.block_on(async_cloned!(interval_ms, app_config, memory; {
-                        spawn_detach(async move {
-                            let mut client = RtuClient::new(app_config, config.clone(), memory, status_sender, cmd_receiver, log_sender);
-                            client.run(delay_after_connect_ms, interval_ms, timeout_ms).await
-                        })
-                        .await
-                    }));
+                    Commands::Gateway(_) => {
+                        let _ = log_sender
+                            .try_send(LogMsg::err("The gateway is only available in server mode."));
                     }
                     Commands::Convert(format) => {
                         if let Some(ref path) = cfg_path {
@@ -237,24 +442,111 @@ fn main() {
                     }
                 }
             } else {
+                let service = Service::new(
+                    app_config.clone(),
+                    memory.clone(),
+                    log_sender.clone(),
+                    capture.clone(),
+                );
+                let functions = app_config
+                    .lock()
+                    .expect("Unable to lock configuration")
+                    .server()
+                    .custom_functions()
+                    .to_vec();
+                let service = if functions.is_empty() {
+                    service
+                } else {
+                    service.with_functions(
+                        lua::Functions::spawn(
+                            functions,
+                            memory.clone(),
+                            app_config.clone(),
+                            log_sender.clone(),
+                        )
+                        .panic(|e| format!("Failed to load custom functions [{e}]")),
+                    )
+                };
+                if app_config
+                    .lock()
+                    .expect("Unable to lock configuration")
+                    .connections
+                    .is_some()
+                {
+                    let _ = log_sender.try_send(LogMsg::info(
+                        "Named connections are only used in client mode, their definitions are not served.",
+                    ));
+                }
                 match args.command.clone() {
-                    Commands::Tcp(config) => {
-                        runtime.block_on(async_cloned!(memory; {
-                        spawn_detach(async move {
-                            let server = TcpServer::new(config, memory, status_sender, log_sender);
-                            server.run().await
-                        })
-                        .await
-                    }));
+                    command @ (Commands::Tcp(_)
+                    | Commands::RtuOverTcp(_)
+                    | Commands::Udp(_)
+                    | Commands::Rtu(_)) => {
+                        let endpoints = app_config
+                            .lock()
+                            .expect("Unable to lock configuration")
+                            .server
+                            .clone()
+                            .unwrap_or_default()
+                            .endpoints()
+                            .to_vec();
+                        runtime.block_on(async {
+                            for endpoint in endpoints {
+                                let service = match endpoint.slave_ids {
+                                    Some(slaves) => service.clone().with_slaves(slaves),
+                                    None => service.clone(),
+                                };
+                                let server = Server::new(
+                                    endpoint.endpoint.command(),
+                                    service,
+                                    status_sender.clone(),
+                                    log_sender.clone(),
+                                );
+                                spawn_detach(async move { server.run().await }).await;
+                            }
+                            let server = Server::new(command, service, status_sender, log_sender);
+                            spawn_detach(async move { server.run().await }).await
+                        });
                     }
-                    Commands::Rtu(config) => {
-                        runtime.block_on(async_cloned!(memory; {
-                        spawn_detach(async move {
-                            let server = RtuServer::new(config, memory, status_sender, log_sender);
-                            server.run().await
-                        })
-                        .await
-                    }));
+                    Commands::Gateway(gateway) => {
+                        let timeout = std::time::Duration::from_millis(timeout_ms);
+                        runtime.block_on(async {
+                            spawn_detach(async move {
+                                match gateway.direction {
+                                    Direction::TcpToRtu(args) => {
+                                        let upstream =
+                                            Upstream::new(Target::Rtu(args.serial), timeout);
+                                        let config = TcpConfig {
+                                            ip: args.ip,
+                                            port: args.port,
+                                            ..Default::default()
+                                        };
+                                        let server = TcpServer::new(
+                                            config,
+                                            service.with_upstream(upstream),
+                                            status_sender,
+                                            log_sender,
+                                        );
+                                        server.run().await
+                                    }
+                                    Direction::RtuToTcp(args) => {
+                                        let addr =
+                                            format!("{}:{}", args.ip, args.port).parse().panic(
+                                                |e| format!("Failed to create SocketAddr ({e})"),
+                                            );
+                                        let upstream = Upstream::new(Target::Tcp(addr), timeout);
+                                        let server = RtuServer::new(
+                                            args.serial,
+                                            service.with_upstream(upstream),
+                                            status_sender,
+                                            log_sender,
+                                        );
+                                        server.run().await
+                                    }
+                                }
+                            })
+                            .await
+                        });
                     }
                     Commands::Convert(format) => {
                         if let Some(ref path) = cfg_path {
@@ -309,10 +601,8 @@ fn main() {
                             Value::Float(v) => format!("{}", v),
                         };
                         if let Ok(v) = def.get_type().encode(&s) {
-                            if memory
-                                .lock()
-                                .expect("Unable to lock memory")
-                                .write(def.get_slave_id().unwrap_or(0), def.get_range(), &v)
+                            if def
+                                .write(&mut memory.lock().expect("Unable to lock memory"), &v)
                                 .is_err()
                             {}
                         }
@@ -320,6 +610,14 @@ fn main() {
                 }
             }
 
+            if args.headless {
+                Headless::new(args.log_format)
+                    .run(status_receiver, log_receiver, lua_runtime)
+                    .panic(|e| format!("Run headless failed [{}]", e));
+                terminate = true;
+                break;
+            }
+
             // Run UI
             let app = App::new(register_handler, app_config.clone(), args.client);
             let cmd_sender = if args.client { Some(cmd_sender) } else { None };
@@ -0,0 +1,161 @@
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Bits of a value spread over up to eight registers
+const MAX_BITS: u16 = 128;
+
+/// Named bit or inclusive range of bits of a bitfield register
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub name: String,
+    pub start: u16,
+    pub end: u16,
+}
+
+impl Field {
+    pub fn is_flag(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Value of the field within the value of the register
+    pub fn get(&self, value: u128) -> u128 {
+        let width = self.end - self.start + 1;
+        (value >> self.start) & (u128::MAX >> (MAX_BITS - width))
+    }
+
+    fn key(&self) -> String {
+        if self.is_flag() {
+            format!("{}", self.start)
+        } else {
+            format!("{}-{}", self.start, self.end)
+        }
+    }
+}
+
+/// Names of the bits of a bitfield register keyed by the bit (e.g. `"0"`) or the inclusive range
+/// of bits (e.g. `"3-5"`). Bit `0` is the least significant bit of the first register, bits `16`
+/// and above continue in the following registers.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(
+    try_from = "BTreeMap<String, String>",
+    into = "BTreeMap<String, String>"
+)]
+pub struct BitField {
+    fields: Vec<Field>,
+}
+
+impl TryFrom<BTreeMap<String, String>> for BitField {
+    type Error = anyhow::Error;
+
+    fn try_from(map: BTreeMap<String, String>) -> Result<Self, Self::Error> {
+        let parse = |bit: &str| {
+            bit.trim()
+                .parse::<u16>()
+                .map_err(|e| anyhow::anyhow!("Invalid bit {bit} ({e})"))
+        };
+        let mut fields = Vec::with_capacity(map.len());
+        for (key, name) in map.into_iter() {
+            let (start, end) = match key.split_once('-') {
+                Some((start, end)) => (parse(start)?, parse(end)?),
+                None => (parse(&key)?, parse(&key)?),
+            };
+            if start > end || end >= MAX_BITS {
+                return Err(anyhow::anyhow!("Invalid bit range {key}"));
+            }
+            fields.push(Field { name, start, end });
+        }
+        fields.sort_by_key(|f| f.start);
+        Ok(Self { fields })
+    }
+}
+
+impl From<BitField> for BTreeMap<String, String> {
+    fn from(bits: BitField) -> Self {
+        bits.fields.into_iter().map(|f| (f.key(), f.name)).collect()
+    }
+}
+
+impl BitField {
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    /// Registers covered by the named bits
+    pub fn words(&self) -> usize {
+        self.fields
+            .iter()
+            .map(|f| f.end as usize / 16 + 1)
+            .max()
+            .unwrap_or(1)
+    }
+
+    /// Value of the named bit or range of bits
+    pub fn field(&self, value: u128, name: &str) -> Option<u128> {
+        self.fields
+            .iter()
+            .find(|f| f.name == name)
+            .map(|f| f.get(value))
+    }
+
+    /// Active flags of the value, e.g. `Fault, Mode=5, Bit 7`. Ranges are always listed with
+    /// their value, set bits without name by their number.
+    pub fn describe(&self, value: u128) -> String {
+        let named = |bit: u16| self.fields.iter().any(|f| f.start <= bit && bit <= f.end);
+        let flags = self
+            .fields
+            .iter()
+            .filter(|f| !f.is_flag() || f.get(value) != 0)
+            .map(|f| {
+                if f.is_flag() {
+                    f.name.clone()
+                } else {
+                    format!("{}={}", f.name, f.get(value))
+                }
+            })
+            .chain(
+                (0..MAX_BITS)
+                    .filter(|bit| (value >> bit) & 1 == 1 && !named(*bit))
+                    .map(|bit| format!("Bit {bit}")),
+            )
+            .join(", ");
+        if flags.is_empty() {
+            String::from("None")
+        } else {
+            flags
+        }
+    }
+}
+
+/// Value of the registers with the first register as the least significant word
+pub fn value(words: &[u16]) -> u128 {
+    words
+        .iter()
+        .take(MAX_BITS as usize / 16)
+        .enumerate()
+        .fold(0, |value, (i, w)| value | (*w as u128) << (16 * i))
+}
+
+/// Registers of the value with at least the given number of registers
+pub fn words(value: u128, min: usize) -> Vec<u16> {
+    let used = (MAX_BITS - value.leading_zeros() as u16).div_ceil(16) as usize;
+    (0..used.max(min).max(1))
+        .map(|i| (value >> (16 * i)) as u16)
+        .collect()
+}
+
+/// State of a single bit of the registers
+pub fn bit(words: &[u16], bit: u16) -> Option<bool> {
+    words
+        .get(bit as usize / 16)
+        .map(|w| (w >> (bit % 16)) & 1 == 1)
+}
+
+/// State of a bit entered as encoded value, only `0` and `1` are accepted
+pub fn flag(values: &[u16]) -> anyhow::Result<bool> {
+    match values {
+        [0] => Ok(false),
+        [1] => Ok(true),
+        _ => Err(anyhow::anyhow!("A single bit only accepts 0 or 1")),
+    }
+}
@@ -1,5 +1,6 @@
 use std::ops::{Div, Mul};
 
+use crate::mem::bitfield::{self, BitField};
 use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
 
@@ -32,6 +33,7 @@ pub enum Format {
     F32le,
     F64,
     F64le,
+    Bitfield,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -40,6 +42,8 @@ pub struct DataType {
     format: Format,
     #[serde(default)]
     reverse: bool,
+    /// Names of the bits of a bitfield register
+    bits: Option<BitField>,
 }
 
 impl Default for DataType {
@@ -47,6 +51,7 @@ impl Default for DataType {
         Self {
             format: Format::U8,
             reverse: false,
+            bits: None,
         }
     }
 }
@@ -70,6 +75,58 @@ impl DataType {
         }
     }
 
+    /// Number of registers of the fixed size formats, `None` for strings and bitfields
+    pub fn registers(&self) -> Option<u16> {
+        match self.format {
+            Format::U8
+            | Format::U16
+            | Format::I8
+            | Format::I16
+            | Format::U16le
+            | Format::I8le
+            | Format::I16le => Some(1),
+            Format::U32 | Format::I32 | Format::U32le | Format::I32le => Some(2),
+            Format::F32 | Format::F32le => Some(2),
+            Format::U64 | Format::I64 | Format::U64le | Format::I64le => Some(4),
+            Format::F64 | Format::F64le => Some(4),
+            Format::U128 | Format::I128 | Format::U128le | Format::I128le => Some(8),
+            Format::PackedAscii
+            | Format::LooseAscii
+            | Format::PackedUtf8
+            | Format::LooseUtf8
+            | Format::Bitfield => None,
+        }
+    }
+
+    /// Named bits of a bitfield register, `None` for all other formats
+    pub fn bits(&self) -> Option<BitField> {
+        matches!(self.format, Format::Bitfield).then(|| self.bits.clone().unwrap_or_default())
+    }
+
+    /// Value of the named bit or range of bits of a bitfield register
+    pub fn field(&self, bytes: &[u16], name: &str) -> Option<u128> {
+        self.bits()?.field(self.bitfield_value(bytes), name)
+    }
+
+    /// Position of the bit of the value within the registers, the bytes of every register are
+    /// swapped if reversed
+    pub fn raw_bit(&self, bit: u16) -> u16 {
+        if self.reverse {
+            bit ^ 8
+        } else {
+            bit
+        }
+    }
+
+    fn bitfield_value(&self, bytes: &[u16]) -> u128 {
+        bitfield::value(
+            &bytes
+                .iter()
+                .map(|v| self.apply_order(*v))
+                .collect::<Vec<_>>(),
+        )
+    }
+
     pub fn as_plain_str(&self, bytes: &[u16]) -> anyhow::Result<String> {
         match self.format {
             Format::F32 => {
@@ -130,6 +187,7 @@ impl DataType {
                     Err(anyhow!("Not enough bytes"))
                 }
             }
+            Format::Bitfield => Ok(format!("{}", self.bitfield_value(bytes))),
             Format::PackedAscii => String::from_utf8(
                 bytes
                     .iter()
@@ -433,6 +491,13 @@ impl DataType {
                     Err(anyhow!("Not enough bytes"))
                 }
             }
+            Format::Bitfield => {
+                let value = self.bitfield_value(bytes);
+                Ok((
+                    format!("0x{:01$X}", value, 4 * bytes.len().max(1)),
+                    self.bits().unwrap_or_default().describe(value),
+                ))
+            }
             Format::PackedAscii => String::from_utf8(
                 bytes
                     .iter()
@@ -858,6 +923,18 @@ impl DataType {
                     self.apply_order(((val & 0xFFFF000000000000) >> 48) as u16),
                 ])
             }
+            Format::Bitfield => {
+                let val: u128 = if let Some(s) = s.strip_prefix("0x") {
+                    u128::from_str_radix(s, 16)?
+                } else {
+                    s.parse()?
+                };
+                let min = self.bits().map(|bits| bits.words()).unwrap_or(1);
+                Ok(bitfield::words(val, min)
+                    .into_iter()
+                    .map(|v| self.apply_order(v))
+                    .collect())
+            }
             Format::PackedAscii => {
                 let mut v = Vec::with_capacity(s.len() / 2 + 1);
                 let bytes: Vec<u8> = s.chars().map(|c| c as u8).collect();
@@ -0,0 +1,58 @@
+use crate::frame;
+
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+use tokio_modbus::bytes::Bytes;
+use tokio_modbus::client::Context;
+use tokio_modbus::prelude::{ExceptionCode, Response};
+
+/// Function code of Read FIFO Queue
+pub const READ_FIFO_QUEUE: u8 = 0x18;
+
+/// Entries returned with a single response as limited by the specification
+pub const MAX_COUNT: usize = 31;
+
+/// Parse the FIFO pointer address of a request (without function code)
+pub fn parse_request(data: &[u8]) -> Result<u16, ExceptionCode> {
+    match data {
+        [hi, lo] => Ok(u16::from_be_bytes([*hi, *lo])),
+        _ => Err(ExceptionCode::IllegalDataValue),
+    }
+}
+
+/// Read FIFO Queue (FC 24) response with the byte count, the FIFO count and the entries
+pub fn response(values: &[u16]) -> Response {
+    let mut data = Vec::with_capacity(4 + 2 * values.len());
+    data.extend_from_slice(&(2 + 2 * values.len() as u16).to_be_bytes());
+    data.extend_from_slice(&(values.len() as u16).to_be_bytes());
+    data.extend(values.iter().flat_map(|v| v.to_be_bytes()));
+    Response::Custom(READ_FIFO_QUEUE, Bytes::from(data))
+}
+
+/// Read the entries of the FIFO queue behind the pointer address
+pub async fn read(
+    ctx: &mut Context,
+    addr: u16,
+    timeout: Duration,
+) -> tokio_modbus::Result<Vec<u16>> {
+    let rsp = match frame::call_custom(ctx, READ_FIFO_QUEUE, &addr.to_be_bytes(), timeout).await? {
+        Ok(rsp) => rsp,
+        Err(exception) => return Ok(Err(exception)),
+    };
+    match rsp.as_ref() {
+        [byte_hi, byte_lo, count_hi, count_lo, data @ ..]
+            if u16::from_be_bytes([*byte_hi, *byte_lo]) as usize == 2 + data.len()
+                && u16::from_be_bytes([*count_hi, *count_lo]) as usize * 2 == data.len() =>
+        {
+            Ok(Ok(data
+                .chunks(2)
+                .map(|w| u16::from_be_bytes([w[0], w[1]]))
+                .collect()))
+        }
+        _ => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Unexpected response {}", frame::to_hex(&rsp)),
+        )
+        .into()),
+    }
+}
@@ -0,0 +1,252 @@
+use crate::frame;
+
+use clap::ValueEnum;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+use tokio_modbus::client::Context;
+use tokio_modbus::prelude::{ExceptionCode, SlaveId};
+
+/// Function code of Read File Record
+pub const READ_FILE_RECORD: u8 = 0x14;
+
+/// Function code of Write File Record
+pub const WRITE_FILE_RECORD: u8 = 0x15;
+
+/// Reference type of all sub-requests
+const REFERENCE_TYPE: u8 = 0x06;
+
+/// Records per file (record numbers `0` to `9999`)
+pub const MAX_RECORDS: usize = 10000;
+
+/// Records fetched with a single request, the response is limited to 253 bytes
+pub const MAX_RECORDS_PER_REQUEST: usize = 120;
+
+/// Encoding of the records in a file on disk
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize, ValueEnum)]
+pub enum FileFormat {
+    /// Records as 16 bit big endian words
+    Binary,
+    /// One record per line (decimal or hexadecimal with 0x prefix)
+    Csv,
+}
+
+impl FileFormat {
+    /// Derive the format from the file extension (defaults to binary)
+    pub fn from_path(path: &str) -> Self {
+        if path.to_lowercase().ends_with(".csv") {
+            FileFormat::Csv
+        } else {
+            FileFormat::Binary
+        }
+    }
+}
+
+/// File of the server preloaded from disk
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct FileConfig {
+    slave_id: Option<SlaveId>,
+    file: u16,
+    path: String,
+    format: Option<FileFormat>,
+}
+
+impl FileConfig {
+    pub fn slave_id(&self) -> SlaveId {
+        self.slave_id.unwrap_or(0)
+    }
+
+    pub fn file(&self) -> u16 {
+        self.file
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn load(&self) -> anyhow::Result<Vec<u16>> {
+        load(
+            &self.path,
+            self.format
+                .unwrap_or_else(|| FileFormat::from_path(&self.path)),
+        )
+    }
+}
+
+fn parse_record(s: &str) -> anyhow::Result<u16> {
+    Ok(
+        match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(hex) => u16::from_str_radix(hex, 16)?,
+            None => s.parse()?,
+        },
+    )
+}
+
+/// Read the records of a file. CSV files may contain several values per line, empty cells and
+/// lines starting with `#` are skipped.
+pub fn load(path: &str, format: FileFormat) -> anyhow::Result<Vec<u16>> {
+    let records: Vec<u16> = match format {
+        FileFormat::Binary => {
+            let bytes = std::fs::read(path)?;
+            if !bytes.len().is_multiple_of(2) {
+                return Err(anyhow::anyhow!("Odd number of bytes"));
+            }
+            bytes
+                .chunks(2)
+                .map(|w| u16::from_be_bytes([w[0], w[1]]))
+                .collect()
+        }
+        FileFormat::Csv => std::fs::read_to_string(path)?
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .flat_map(|line| line.split(','))
+            .map(str::trim)
+            .filter(|cell| !cell.is_empty())
+            .map(|cell| {
+                parse_record(cell).map_err(|e| anyhow::anyhow!("Invalid record {cell} ({e})"))
+            })
+            .collect::<anyhow::Result<_>>()?,
+    };
+    if records.len() > MAX_RECORDS {
+        return Err(anyhow::anyhow!(
+            "{} records exceed the maximum of {MAX_RECORDS}",
+            records.len()
+        ));
+    }
+    Ok(records)
+}
+
+/// Write the records to a file
+pub fn save(path: &str, format: FileFormat, records: &[u16]) -> anyhow::Result<()> {
+    let content = match format {
+        FileFormat::Binary => records.iter().flat_map(|r| r.to_be_bytes()).collect(),
+        FileFormat::Csv => records
+            .iter()
+            .map(|r| format!("{r}\n"))
+            .join("")
+            .into_bytes(),
+    };
+    Ok(std::fs::write(path, content)?)
+}
+
+/// Sub-request of a Read File Record (FC 20) or Write File Record (FC 21) request
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SubRequest {
+    pub file: u16,
+    pub record: u16,
+    pub length: u16,
+    /// Records to write (empty for reads)
+    pub data: Vec<u16>,
+}
+
+impl SubRequest {
+    /// Records covered by the sub-request
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.record as usize..self.record as usize + self.length as usize
+    }
+}
+
+/// Split the data of a request (without function code) into its sub-requests. Write requests carry
+/// the records to write.
+pub fn parse_request(data: &[u8], write: bool) -> Result<Vec<SubRequest>, ExceptionCode> {
+    let [count, rest @ ..] = data else {
+        return Err(ExceptionCode::IllegalDataValue);
+    };
+    let mut rest = rest;
+    if *count as usize != rest.len() || !(0x07..=0xF5).contains(count) {
+        return Err(ExceptionCode::IllegalDataValue);
+    }
+    let mut requests = Vec::new();
+    while !rest.is_empty() {
+        let [reference, file_hi, file_lo, record_hi, record_lo, length_hi, length_lo, tail @ ..] =
+            rest
+        else {
+            return Err(ExceptionCode::IllegalDataValue);
+        };
+        let request = SubRequest {
+            file: u16::from_be_bytes([*file_hi, *file_lo]),
+            record: u16::from_be_bytes([*record_hi, *record_lo]),
+            length: u16::from_be_bytes([*length_hi, *length_lo]),
+            data: Vec::new(),
+        };
+        rest = tail;
+        let request = if write {
+            let len = 2 * request.length as usize;
+            if rest.len() < len {
+                return Err(ExceptionCode::IllegalDataValue);
+            }
+            let (data, tail) = rest.split_at(len);
+            rest = tail;
+            SubRequest {
+                data: data
+                    .chunks(2)
+                    .map(|w| u16::from_be_bytes([w[0], w[1]]))
+                    .collect(),
+                ..request
+            }
+        } else {
+            request
+        };
+        if *reference != REFERENCE_TYPE || request.file == 0 || request.range().end > MAX_RECORDS {
+            return Err(ExceptionCode::IllegalDataAddress);
+        }
+        requests.push(request);
+    }
+    Ok(requests)
+}
+
+/// Data of a Read File Record (FC 20) response with the records of all sub-requests
+pub fn read_response(records: &[Vec<u16>]) -> Result<Vec<u8>, ExceptionCode> {
+    let size: usize = records.iter().map(|r| 2 + 2 * r.len()).sum();
+    // Function code and data length leave 251 bytes for the sub-responses
+    if size > 251 {
+        return Err(ExceptionCode::IllegalDataValue);
+    }
+    let mut data = vec![size as u8];
+    for records in records.iter() {
+        data.push((1 + 2 * records.len()) as u8);
+        data.push(REFERENCE_TYPE);
+        data.extend(records.iter().flat_map(|r| r.to_be_bytes()));
+    }
+    Ok(data)
+}
+
+/// Read the records of a file range, split into several requests if necessary
+pub async fn read(
+    ctx: &mut Context,
+    file: u16,
+    record: u16,
+    length: u16,
+    timeout: Duration,
+) -> tokio_modbus::Result<Vec<u16>> {
+    let mut records = Vec::with_capacity(length as usize);
+    let end = record as usize + length as usize;
+    for start in (record as usize..end).step_by(MAX_RECORDS_PER_REQUEST) {
+        let len = MAX_RECORDS_PER_REQUEST.min(end - start) as u16;
+        let mut request = vec![0x07, REFERENCE_TYPE];
+        request.extend_from_slice(&file.to_be_bytes());
+        request.extend_from_slice(&(start as u16).to_be_bytes());
+        request.extend_from_slice(&len.to_be_bytes());
+        let rsp = match frame::call_custom(ctx, READ_FILE_RECORD, &request, timeout).await? {
+            Ok(rsp) => rsp,
+            Err(exception) => return Ok(Err(exception)),
+        };
+        let words = 2 * len as usize;
+        match rsp.as_ref() {
+            [_, sub_len, REFERENCE_TYPE, data @ ..]
+                if *sub_len as usize == words + 1 && data.len() == words =>
+            {
+                records.extend(data.chunks(2).map(|w| u16::from_be_bytes([w[0], w[1]])))
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Unexpected response {}", frame::to_hex(&rsp)),
+                )
+                .into())
+            }
+        }
+    }
+    Ok(Ok(records))
+}
@@ -1,8 +1,11 @@
+use crate::mem::fifo;
+
 use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fmt::Debug;
 use tokio_modbus::prelude::SlaveId;
+use tokio_modbus::FunctionCode;
 
 const SLICE_SIZE: usize = 1024;
 
@@ -33,9 +36,80 @@ impl<Key: Into<usize> + Clone + Debug> Range<Key> {
     }
 }
 
+/// The four independent Modbus data tables kept per slave
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Table {
+    Coils,
+    DiscreteInputs,
+    InputRegisters,
+    HoldingRegisters,
+}
+
+impl Table {
+    /// Returns the table a function code operates on, if any
+    pub fn from_function_code(code: FunctionCode) -> Option<Self> {
+        match code {
+            FunctionCode::ReadCoils
+            | FunctionCode::WriteSingleCoil
+            | FunctionCode::WriteMultipleCoils => Some(Table::Coils),
+            FunctionCode::ReadDiscreteInputs => Some(Table::DiscreteInputs),
+            FunctionCode::ReadInputRegisters => Some(Table::InputRegisters),
+            FunctionCode::ReadHoldingRegisters
+            | FunctionCode::WriteSingleRegister
+            | FunctionCode::WriteMultipleRegisters
+            | FunctionCode::MaskWriteRegister
+            | FunctionCode::ReadWriteMultipleRegisters => Some(Table::HoldingRegisters),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Table {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            Table::Coils => f.write_str("Coils"),
+            Table::DiscreteInputs => f.write_str("DiscreteInputs"),
+            Table::InputRegisters => f.write_str("InputRegisters"),
+            Table::HoldingRegisters => f.write_str("HoldingRegisters"),
+        }
+    }
+}
+
+/// Slave whose tables are kept in memory
+///
+/// The slaves of different client connections are kept apart by the index of the connection.
+/// Index `0` is used by the server and by definitions without connection.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+pub struct Unit {
+    pub connection: usize,
+    pub slave: SlaveId,
+}
+
+impl Unit {
+    pub fn new(connection: usize, slave: SlaveId) -> Self {
+        Self { connection, slave }
+    }
+}
+
+impl From<SlaveId> for Unit {
+    fn from(slave: SlaveId) -> Self {
+        Self::new(0, slave)
+    }
+}
+
+impl std::fmt::Display for Unit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}", self.slave)
+    }
+}
+
 pub struct Memory {
-    slices: HashMap<(SlaveId, usize), [u16; SLICE_SIZE]>,
-    bounds: HashMap<SlaveId, Range<usize>>,
+    slices: HashMap<(Unit, Table, usize), [u16; SLICE_SIZE]>,
+    bounds: HashMap<(Unit, Table), Range<usize>>,
+    files: HashMap<(Unit, u16), Vec<u16>>,
+    fifos: HashMap<(Unit, u16), VecDeque<u16>>,
 }
 
 impl Memory {
@@ -43,17 +117,21 @@ impl Memory {
         Self {
             slices: HashMap::new(),
             bounds: HashMap::new(), //Range::new(usize::max_value(), usize::max_value()),
+            files: HashMap::new(),
+            fifos: HashMap::new(),
         }
     }
 
     pub fn init<Key: Into<usize> + Clone + Debug>(
         &mut self,
-        slave: SlaveId,
+        slave: impl Into<Unit>,
+        table: Table,
         ranges: &[Range<Key>],
     ) {
+        let slave = slave.into();
         let bounds = self
             .bounds
-            .entry(slave)
+            .entry((slave, table))
             .or_insert(Range::new(usize::MAX, usize::MAX));
         for range in ranges.iter() {
             let upper = if bounds.end() == usize::MAX {
@@ -66,7 +144,7 @@ impl Memory {
             let range = (range.start() / SLICE_SIZE, range.end() / SLICE_SIZE + 1);
             for i in range.0..range.1 {
                 self.slices
-                    .entry((slave, i))
+                    .entry((slave, table, i))
                     .or_insert_with(|| [0; SLICE_SIZE]);
             }
         }
@@ -74,28 +152,32 @@ impl Memory {
 
     pub fn write<'a, Key: Into<usize> + Clone + Debug>(
         &mut self,
-        slave: SlaveId,
+        slave: impl Into<Unit>,
+        table: Table,
         range: Range<Key>,
         mut values: &'a [u16],
     ) -> anyhow::Result<&'a [u16]> {
+        let slave = slave.into();
         let range = (range.start(), range.end());
-        let bounds = self.bounds.get(&slave);
+        let bounds = self.bounds.get(&(slave, table));
         if bounds.is_none() {
-            return Err(anyhow!("Invalid memory address ({slave}, {range:?})"));
+            return Err(anyhow!(
+                "Invalid memory address ({slave}, {table}, {range:?})"
+            ));
         }
         let bounds = bounds.unwrap();
         if bounds.start() > range.0 || bounds.end() < range.1 {
             return Err(anyhow!(
-                "Range not available in memory ({slave}, {range:?})"
+                "Range not available in memory ({slave}, {table}, {range:?})"
             ));
         }
         if (range.1 - range.0) < values.len() {
             return Err(anyhow!("Range too large for given value slice."));
         } else if !((range.0 / SLICE_SIZE)..(range.1 / SLICE_SIZE + 1))
-            .all(|v| self.slices.contains_key(&(slave, v)))
+            .all(|v| self.slices.contains_key(&(slave, table, v)))
         {
             return Err(anyhow!(
-                "Range not available in memory ({slave}, {range:?})"
+                "Range not available in memory ({slave}, {table}, {range:?})"
             ));
         }
 
@@ -105,7 +187,7 @@ impl Memory {
             let bound = std::cmp::min(len, SLICE_SIZE - start);
             let slice = self
                 .slices
-                .get_mut(&(slave, idx))
+                .get_mut(&(slave, table, idx))
                 .expect("Slice does not exist.");
             slice[start..(start + bound)].copy_from_slice(&values[..bound]);
             values = &values[bound..];
@@ -118,21 +200,23 @@ impl Memory {
 
     pub fn read<Key: Into<usize> + Clone + Debug>(
         &mut self,
-        slave: SlaveId,
+        slave: impl Into<Unit>,
+        table: Table,
         range: &Range<Key>,
     ) -> anyhow::Result<Vec<&u16>> {
-        let bounds = self.bounds.get(&slave);
-        if bounds.is_none() {
-            return Err(anyhow!("Invalid memory address ({slave}, {range:?})"));
+        let slave = slave.into();
+        if !self.bounds.keys().any(|(s, _)| *s == slave) {
+            return Err(anyhow!(
+                "Invalid memory address ({slave}, {table}, {range:?})"
+            ));
         }
-        let bounds = bounds.unwrap();
-        if bounds.start() > range.end()
-            || bounds.end() < range.end()
+        let bounds = self.bounds.get(&(slave, table));
+        if bounds.is_none_or(|bounds| bounds.start() > range.end() || bounds.end() < range.end())
             || !((range.start() / SLICE_SIZE)..(range.end() / SLICE_SIZE + 1))
-                .all(|v| self.slices.contains_key(&(slave, v)))
+                .all(|v| self.slices.contains_key(&(slave, table, v)))
         {
             let r = range.clone();
-            self.init(slave, &[r]);
+            self.init(slave, table, &[r]);
         }
 
         let mut len = range.end() - range.start();
@@ -142,7 +226,7 @@ impl Memory {
             let bound = std::cmp::min(len, SLICE_SIZE - start);
             let slice = self
                 .slices
-                .get(&(slave, idx))
+                .get(&(slave, table, idx))
                 .expect("Slice does not exist.");
             slice[start..(start + bound)]
                 .iter()
@@ -153,4 +237,94 @@ impl Memory {
 
         Ok(vec)
     }
+
+    /// Change a single bit of the register and keep all other bits (read-modify-write)
+    pub fn write_bit(
+        &mut self,
+        slave: impl Into<Unit>,
+        table: Table,
+        addr: u16,
+        bit: u16,
+        set: bool,
+    ) -> anyhow::Result<()> {
+        if bit >= 16 {
+            return Err(anyhow!("Bit {bit} exceeds the 16 bits of a register"));
+        }
+        let slave = slave.into();
+        let range = Range::new(addr, addr + 1);
+        let value = *self.read(slave, table, &range)?[0];
+        let value = if set {
+            value | (1 << bit)
+        } else {
+            value & !(1 << bit)
+        };
+        self.write(slave, table, range, &[value]).map(|_| ())
+    }
+
+    /// Replace the records of the file (file record access)
+    pub fn load_file(&mut self, slave: impl Into<Unit>, file: u16, records: Vec<u16>) {
+        self.files.insert((slave.into(), file), records);
+    }
+
+    /// Records of the file, `None` if the range exceeds the file
+    pub fn read_file(
+        &self,
+        slave: impl Into<Unit>,
+        file: u16,
+        range: std::ops::Range<usize>,
+    ) -> Option<&[u16]> {
+        self.files
+            .get(&(slave.into(), file))
+            .and_then(|records| records.get(range))
+    }
+
+    /// Write the records starting at the given record, the file grows as required
+    pub fn write_file(&mut self, slave: impl Into<Unit>, file: u16, record: usize, values: &[u16]) {
+        let records = self.files.entry((slave.into(), file)).or_default();
+        if records.len() < record + values.len() {
+            records.resize(record + values.len(), 0);
+        }
+        records[record..record + values.len()].copy_from_slice(values);
+    }
+
+    /// Replace the entries of the FIFO queue behind the pointer address (read FIFO queue)
+    pub fn init_fifo(&mut self, slave: impl Into<Unit>, addr: u16, values: &[u16]) {
+        self.fifos
+            .insert((slave.into(), addr), values.iter().copied().collect());
+    }
+
+    /// Entries of the FIFO queue, `None` if there is no queue at the address
+    pub fn fifo(&self, slave: impl Into<Unit>, addr: u16) -> Option<Vec<u16>> {
+        self.fifos
+            .get(&(slave.into(), addr))
+            .map(|queue| queue.iter().copied().collect())
+    }
+
+    /// Append an entry to the FIFO queue. Returns `false` if the entry does not fit into the queue
+    /// or there is no queue at the address.
+    pub fn push_fifo(&mut self, slave: impl Into<Unit>, addr: u16, values: &[u16]) -> bool {
+        match self.fifos.get_mut(&(slave.into(), addr)) {
+            Some(queue) if queue.len() + values.len() <= fifo::MAX_COUNT => {
+                queue.extend(values);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Take all entries of the FIFO queue, `None` if there is no queue at the address
+    pub fn pop_fifo(&mut self, slave: impl Into<Unit>, addr: u16) -> Option<Vec<u16>> {
+        self.fifos
+            .get_mut(&(slave.into(), addr))
+            .map(|queue| queue.drain(..).collect())
+    }
+
+    /// Append the entries read by the client, only the latest entries are kept
+    pub fn record_fifo(&mut self, slave: impl Into<Unit>, addr: u16, values: &[u16]) {
+        let queue = self.fifos.entry((slave.into(), addr)).or_default();
+        queue.extend(values);
+        while queue.len() > fifo::MAX_COUNT {
+            queue.pop_front();
+        }
+    }
 }
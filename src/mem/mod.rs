@@ -4,5 +4,8 @@ pub mod data;
 #[cfg(not(feature = "f128"))]
 pub mod datav2;
 
+pub mod bitfield;
+pub mod fifo;
+pub mod file;
 pub mod memory;
 pub mod register;
@@ -4,7 +4,8 @@ use crate::mem::data::DataType;
 #[cfg(not(feature = "f128"))]
 use crate::mem::datav2::DataType;
 
-use crate::mem::memory::{Memory, Range};
+use crate::mem::bitfield;
+use crate::mem::memory::{Memory, Range, Table, Unit};
 use crate::util::str;
 use crate::util::Expect;
 use crate::AppConfig;
@@ -109,8 +110,14 @@ pub struct Definition {
     #[serde(flatten)]
     r#type: DataType,
     read_code: u8,
+    /// Function code used to write the register in client mode, derived from the length if omitted
+    write_code: Option<u8>,
     access: AccessType,
     default: Option<Value>,
+    /// Initial entries of a FIFO queue read with FC 24, served in server mode
+    queue: Option<Vec<Value>>,
+    /// Single bit of the register the definition is scoped to
+    bit: Option<u16>,
     on_update: Option<String>,
     r#virtual: Option<bool>,
     values: Option<Vec<Values>>,
@@ -118,6 +125,11 @@ pub struct Definition {
     #[serde(skip, default = "next_counter")]
     index: usize,
     resolution: Option<f64>,
+    /// Name of the client connection the register is read from
+    connection: Option<String>,
+    /// Index of the connection, resolved after loading the configuration
+    #[serde(skip)]
+    connection_index: usize,
 }
 
 impl Definition {
@@ -144,14 +156,19 @@ impl Definition {
             length,
             r#type,
             read_code,
+            write_code: None,
             access,
             default,
+            queue: None,
+            bit: None,
             on_update,
             r#virtual,
             values,
             index: next_counter(),
             description,
             resolution,
+            connection: None,
+            connection_index: 0,
         }
     }
 
@@ -175,10 +192,100 @@ impl Definition {
         &self.default
     }
 
+    /// The read code is one of the read functions of a table or FIFO queue. Custom function codes
+    /// are answered by their Lua handlers and can not be displayed as register.
+    pub fn is_readable(&self) -> bool {
+        matches!(
+            FunctionCode::new(self.read_code),
+            FunctionCode::ReadCoils
+                | FunctionCode::ReadDiscreteInputs
+                | FunctionCode::ReadInputRegisters
+                | FunctionCode::ReadHoldingRegisters
+                | FunctionCode::ReadFifoQueue
+        )
+    }
+
+    /// The register is the pointer address of a FIFO queue read with FC 24
+    pub fn is_fifo(&self) -> bool {
+        FunctionCode::new(self.read_code) == FunctionCode::ReadFifoQueue
+    }
+
+    /// Encoded initial entries of the FIFO queue, every entry occupies `length` registers
+    pub fn queue(&self) -> anyhow::Result<Vec<u16>> {
+        let mut values = Vec::new();
+        for value in self.queue.iter().flatten() {
+            let mut entry = self.r#type.encode(&value.to_string())?;
+            if entry.len() > self.length as usize {
+                return Err(anyhow::anyhow!(
+                    "Entry {value} requires a longer register as available"
+                ));
+            }
+            entry.resize(self.length as usize, 0);
+            values.extend(entry);
+        }
+        Ok(values)
+    }
+
+    pub fn bit(&self) -> Option<u16> {
+        self.bit
+    }
+
+    /// The bit is part of a single holding or input register
+    pub fn is_valid_bit(&self) -> bool {
+        self.bit.is_none_or(|bit| {
+            bit < 16
+                && self.length == 1
+                && !self.is_fifo()
+                && matches!(
+                    self.table(),
+                    Table::HoldingRegisters | Table::InputRegisters
+                )
+        })
+    }
+
+    /// Registers of the definition decoded by its type, only the state of the bit (`0` or `1`)
+    /// if the definition is scoped to a single bit
+    pub fn scope(&self, words: &[u16]) -> Vec<u16> {
+        match self.bit {
+            Some(bit) => vec![bitfield::bit(words, bit).unwrap_or_default() as u16],
+            None => words.to_vec(),
+        }
+    }
+
+    /// Write the encoded values to memory, only the bit if the definition is scoped to a single
+    /// bit
+    pub fn write(&self, memory: &mut Memory, values: &[u16]) -> anyhow::Result<()> {
+        match self.bit {
+            Some(bit) => memory.write_bit(
+                self.unit(),
+                self.table(),
+                self.get_address(),
+                bit,
+                bitfield::flag(values)?,
+            ),
+            None => memory
+                .write(self.unit(), self.table(), self.get_range(), values)
+                .map(|_| ()),
+        }
+    }
+
     pub fn get_slave_id(&self) -> &Option<SlaveId> {
         &self.slave_id
     }
 
+    pub fn get_connection(&self) -> &Option<String> {
+        &self.connection
+    }
+
+    pub fn set_connection_index(&mut self, index: usize) {
+        self.connection_index = index;
+    }
+
+    /// Slave of the register in memory
+    pub fn unit(&self) -> Unit {
+        Unit::new(self.connection_index, self.slave_id.unwrap_or(0))
+    }
+
     pub fn get_range(&self) -> Range<u16> {
         Range::new(self.address.as_u16(), self.address.as_u16() + self.length)
     }
@@ -195,6 +302,44 @@ impl Definition {
         self.read_code
     }
 
+    /// Function code used to write the register. Single coils and registers are written with
+    /// FC 05 and FC 06, longer ones with FC 15 and FC 16.
+    pub fn write_code(&self) -> FunctionCode {
+        match (self.write_code, self.table(), self.length) {
+            (Some(code), _, _) => FunctionCode::new(code),
+            (None, Table::Coils | Table::DiscreteInputs, 1) => FunctionCode::WriteSingleCoil,
+            (None, Table::Coils | Table::DiscreteInputs, _) => FunctionCode::WriteMultipleCoils,
+            (None, _, 1) => FunctionCode::WriteSingleRegister,
+            (None, _, _) => FunctionCode::WriteMultipleRegisters,
+        }
+    }
+
+    /// The write code writes the table of the read code. Only holding registers are read back
+    /// with FC 23.
+    pub fn is_writable(&self) -> bool {
+        match (self.table(), self.write_code()) {
+            (
+                Table::Coils | Table::DiscreteInputs,
+                FunctionCode::WriteSingleCoil | FunctionCode::WriteMultipleCoils,
+            ) => true,
+            (
+                Table::InputRegisters | Table::HoldingRegisters,
+                FunctionCode::WriteSingleRegister | FunctionCode::WriteMultipleRegisters,
+            ) => true,
+            (Table::HoldingRegisters, FunctionCode::ReadWriteMultipleRegisters) => !self.is_fifo(),
+            _ => false,
+        }
+    }
+
+    /// The pointer address of a FIFO queue is a holding register
+    pub fn table(&self) -> Table {
+        if self.is_fifo() {
+            return Table::HoldingRegisters;
+        }
+        Table::from_function_code(FunctionCode::new(self.read_code))
+            .unwrap_or_else(|| panic!("Invalid read function code for register {:?}", self.address))
+    }
+
     pub fn length(&self) -> u16 {
         self.length
     }
@@ -218,11 +363,13 @@ impl Definition {
 
 #[derive(Clone)]
 pub struct Register {
-    slave: SlaveId,
+    unit: Unit,
+    connection: Option<String>,
     address: u16,
     value: (String, String),
     length: u16,
-    function_code: FunctionCode,
+    write_code: FunctionCode,
+    table: Table,
     raw: Vec<u16>,
     r#type: DataType,
     access: AccessType,
@@ -230,47 +377,48 @@ pub struct Register {
     description: Option<String>,
     index: usize,
     resolution: f64,
+    fifo: bool,
+    bit: Option<u16>,
 }
 
 impl Register {
     pub fn new(definition: &Definition, memory: &Arc<Mutex<Memory>>) -> Self {
-        let read_code = FunctionCode::new(definition.read_code);
-        match read_code {
-            FunctionCode::WriteSingleCoil
-            | FunctionCode::WriteMultipleCoils
-            | FunctionCode::WriteSingleRegister
-            | FunctionCode::WriteMultipleRegisters
-            | FunctionCode::ReadWriteMultipleRegisters
-            | FunctionCode::Custom(_) => {
-                panic!(
-                    "Invalid read function code for register {:?}",
-                    definition.address
+        if !definition.is_readable() {
+            panic!(
+                "Invalid read function code for register {:?}",
+                definition.address
+            )
+        }
+
+        let (bytes, value) = if definition.is_fifo() {
+            Self::queued(definition, memory)
+        } else {
+            let bytes: Vec<u16> = memory
+                .lock()
+                .expect("Unable to lock memory")
+                .read(
+                    definition.unit(),
+                    definition.table(),
+                    &definition.get_range(),
                 )
-            }
-            _ => {}
+                .panic(|e| format!("{}", e))
+                .into_iter()
+                .copied()
+                .collect();
+            let value = definition
+                .get_type()
+                .as_str(&definition.scope(&bytes), definition.get_resolution())
+                .unwrap_or((str!("Invalid data"), String::new()));
+            (bytes, value)
         };
 
-        let bytes: Vec<u16> = memory
-            .lock()
-            .expect("Unable to lock memory")
-            .read(
-                definition.get_slave_id().unwrap_or(0),
-                &definition.get_range(),
-            )
-            .panic(|e| format!("{}", e))
-            .into_iter()
-            .copied()
-            .collect();
-        let value = definition
-            .get_type()
-            .as_str(&bytes, definition.get_resolution())
-            .unwrap_or((str!("Invalid data"), String::new()));
-
         Self {
-            slave: definition.get_slave_id().unwrap_or(0),
+            unit: definition.unit(),
+            connection: definition.get_connection().clone(),
             address: definition.address.as_u16(),
             value,
-            function_code: read_code,
+            write_code: definition.write_code(),
+            table: definition.table(),
             length: definition.length(),
             raw: bytes,
             r#type: definition.get_type().clone(),
@@ -279,9 +427,39 @@ impl Register {
             index: definition.get_index(),
             description: definition.description(),
             resolution: definition.get_resolution(),
+            fifo: definition.is_fifo(),
+            bit: definition.bit(),
         }
     }
 
+    /// Entries of the FIFO queue and their values, decoded entry by entry
+    fn queued(
+        definition: &Definition,
+        memory: &Arc<Mutex<Memory>>,
+    ) -> (Vec<u16>, (String, String)) {
+        let entries = memory
+            .lock()
+            .expect("Unable to lock memory")
+            .fifo(definition.unit(), definition.get_address())
+            .unwrap_or_default();
+        let values = entries
+            .chunks(definition.length().max(1) as usize)
+            .map(|entry| {
+                definition
+                    .get_type()
+                    .as_str(entry, definition.get_resolution())
+                    .map(|(_, value)| value)
+                    .unwrap_or(str!("Invalid data"))
+            })
+            .collect::<Vec<_>>();
+        let value = if values.is_empty() {
+            str!("Empty")
+        } else {
+            values.join(", ")
+        };
+        (entries, (format!("FIFO ({})", values.len()), value))
+    }
+
     pub fn values(&self) -> &Option<Vec<Values>> {
         &self.values
     }
@@ -291,7 +469,15 @@ impl Register {
     }
 
     pub fn slave_id(&self) -> SlaveId {
-        self.slave
+        self.unit.slave
+    }
+
+    pub fn unit(&self) -> Unit {
+        self.unit
+    }
+
+    pub fn connection(&self) -> &Option<String> {
+        &self.connection
     }
 
     pub fn address(&self) -> u16 {
@@ -318,8 +504,12 @@ impl Register {
         self.resolution
     }
 
-    pub fn function_code(&self) -> FunctionCode {
-        self.function_code
+    pub fn write_code(&self) -> FunctionCode {
+        self.write_code
+    }
+
+    pub fn table(&self) -> Table {
+        self.table
     }
 
     pub fn access_type(&self) -> AccessType {
@@ -329,6 +519,15 @@ impl Register {
     pub fn get_index(&self) -> usize {
         self.index
     }
+
+    pub fn is_fifo(&self) -> bool {
+        self.fifo
+    }
+
+    /// Single bit of the register the value is scoped to
+    pub fn bit(&self) -> Option<u16> {
+        self.bit
+    }
 }
 
 pub struct Handler {
@@ -360,10 +559,55 @@ impl Handler {
             .collect()
     }
 
-    pub fn set_values(&mut self, slave: SlaveId, addr: u16, values: &[u16]) -> anyhow::Result<()> {
+    pub fn set_values(
+        &mut self,
+        slave: impl Into<Unit>,
+        table: Table,
+        addr: u16,
+        values: &[u16],
+    ) -> anyhow::Result<()> {
         let mut memory = self.memory.lock().expect("Unable to lock memory");
         memory
-            .write(slave, Range::new(addr, addr + values.len() as u16), values)
+            .write(
+                slave,
+                table,
+                Range::new(addr, addr + values.len() as u16),
+                values,
+            )
             .map(|_| ())
     }
+
+    /// Change a single bit of the register in memory and keep all other bits. Bits `16` and above
+    /// continue in the following registers.
+    pub fn set_bit(&mut self, register: &Register, bit: u16, set: bool) -> anyhow::Result<()> {
+        if bit >= 16 * register.length() {
+            return Err(anyhow::anyhow!(
+                "Bit {bit} exceeds the register length of {} bits",
+                16 * register.length()
+            ));
+        }
+        let mut memory = self.memory.lock().expect("Unable to lock memory");
+        memory.write_bit(
+            register.unit(),
+            register.table(),
+            register.address() + bit / 16,
+            bit % 16,
+            set,
+        )
+    }
+
+    /// Append the encoded entry to the FIFO queue of the register, padded to its length
+    pub fn push_fifo(&mut self, register: &Register, values: &[u16]) -> anyhow::Result<()> {
+        let mut entry = values.to_vec();
+        entry.resize(register.length() as usize, 0);
+        let mut memory = self.memory.lock().expect("Unable to lock memory");
+        if memory.push_fifo(register.unit(), register.address(), &entry) {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(
+                "FIFO queue {:#06X} is full",
+                register.address()
+            ))
+        }
+    }
 }
@@ -1,18 +1,117 @@
+use crate::frame;
+use crate::mem::memory::{Table, Unit};
+use crate::mem::register::{AccessType, Register};
 use crate::util::str;
 use chrono::Local;
-use tokio_modbus::prelude::SlaveId;
+use tokio_modbus::FunctionCode;
 
 pub enum Status {
     String(String),
 }
 
+#[derive(Clone)]
 pub enum Command {
     Connect,
     Disconnect,
-    WriteSingleCoil((SlaveId, u16, bool, bool)),
-    WriteMultipleCoils((SlaveId, u16, Vec<bool>, bool)),
-    WriteSingleRegister((SlaveId, u16, u16, bool)),
-    WriteMultipleRegisters((SlaveId, u16, Vec<u16>, bool)),
+    /// The table of the register is updated after writing a write-only register
+    WriteSingleCoil((Unit, Table, u16, bool, bool)),
+    WriteMultipleCoils((Unit, Table, u16, Vec<bool>, bool)),
+    WriteSingleRegister((Unit, Table, u16, u16, bool)),
+    WriteMultipleRegisters((Unit, Table, u16, Vec<u16>, bool)),
+    /// Address, AND mask and OR mask
+    MaskWriteRegister((Unit, Table, u16, u16, u16, bool)),
+    /// Read address, read count, write address and values
+    ReadWriteMultipleRegisters((Unit, Table, u16, u16, u16, Vec<u16>, bool)),
+    /// Query all device identification objects of the slave
+    ReadDeviceIdentification(Unit),
+    /// Query the server id (FC 17) and the diagnostic counters (FC 08) of the slave
+    ReadDiagnostics(Unit),
+}
+
+impl Command {
+    /// Write the encoded values to the register with its write function code
+    pub fn write(register: &Register, values: Vec<u16>) -> anyhow::Result<Command> {
+        let unit = register.unit();
+        let table = register.table();
+        let addr = register.address();
+        let write_only = register.access_type() == AccessType::WriteOnly;
+        match register.write_code() {
+            FunctionCode::WriteSingleCoil => Ok(Command::WriteSingleCoil((
+                unit,
+                table,
+                addr,
+                values[0] != 0,
+                write_only,
+            ))),
+            FunctionCode::WriteMultipleCoils => Ok(Command::WriteMultipleCoils((
+                unit,
+                table,
+                addr,
+                values.iter().map(|e| *e != 0).collect(),
+                write_only,
+            ))),
+            FunctionCode::WriteSingleRegister => Ok(Command::WriteSingleRegister((
+                unit, table, addr, values[0], write_only,
+            ))),
+            FunctionCode::WriteMultipleRegisters => Ok(Command::WriteMultipleRegisters((
+                unit, table, addr, values, write_only,
+            ))),
+            // Write the register and read back the result within the same transaction
+            FunctionCode::ReadWriteMultipleRegisters => Ok(Command::ReadWriteMultipleRegisters((
+                unit,
+                table,
+                addr,
+                register.length(),
+                addr,
+                values,
+                write_only,
+            ))),
+            code => Err(anyhow::anyhow!(
+                "Function code {code} is not supported to write register {addr:#06X}"
+            )),
+        }
+    }
+
+    /// Change a single bit of the register with Mask Write Register (FC 22). Bits `16` and above
+    /// continue in the following registers.
+    pub fn write_bit(register: &Register, bit: u16, set: bool) -> anyhow::Result<Command> {
+        if register.table() != Table::HoldingRegisters {
+            return Err(anyhow::anyhow!(
+                "Single bits can only be written to holding registers"
+            ));
+        }
+        if bit >= 16 * register.length() {
+            return Err(anyhow::anyhow!(
+                "Bit {bit} exceeds the register length of {} bits",
+                16 * register.length()
+            ));
+        }
+        let (and_mask, or_mask) = frame::bit_masks(bit % 16, set)?;
+        Ok(Command::MaskWriteRegister((
+            register.unit(),
+            register.table(),
+            register.address() + bit / 16,
+            and_mask,
+            or_mask,
+            register.access_type() == AccessType::WriteOnly,
+        )))
+    }
+
+    /// Index of the connection the command is meant for, `None` if it applies to all
+    pub fn connection(&self) -> Option<usize> {
+        match self {
+            Command::Connect | Command::Disconnect => None,
+            Command::WriteSingleCoil((unit, ..))
+            | Command::WriteMultipleCoils((unit, ..))
+            | Command::WriteSingleRegister((unit, ..))
+            | Command::WriteMultipleRegisters((unit, ..))
+            | Command::MaskWriteRegister((unit, ..))
+            | Command::ReadWriteMultipleRegisters((unit, ..)) => Some(unit.connection),
+            Command::ReadDeviceIdentification(unit) | Command::ReadDiagnostics(unit) => {
+                Some(unit.connection)
+            }
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -0,0 +1,687 @@
+#[cfg(feature = "f128")]
+use crate::mem::data::DataType;
+
+#[cfg(not(feature = "f128"))]
+use crate::mem::datav2::DataType;
+
+use crate::capture::{self, Entry};
+use crate::diag::{self, SubFunction};
+use crate::frame;
+use crate::ident::{self, Category};
+use crate::mem::bitfield;
+use crate::mem::file::{self, FileFormat};
+use crate::mem::memory::Table;
+use crate::rtu::RtuConfig;
+use crate::tcp::TcpConfig;
+use crate::util::str;
+use crate::AppConfig;
+
+use anyhow::anyhow;
+use clap::{Args, Subcommand};
+use itertools::Itertools;
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio_modbus::bytes::Bytes;
+use tokio_modbus::client::Context;
+use tokio_modbus::prelude::{
+    Client, ObjectId, Reader, Request, Slave, SlaveContext, SlaveId, Writer,
+};
+use tokio_modbus::FunctionCode;
+
+/// Non-interactive operations executed once instead of starting the UI
+#[derive(Subcommand, Clone, Debug)]
+pub enum OneShot {
+    /// Read the given registers or coils once and print the value
+    Read(Target),
+
+    /// Write the given value once
+    Write(WriteArgs),
+
+    /// Write the given value and read the registers back in a single transaction (FC 23)
+    ReadWrite(ReadWriteArgs),
+
+    /// Read the device identification objects (FC 43 / MEI 14) and print them
+    Identify(IdentifyArgs),
+
+    /// Read the server id (FC 17) and the diagnostic counters (FC 08) or run a single diagnostics sub-function
+    Diagnostics(DiagnosticsArgs),
+
+    /// Read a range of file records (FC 20) and write them to disk
+    ReadFile(ReadFileArgs),
+
+    /// Send the requests of a server capture (--capture) with the original timing and compare the responses
+    Replay(ReplayArgs),
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct Target {
+    /// Name of a register definition of the configuration file providing slave, function code, address, length and type.
+    #[arg(long)]
+    pub name: Option<String>,
+
+    /// The Modbus slave id.
+    #[arg(long)]
+    pub slave: Option<SlaveId>,
+
+    /// The read function code selecting the table [values: 1, 2, 3, 4]
+    #[arg(long)]
+    pub fc: Option<u8>,
+
+    /// The start address (decimal or hexadecimal with 0x prefix).
+    #[arg(long, value_parser = parse_address)]
+    pub addr: Option<u16>,
+
+    /// The number of registers or coils. Defaults to the size of the type or 1.
+    #[arg(long)]
+    pub len: Option<u16>,
+
+    /// The data type used to decode or encode the value (e.g. U16, I32, F32).
+    #[arg(long = "type")]
+    pub r#type: Option<String>,
+
+    /// Flip the bytes of each register before interpretation.
+    #[arg(long, default_value_t = false)]
+    pub reverse: bool,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct WriteArgs {
+    #[command(flatten)]
+    pub target: Target,
+
+    /// The value to write. Without type a list of raw values separated by comma (e.g. 0x1234,12 or 1,0,1 for coils).
+    pub value: String,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct ReadWriteArgs {
+    #[command(flatten)]
+    pub target: Target,
+
+    /// The start address of the write (decimal or hexadecimal with 0x prefix). Defaults to the read address.
+    #[arg(long, value_parser = parse_address)]
+    pub write_addr: Option<u16>,
+
+    /// The value to write. Without type a list of raw values separated by comma (e.g. 0x1234,12).
+    pub value: String,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct IdentifyArgs {
+    /// The Modbus slave id.
+    #[arg(long)]
+    pub slave: Option<SlaveId>,
+
+    /// The category of objects to read.
+    #[arg(long, value_enum, default_value_t = Category::Basic)]
+    pub category: Category,
+
+    /// The first object (stream access) or the single object (specific access) to read.
+    #[arg(long, value_parser = parse_object_id, default_value_t = 0)]
+    pub object: ObjectId,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct DiagnosticsArgs {
+    /// The Modbus slave id.
+    #[arg(long)]
+    pub slave: Option<SlaveId>,
+
+    /// Run only this sub-function instead of reading the server id and all counters.
+    #[arg(long, value_enum)]
+    pub sub_function: Option<SubFunction>,
+
+    /// The data field of the request (decimal or hexadecimal with 0x prefix).
+    #[arg(long, value_parser = parse_address, default_value_t = 0)]
+    pub data: u16,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct ReadFileArgs {
+    /// The Modbus slave id.
+    #[arg(long)]
+    pub slave: Option<SlaveId>,
+
+    /// The file number (1 to 65535).
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    pub file: u16,
+
+    /// The first record to read (0 to 9999).
+    #[arg(long, value_parser = parse_address, default_value_t = 0)]
+    pub record: u16,
+
+    /// The number of records to read.
+    #[arg(long)]
+    pub len: u16,
+
+    /// Format of the output file. Derived from the file extension if omitted.
+    #[arg(long, value_enum)]
+    pub format: Option<FileFormat>,
+
+    /// The file the records are written to.
+    pub output: String,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct ReplayArgs {
+    /// The capture file written by the server.
+    pub file: String,
+
+    /// Factor applied to the original timing (2.0 replays twice as fast, 0 without any delay).
+    #[arg(long, default_value_t = 1.0)]
+    pub speed: f64,
+
+    /// Send all requests to this slave instead of the captured one.
+    #[arg(long)]
+    pub slave: Option<SlaveId>,
+}
+
+/// Exit status of a one-shot operation
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Status {
+    Success = 0,
+    InvalidArguments = 1,
+    ConnectionFailed = 2,
+    Exception = 3,
+    Mismatch = 4,
+}
+
+pub fn parse_address(s: &str) -> Result<u16, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|e| format!("Invalid address {s} ({e})"))
+}
+
+fn parse_object_id(s: &str) -> Result<ObjectId, String> {
+    parse_address(s).and_then(|id| {
+        ObjectId::try_from(id).map_err(|_| format!("Invalid object id {s} (0 to 255)"))
+    })
+}
+
+pub fn parse_raw(s: &str) -> anyhow::Result<Vec<u16>> {
+    s.split([',', ' '])
+        .filter(|v| !v.is_empty())
+        .map(|v| match v {
+            "true" => Ok(1),
+            "false" => Ok(0),
+            v => parse_address(v).map_err(|e| anyhow!(e)),
+        })
+        .collect()
+}
+
+/// Fully resolved target of an operation
+pub struct Operation {
+    pub slave: SlaveId,
+    pub table: Table,
+    pub addr: u16,
+    pub len: Option<u16>,
+    pub data_type: Option<DataType>,
+    /// Single bit of the register the definition is scoped to
+    pub bit: Option<u16>,
+}
+
+impl Operation {
+    pub fn resolve(
+        target: &Target,
+        config: &AppConfig,
+        default_slave: SlaveId,
+    ) -> anyhow::Result<Self> {
+        let definition = match target.name {
+            Some(ref name) => Some(
+                config
+                    .definitions
+                    .get(name)
+                    .ok_or(anyhow!("No register definition named '{name}'"))?,
+            ),
+            None => None,
+        };
+        let data_type = match target.r#type {
+            Some(ref t) => Some(serde_json::from_value::<DataType>(serde_json::json!({
+                "type": t,
+                "reverse": target.reverse
+            }))?),
+            None => definition.map(|d| d.get_type()),
+        };
+        let code = target
+            .fc
+            .or(definition.map(|d| d.read_code()))
+            .ok_or(anyhow!("Missing function code (--fc)"))?;
+        if FunctionCode::new(code) == FunctionCode::ReadFifoQueue {
+            return Err(anyhow!(
+                "Read FIFO Queue (FC 24) is not supported by one-shot operations"
+            ));
+        }
+        let table = Table::from_function_code(FunctionCode::new(code))
+            .ok_or(anyhow!("Function code {code} does not select a table"))?;
+        Ok(Self {
+            slave: target
+                .slave
+                .or(definition.and_then(|d| *d.get_slave_id()))
+                .unwrap_or(default_slave),
+            table,
+            addr: target
+                .addr
+                .or(definition.map(|d| d.get_address()))
+                .ok_or(anyhow!("Missing address (--addr)"))?,
+            // Without explicit length the type of the value selects the number of registers
+            len: target
+                .len
+                .or(definition.map(|d| d.length()))
+                .or(data_type.as_ref().and_then(|t| t.registers())),
+            data_type,
+            bit: definition.and_then(|d| d.bit()),
+        })
+    }
+}
+
+/// Operation requested on the command line
+enum Job {
+    Read(Operation),
+    Write(Operation, String),
+    ReadWrite(Operation, Option<u16>, String),
+    Identify(IdentifyArgs),
+    Diagnostics(DiagnosticsArgs),
+    ReadFile(ReadFileArgs),
+    Replay(Vec<Entry>, ReplayArgs),
+}
+
+impl Job {
+    fn resolve(
+        oneshot: &OneShot,
+        config: &AppConfig,
+        default_slave: SlaveId,
+    ) -> anyhow::Result<Self> {
+        Ok(match oneshot {
+            OneShot::Read(target) => Job::Read(Operation::resolve(target, config, default_slave)?),
+            OneShot::Write(args) => Job::Write(
+                Operation::resolve(&args.target, config, default_slave)?,
+                args.value.clone(),
+            ),
+            OneShot::ReadWrite(args) => Job::ReadWrite(
+                Operation::resolve(&args.target, config, default_slave)?,
+                args.write_addr,
+                args.value.clone(),
+            ),
+            OneShot::Identify(args) => Job::Identify(args.clone()),
+            OneShot::Diagnostics(args) => Job::Diagnostics(args.clone()),
+            OneShot::ReadFile(args) => {
+                if args.record as usize + args.len as usize > file::MAX_RECORDS {
+                    return Err(anyhow!(
+                        "Records {} to {} exceed the file (0 to 9999)",
+                        args.record,
+                        args.record as usize + args.len as usize - 1
+                    ));
+                }
+                Job::ReadFile(args.clone())
+            }
+            OneShot::Replay(args) => Job::Replay(
+                capture::load(&args.file)
+                    .map_err(|e| anyhow!("Failed to load capture {} ({e})", args.file))?,
+                args.clone(),
+            ),
+        })
+    }
+
+    /// Slave of the connection, replays select the slave per request
+    fn slave(&self, default_slave: SlaveId) -> SlaveId {
+        match self {
+            Job::Read(op) | Job::Write(op, _) | Job::ReadWrite(op, ..) => op.slave,
+            Job::Identify(args) => args.slave.unwrap_or(default_slave),
+            Job::Diagnostics(args) => args.slave.unwrap_or(default_slave),
+            Job::ReadFile(args) => args.slave.unwrap_or(default_slave),
+            Job::Replay(_, args) => args.slave.unwrap_or(default_slave),
+        }
+    }
+}
+
+/// Flatten the nested results of a client operation into the exit status
+pub async fn call<T>(
+    timeout: Duration,
+    fut: impl Future<Output = tokio_modbus::Result<T>>,
+) -> Result<T, (Status, String)> {
+    match tokio::time::timeout(timeout, fut).await {
+        Ok(Ok(Ok(v))) => Ok(v),
+        Ok(Ok(Err(e))) => Err((Status::Exception, format!("Modbus exception: {e}"))),
+        Ok(Err(e)) => Err((Status::ConnectionFailed, format!("Modbus error: {e}"))),
+        Err(_) => Err((Status::ConnectionFailed, str!("Timeout"))),
+    }
+}
+
+async fn read(
+    ctx: &mut Context,
+    op: &Operation,
+    timeout: Duration,
+) -> Result<String, (Status, String)> {
+    let len = op.len.unwrap_or(1);
+    let values: Vec<u16> = match op.table {
+        Table::Coils => call(timeout, ctx.read_coils(op.addr, len))
+            .await?
+            .into_iter()
+            .map(u16::from)
+            .collect(),
+        Table::DiscreteInputs => call(timeout, ctx.read_discrete_inputs(op.addr, len))
+            .await?
+            .into_iter()
+            .map(u16::from)
+            .collect(),
+        Table::InputRegisters => call(timeout, ctx.read_input_registers(op.addr, len)).await?,
+        Table::HoldingRegisters => call(timeout, ctx.read_holding_registers(op.addr, len)).await?,
+    };
+    decode(op, &values)
+}
+
+/// Decode the values with the type of the operation or print them raw
+pub fn decode(op: &Operation, values: &[u16]) -> Result<String, (Status, String)> {
+    let values = &match op.bit {
+        Some(bit) => vec![bitfield::bit(values, bit).unwrap_or_default() as u16],
+        None => values.to_vec(),
+    };
+    match op.data_type {
+        Some(ref t) => t.as_plain_str(values).map_err(|e| {
+            (
+                Status::InvalidArguments,
+                format!("Failed to decode value ({e})"),
+            )
+        }),
+        None => Ok(values
+            .iter()
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>()
+            .join(" ")),
+    }
+}
+
+/// Encode the value with the type of the operation or parse it as list of raw values
+pub fn encode(op: &Operation, value: &str) -> Result<Vec<u16>, (Status, String)> {
+    match (op.table, op.data_type.as_ref()) {
+        (Table::HoldingRegisters, Some(t)) => t.encode(value),
+        _ => parse_raw(value),
+    }
+    .map_err(|e| {
+        (
+            Status::InvalidArguments,
+            format!("Invalid value {value} ({e})"),
+        )
+    })
+}
+
+async fn write(
+    ctx: &mut Context,
+    op: &Operation,
+    value: &str,
+    timeout: Duration,
+) -> Result<(), (Status, String)> {
+    let values = encode(op, value)?;
+    if values.is_empty() || op.len.is_some_and(|len| len as usize != values.len()) {
+        return Err((
+            Status::InvalidArguments,
+            format!(
+                "Value {value} does not match the register length ({})",
+                op.len.unwrap_or_default()
+            ),
+        ));
+    }
+    match op.table {
+        Table::Coils if values.len() == 1 => {
+            call(timeout, ctx.write_single_coil(op.addr, values[0] != 0)).await
+        }
+        Table::Coils => {
+            let coils: Vec<bool> = values.iter().map(|v| *v != 0).collect();
+            call(timeout, ctx.write_multiple_coils(op.addr, &coils)).await
+        }
+        Table::HoldingRegisters if op.bit.is_some() => {
+            let set = bitfield::flag(&values).map_err(|e| {
+                (
+                    Status::InvalidArguments,
+                    format!("Invalid value {value} ({e})"),
+                )
+            })?;
+            let (and_mask, or_mask) = frame::bit_masks(op.bit.unwrap_or_default(), set)
+                .map_err(|e| (Status::InvalidArguments, format!("{e}")))?;
+            call(
+                timeout,
+                ctx.masked_write_register(op.addr, and_mask, or_mask),
+            )
+            .await
+        }
+        Table::HoldingRegisters if values.len() == 1 => {
+            call(timeout, ctx.write_single_register(op.addr, values[0])).await
+        }
+        Table::HoldingRegisters => {
+            call(timeout, ctx.write_multiple_registers(op.addr, &values)).await
+        }
+        table => Err((
+            Status::InvalidArguments,
+            format!("Table {table} is not writable"),
+        )),
+    }
+}
+
+async fn read_write(
+    ctx: &mut Context,
+    op: &Operation,
+    write_addr: Option<u16>,
+    value: &str,
+    timeout: Duration,
+) -> Result<String, (Status, String)> {
+    if op.table != Table::HoldingRegisters {
+        return Err((
+            Status::InvalidArguments,
+            format!("Table {} does not support FC 23", op.table),
+        ));
+    }
+    let values = encode(op, value)?;
+    if values.is_empty() {
+        return Err((Status::InvalidArguments, format!("Invalid value {value}")));
+    }
+    let len = op.len.unwrap_or(values.len() as u16);
+    let write_addr = write_addr.unwrap_or(op.addr);
+    let values = call(
+        timeout,
+        ctx.read_write_multiple_registers(op.addr, len, write_addr, &values),
+    )
+    .await?;
+    decode(op, &values)
+}
+
+async fn identify(
+    ctx: &mut Context,
+    args: &IdentifyArgs,
+    timeout: Duration,
+) -> Result<String, (Status, String)> {
+    let objects = call(
+        timeout,
+        ident::read(ctx, args.category.into(), args.object, timeout),
+    )
+    .await?;
+    Ok(objects.iter().map(ident::describe).join("\n"))
+}
+
+async fn diagnostics(
+    ctx: &mut Context,
+    args: &DiagnosticsArgs,
+    timeout: Duration,
+) -> Result<String, (Status, String)> {
+    match args.sub_function {
+        Some(sub) => {
+            let value = call(timeout, diag::query(ctx, sub, args.data, timeout)).await?;
+            Ok(diag::describe(sub, value))
+        }
+        None => Ok(call(timeout, diag::report(ctx, timeout)).await?.join("\n")),
+    }
+}
+
+async fn read_file(
+    ctx: &mut Context,
+    args: &ReadFileArgs,
+    timeout: Duration,
+) -> Result<String, (Status, String)> {
+    // Every request of the range has the full timeout
+    let requests = (args.len as usize)
+        .div_ceil(file::MAX_RECORDS_PER_REQUEST)
+        .max(1);
+    let records = call(
+        timeout * requests as u32,
+        file::read(ctx, args.file, args.record, args.len, timeout),
+    )
+    .await?;
+    let format = args
+        .format
+        .unwrap_or_else(|| FileFormat::from_path(&args.output));
+    file::save(&args.output, format, &records).map_err(|e| {
+        (
+            Status::InvalidArguments,
+            format!("Failed to write {} ({e})", args.output),
+        )
+    })?;
+    Ok(format!(
+        "{} records written to {}",
+        records.len(),
+        args.output
+    ))
+}
+
+async fn replay(
+    ctx: &mut Context,
+    entries: &[Entry],
+    args: &ReplayArgs,
+    timeout: Duration,
+) -> Result<(), (Status, String)> {
+    let start = Instant::now();
+    let first = entries.first().map(|e| e.elapsed_ms).unwrap_or_default();
+    let mut mismatches = 0;
+    for entry in entries {
+        if args.speed > 0.0 {
+            let offset = entry.elapsed_ms.saturating_sub(first) as f64 / args.speed;
+            tokio::time::sleep_until(start + Duration::from_millis(offset as u64)).await;
+        }
+        let request = entry
+            .request_pdu()
+            .and_then(|pdu| Ok(Request::try_from(Bytes::from(pdu))?))
+            .map_err(|e| {
+                (
+                    Status::InvalidArguments,
+                    format!("Invalid request {} ({e})", entry.request),
+                )
+            })?;
+        let slave = args.slave.unwrap_or(entry.slave);
+        ctx.set_slave(Slave(slave));
+        let response = match tokio::time::timeout(timeout, ctx.call(request)).await {
+            Ok(Ok(result)) => Some(frame::to_hex(&frame::encode_response(
+                entry.function,
+                &result,
+            ))),
+            Ok(Err(e)) => return Err((Status::ConnectionFailed, format!("Modbus error: {e}"))),
+            Err(_) => None,
+        };
+        let status = if response == entry.response {
+            "OK"
+        } else {
+            mismatches += 1;
+            "MISMATCH"
+        };
+        let or_none = |r: &Option<String>| r.clone().unwrap_or(str!("-"));
+        println!(
+            "{} Slave: {}, Request: {}, Expected: {}, Received: {}",
+            status,
+            slave,
+            entry.request,
+            or_none(&entry.response),
+            or_none(&response)
+        );
+    }
+    match mismatches {
+        0 => Ok(()),
+        n => Err((
+            Status::Mismatch,
+            format!("{n} of {} responses differ from the capture", entries.len()),
+        )),
+    }
+}
+
+async fn execute(mut ctx: Context, job: &Job, timeout: Duration) -> Result<(), (Status, String)> {
+    match job {
+        Job::Read(op) => println!("{}", read(&mut ctx, op, timeout).await?),
+        Job::Write(op, value) => write(&mut ctx, op, value, timeout).await?,
+        Job::ReadWrite(op, write_addr, value) => println!(
+            "{}",
+            read_write(&mut ctx, op, *write_addr, value, timeout).await?
+        ),
+        Job::Identify(args) => println!("{}", identify(&mut ctx, args, timeout).await?),
+        Job::Diagnostics(args) => println!("{}", diagnostics(&mut ctx, args, timeout).await?),
+        Job::ReadFile(args) => println!("{}", read_file(&mut ctx, args, timeout).await?),
+        Job::Replay(entries, args) => replay(&mut ctx, entries, args, timeout).await?,
+    }
+    let _ = ctx.disconnect().await;
+    Ok(())
+}
+
+fn report(result: Result<(), (Status, String)>) -> Status {
+    match result {
+        Ok(()) => Status::Success,
+        Err((status, msg)) => {
+            eprintln!("{msg}");
+            status
+        }
+    }
+}
+
+/// Execute the operation on a Modbus TCP server (or RTU over TCP, UDP)
+pub async fn run_tcp(config: &TcpConfig, app_config: &AppConfig, oneshot: &OneShot) -> Status {
+    let timeout = Duration::from_millis(app_config.timeout_ms.unwrap_or(3000));
+    let job = match Job::resolve(oneshot, app_config, 0) {
+        Ok(job) => job,
+        Err(e) => return report(Err((Status::InvalidArguments, e.to_string()))),
+    };
+    let addr = match config.addr() {
+        Ok(addr) => addr,
+        Err(e) => return report(Err((Status::InvalidArguments, e.to_string()))),
+    };
+    // The retries of UDP are part of a single operation
+    let operation_timeout = Duration::from_millis(
+        config
+            .transport
+            .operation_timeout_ms(timeout.as_millis() as u64),
+    );
+    let connect = config.connect(Slave(job.slave(0)), timeout);
+    let result = match tokio::time::timeout(timeout, connect).await {
+        Ok(Ok(ctx)) => execute(ctx, &job, operation_timeout).await,
+        Ok(Err(e)) => Err((
+            Status::ConnectionFailed,
+            format!("Failed to connect to {addr} ({e})"),
+        )),
+        Err(_) => Err((
+            Status::ConnectionFailed,
+            format!("Failed to connect to {addr} (Timeout)"),
+        )),
+    };
+    report(result)
+}
+
+/// Execute the operation on a Modbus RTU server
+pub async fn run_rtu(config: &RtuConfig, app_config: &AppConfig, oneshot: &OneShot) -> Status {
+    let timeout = Duration::from_millis(app_config.timeout_ms.unwrap_or(3000));
+    let job = match Job::resolve(oneshot, app_config, config.client_id) {
+        Ok(job) => job,
+        Err(e) => return report(Err((Status::InvalidArguments, e.to_string()))),
+    };
+    let result = match tokio_serial::SerialStream::open(&config.serial.create_serial_builder()) {
+        Ok(port) => {
+            let ctx = config
+                .serial
+                .attach_slave(port, Slave(job.slave(config.client_id)));
+            execute(ctx, &job, timeout).await
+        }
+        Err(e) => Err((
+            Status::ConnectionFailed,
+            format!(
+                "Failed to open SerialStream {} ({e})",
+                config.serial.as_str()
+            ),
+        )),
+    };
+    report(result)
+}
@@ -0,0 +1,126 @@
+use crate::mem::memory::Memory;
+use crate::mem::register::Handler;
+use crate::AppConfig;
+
+use chrono::Local;
+use clap::ValueEnum;
+use itertools::Itertools;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::{Arc, Mutex};
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum RecordFormat {
+    /// Comma separated values with a header row
+    Csv,
+    /// One JSON object per line
+    Jsonl,
+}
+
+impl RecordFormat {
+    /// Derive the format from the file extension (defaults to JSON Lines)
+    pub fn from_path(path: &str) -> Self {
+        if path.to_lowercase().ends_with(".csv") {
+            RecordFormat::Csv
+        } else {
+            RecordFormat::Jsonl
+        }
+    }
+}
+
+/// Appends the decoded values of all registers to a file once per poll cycle. Clients of several
+/// connections share the recorder, a row is appended once all of them completed a poll cycle.
+pub struct Recorder {
+    handler: Handler,
+    format: RecordFormat,
+    file: BufWriter<File>,
+    write_header: bool,
+    connections: usize,
+    completed: HashSet<usize>,
+}
+
+fn escape_csv(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+impl Recorder {
+    pub fn new(
+        path: &str,
+        format: RecordFormat,
+        config: Arc<Mutex<AppConfig>>,
+        memory: Arc<Mutex<Memory>>,
+    ) -> anyhow::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let write_header = file.metadata()?.len() == 0;
+        Ok(Self {
+            handler: Handler::new(config, memory),
+            format,
+            file: BufWriter::new(file),
+            write_header,
+            connections: 1,
+            completed: HashSet::new(),
+        })
+    }
+
+    /// Number of connections which complete a poll cycle before a row is appended
+    pub fn with_connections(mut self, connections: usize) -> Self {
+        self.connections = connections.max(1);
+        self
+    }
+
+    /// Mark the poll cycle of the connection with the given index as completed. The row is
+    /// written once every connection completed a cycle since the last row.
+    pub fn complete(&mut self, connection: usize) -> anyhow::Result<()> {
+        self.completed.insert(connection);
+        if self.completed.len() < self.connections {
+            return Ok(());
+        }
+        self.completed.clear();
+        self.record()
+    }
+
+    /// Write a single row with the current values of all registers
+    pub fn record(&mut self) -> anyhow::Result<()> {
+        let timestamp = Local::now().to_rfc3339();
+        let values = self.handler.values();
+        let registers = values
+            .iter()
+            .sorted_by_key(|(_, r)| r.get_index())
+            .collect::<Vec<_>>();
+        match self.format {
+            RecordFormat::Csv => {
+                if self.write_header {
+                    let header = registers.iter().map(|(name, _)| escape_csv(name)).join(",");
+                    writeln!(self.file, "timestamp,{}", header)?;
+                    self.write_header = false;
+                }
+                let row = registers
+                    .iter()
+                    .map(|(_, r)| escape_csv(&r.value().1))
+                    .join(",");
+                writeln!(self.file, "{},{}", timestamp, row)?;
+            }
+            RecordFormat::Jsonl => {
+                let values: serde_json::Map<String, serde_json::Value> = registers
+                    .iter()
+                    .map(|(name, r)| ((*name).clone(), r.value().1.clone().into()))
+                    .collect();
+                writeln!(
+                    self.file,
+                    "{}",
+                    serde_json::json!({ "timestamp": timestamp, "values": values })
+                )?;
+            }
+        }
+        self.file.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,68 @@
+use crate::frame;
+
+use async_trait::async_trait;
+use std::fmt::Debug;
+use std::io::{Error, ErrorKind};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_modbus::client::{Client as ModbusClient, Context};
+use tokio_modbus::prelude::{Request, Response, SlaveContext};
+use tokio_modbus::Slave;
+
+/// Modbus ASCII client on top of any byte stream (e.g. a serial port)
+#[derive(Debug)]
+struct Client<T> {
+    transport: T,
+    slave: Slave,
+    buf: Vec<u8>,
+}
+
+/// Attach a Modbus ASCII client context to the transport
+pub fn attach_slave<T>(transport: T, slave: Slave) -> Context
+where
+    T: AsyncRead + AsyncWrite + Debug + Unpin + Send + 'static,
+{
+    let client: Box<dyn ModbusClient> = Box::new(Client {
+        transport,
+        slave,
+        buf: Vec::with_capacity(frame::ascii::MAX_ADU_SIZE),
+    });
+    Context::from(client)
+}
+
+impl<T> SlaveContext for Client<T> {
+    fn set_slave(&mut self, slave: Slave) {
+        self.slave = slave;
+    }
+}
+
+#[async_trait]
+impl<T> ModbusClient for Client<T>
+where
+    T: AsyncRead + AsyncWrite + Debug + Unpin + Send + 'static,
+{
+    async fn call(&mut self, request: Request<'_>) -> tokio_modbus::Result<Response> {
+        let function = request.function_code().value();
+        let adu = frame::ascii::encode(self.slave.into(), &frame::encode_request(&request), false);
+        // Responses to previous (timed out) requests are outdated
+        self.buf.clear();
+        self.transport.write_all(&adu).await?;
+
+        let mut chunk = [0u8; frame::ascii::MAX_ADU_SIZE];
+        let pdu = loop {
+            match self.transport.read(&mut chunk).await? {
+                0 => return Err(Error::from(ErrorKind::UnexpectedEof).into()),
+                n => self.buf.extend_from_slice(&chunk[..n]),
+            }
+            if let Some((slave, pdu)) = frame::ascii::decode(&mut self.buf) {
+                if slave == self.slave.0 {
+                    break pdu;
+                }
+            }
+        };
+        frame::decode_response(function, pdu)
+    }
+
+    async fn disconnect(&mut self) -> std::io::Result<()> {
+        self.transport.shutdown().await
+    }
+}
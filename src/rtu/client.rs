@@ -1,6 +1,12 @@
-use crate::mem::memory::{Memory, Range};
+use crate::connection;
+use crate::diag;
+use crate::frame;
+use crate::ident;
+use crate::mem::fifo;
+use crate::mem::memory::{Memory, Range, Table, Unit};
 use crate::mem::register::AccessType;
 use crate::msg::LogMsg;
+use crate::record::Recorder;
 use crate::rtu::RtuConfig;
 use crate::util::{str, Expect};
 use crate::{AppConfig, Command, Status};
@@ -10,17 +16,22 @@ use std::sync::{Arc, Mutex};
 use std::time::{Duration, SystemTime};
 use tokio::sync::mpsc::{Receiver, Sender};
 use tokio_modbus::prelude::SlaveId;
-use tokio_modbus::prelude::{rtu, Client as ModbusClient, Reader, Slave, SlaveContext, Writer};
+use tokio_modbus::prelude::{
+    Client as ModbusClient, ReadCode, Reader, Slave, SlaveContext, Writer,
+};
 use tokio_modbus::FunctionCode;
-use tokio_serial::{DataBits, Parity, SerialPortBuilder, SerialStream, StopBits};
+use tokio_serial::SerialStream;
 
 pub struct Client {
     config: RtuConfig,
+    app_config: Arc<Mutex<AppConfig>>,
     memory: Arc<Mutex<Memory>>,
+    connection: usize,
     operations: Vec<(SlaveId, FunctionCode, Range<u16>)>,
     status_sender: Sender<Status>,
     cmd_receiver: Receiver<Command>,
     log_sender: Sender<LogMsg>,
+    recorder: Option<Arc<Mutex<Recorder>>>,
 }
 
 impl Client {
@@ -31,24 +42,50 @@ impl Client {
         status_sender: Sender<Status>,
         cmd_receiver: Receiver<Command>,
         log_sender: Sender<LogMsg>,
+        recorder: Option<Arc<Mutex<Recorder>>>,
     ) -> Self {
-        let operations = Self::init(app_config);
         Self {
             config: rtu_config,
+            app_config,
             memory,
-            operations,
+            connection: 0,
+            operations: Vec::new(),
             status_sender,
             cmd_receiver,
             log_sender,
+            recorder,
         }
     }
 
-    fn init(app_config: Arc<Mutex<AppConfig>>) -> Vec<(SlaveId, FunctionCode, Range<u16>)> {
+    /// Status message of the connection
+    fn status(&self, msg: String) -> Status {
+        let config = self
+            .app_config
+            .lock()
+            .expect("Unable to lock configuration");
+        connection::status(&config, self.connection, msg)
+    }
+
+    /// Only poll the definitions of the named connection with the given index
+    pub fn with_connection(mut self, connection: usize) -> Self {
+        self.connection = connection;
+        self
+    }
+
+    /// Plan the read operations of all definitions of the connection
+    fn init(
+        app_config: Arc<Mutex<AppConfig>>,
+        connection: usize,
+    ) -> Vec<(SlaveId, FunctionCode, Range<u16>)> {
         let config = app_config.lock().expect("Unable to lock configuration");
         let sorted_defs = config
             .definitions
             .iter()
-            .filter(|d| !d.1.is_virtual() && d.1.access_type() != AccessType::WriteOnly)
+            .filter(|d| {
+                d.1.unit().connection == connection
+                    && !d.1.is_virtual()
+                    && d.1.access_type() != AccessType::WriteOnly
+            })
             .sorted_by(|a, b| {
                 a.1.get_slave_id()
                     .unwrap_or(1)
@@ -62,7 +99,7 @@ impl Client {
             .collect::<Vec<_>>();
 
         let is_allowed = |slave: SlaveId, fc: u8, addr: u16, end: usize| {
-            for mem in config.contiguous_memory.iter() {
+            for mem in config.contiguous_memory(connection).iter() {
                 if mem.slave_id.unwrap_or(1) == slave
                     && mem.read_code == fc
                     && addr as usize >= mem.range.start()
@@ -92,6 +129,7 @@ impl Client {
                     Some((slave, fc, range)) => {
                         if slave != def.get_slave_id().unwrap_or(1)
                             || fc != def.read_code()
+                            || def.is_fifo()
                             || def.get_range().start() + def.get_range().length() - range.start()
                                 > 126
                             || !is_allowed(
@@ -132,105 +170,59 @@ impl Client {
             .collect()
     }
 
-    async fn create_serial_builder(&self) -> SerialPortBuilder {
-        let mut builder = tokio_serial::new(self.config.path.clone(), self.config.baud_rate);
-        let data_bits = self.config.data_bits.unwrap_or(8);
-        let stop_bits = self.config.stop_bits.unwrap_or(1);
-        let parity = self
-            .config
-            .parity
-            .as_ref()
-            .unwrap_or(&"NONE".to_string())
-            .to_uppercase();
-        let flow_control = self
-            .config
-            .flow_control
-            .as_ref()
-            .unwrap_or(&crate::rtu::FlowControl::None);
-
-        builder = builder.data_bits(match data_bits {
-            5 => DataBits::Five,
-            6 => DataBits::Six,
-            7 => DataBits::Seven,
-            8 => DataBits::Eight,
-            _ => panic!("Invalid data bits specified."),
-        });
-
-        builder = builder.stop_bits(match stop_bits {
-            1 => StopBits::One,
-            2 => StopBits::Two,
-            _ => panic!("Invalid stop bits specified"),
-        });
-
-        if parity == "ODD" {
-            builder = builder.parity(Parity::Odd);
-        } else if parity == "EVEN" {
-            builder = builder.parity(Parity::Even);
-        } else if parity == "NONE" {
-            builder = builder.parity(Parity::None);
-        } else {
-            panic!("Invalid parity specified");
+    /// Update the memory with the values written to a write-only register, which is never
+    /// polled, or read back after a write. Failures are logged.
+    async fn mirror(&self, unit: Unit, table: Table, addr: u16, values: &[u16]) {
+        let result = self
+            .memory
+            .lock()
+            .expect("Unable to lock memory")
+            .write(
+                unit,
+                table,
+                Range::new(addr, addr + values.len() as u16),
+                values,
+            )
+            .map(|_| ());
+        if let Err(e) = result {
+            let _ = self
+                .log_sender
+                .send(LogMsg::err(&format!("Failed to write to memory ({e})")))
+                .await;
         }
-
-        builder = builder.flow_control(match flow_control {
-            crate::rtu::FlowControl::None => tokio_serial::FlowControl::None,
-            crate::rtu::FlowControl::Software => tokio_serial::FlowControl::Software,
-            crate::rtu::FlowControl::Hardware => tokio_serial::FlowControl::Hardware,
-        });
-
-        builder
-    }
-
-    fn config_as_str(&self) -> String {
-        let path = &self.config.path;
-        let baud_rate = self.config.baud_rate;
-        let data_bits = self.config.data_bits.unwrap_or(8);
-        let stop_bits = self.config.stop_bits.unwrap_or(1);
-        let parity = self
-            .config
-            .parity
-            .as_ref()
-            .unwrap_or(&"NONE".to_string())
-            .to_uppercase();
-        let flow_control = self
-            .config
-            .flow_control
-            .as_ref()
-            .unwrap_or(&crate::rtu::FlowControl::None);
-        format!(
-            "{}, baud rate: {}, data bits: {}, parity: {}, stop bits: {}, flow control: {}",
-            path, baud_rate, data_bits, parity, stop_bits, flow_control
-        )
     }
 
     pub async fn run(&mut self, delay_after_connect: u64, interval_ms: u64, timeout_ms: u64) {
-        let builder = self.create_serial_builder().await;
+        self.operations = Self::init(self.app_config.clone(), self.connection);
+        let builder = self.config.serial.create_serial_builder();
         let port =
             SerialStream::open(&builder).panic(|e| format!("Failed to open SerialStream ({e})"));
         let slave = Slave(self.config.client_id);
-        let mut connection = Some(rtu::attach_slave(port, slave));
+        let mut connection = Some(self.config.serial.attach_slave(port, slave));
         if connection.is_some() {
             let _ = self
                 .status_sender
-                .send(Status::String(str!("Modbus TCP connected.")))
+                .send(self.status(format!("{} connected.", self.config.serial.protocol())))
                 .await;
             let _ = self
                 .log_sender
                 .send(LogMsg::ok(&format!(
-                    "Modbus RTU connected to {}",
-                    self.config_as_str()
+                    "{} connected to {}",
+                    self.config.serial.protocol(),
+                    self.config.serial.as_str()
                 )))
                 .await;
         } else {
             let _ = self
                 .status_sender
-                .send(Status::String(str!("Modbus TCP disconnected.")))
+                .send(self.status(format!("{} disconnected.", self.config.serial.protocol())))
                 .await;
             let _ = self
                 .log_sender
                 .send(LogMsg::err(&format!(
-                    "Modbus TCP failed to connect to {}",
-                    self.config_as_str()
+                    "{} failed to connect to {}",
+                    self.config.serial.protocol(),
+                    self.config.serial.as_str()
                 )))
                 .await;
         };
@@ -322,6 +314,15 @@ impl Client {
                             )
                             .await
                         }
+                        FunctionCode::ReadFifoQueue => {
+                            context.set_slave(Slave(*slave));
+                            Ok(fifo::read(
+                                context,
+                                op.start() as u16,
+                                Duration::from_millis(timeout_ms),
+                            )
+                            .await)
+                        }
                         _ => panic!("Invalid function code in operation."),
                     };
                     if let Ok(Ok(Ok(vec))) = modbus_result {
@@ -332,17 +333,42 @@ impl Client {
                                 end = op.end()
                             )))
                             .await;
-                        let mut memory = self.memory.lock().expect("Unable to lock memory");
-                        memory
-                            .write(*slave, Range::new(op.start(), op.start() + vec.len()), &vec)
-                            .panic(|e| format!("Failed to write to memory ({})", e));
-                        drop(memory);
+                        {
+                            let mut memory = self.memory.lock().expect("Unable to lock memory");
+                            let unit = Unit::new(self.connection, *slave);
+                            if *fc == FunctionCode::ReadFifoQueue {
+                                memory.record_fifo(unit, op.start() as u16, &vec);
+                            } else {
+                                let table = Table::from_function_code(*fc)
+                                    .expect("Invalid function code in operation.");
+                                memory
+                                    .write(
+                                        unit,
+                                        table,
+                                        Range::new(op.start(), op.start() + vec.len()),
+                                        &vec,
+                                    )
+                                    .panic(|e| format!("Failed to write to memory ({})", e));
+                            }
+                        }
                         op_idx = if op_idx + 1 == self.operations.len() {
                             0
                         } else {
                             op_idx + 1
                         };
                         retries = 0;
+                        if op_idx == 0 {
+                            if let Some(Err(e)) = self.recorder.as_ref().map(|r| {
+                                r.lock()
+                                    .expect("Unable to lock recorder")
+                                    .complete(self.connection)
+                            }) {
+                                let _ = self
+                                    .log_sender
+                                    .send(LogMsg::err(&format!("Failed to record values ({e})")))
+                                    .await;
+                            }
+                        }
                     } else {
                         retries += 1;
                         if retries > 3 {
@@ -352,6 +378,20 @@ impl Client {
                                 op_idx + 1
                             };
                             retries = 0;
+                            if op_idx == 0 {
+                                if let Some(Err(e)) = self.recorder.as_ref().map(|r| {
+                                    r.lock()
+                                        .expect("Unable to lock recorder")
+                                        .complete(self.connection)
+                                }) {
+                                    let _ = self
+                                        .log_sender
+                                        .send(LogMsg::err(&format!(
+                                            "Failed to record values ({e})"
+                                        )))
+                                        .await;
+                                }
+                            }
                         }
 
                         let err = match modbus_result {
@@ -369,10 +409,13 @@ impl Client {
                                 err = err
                             )))
                             .await;
-                        let _ = self
-                            .status_sender
-                            .send(Status::String(str!("Modbus RTU disconnected.")))
-                            .await;
+                        let _ =
+                            self.status_sender
+                                .send(self.status(format!(
+                                    "{} disconnected.",
+                                    self.config.serial.protocol()
+                                )))
+                                .await;
                         reconnect = true;
                     }
                 }
@@ -383,13 +426,17 @@ impl Client {
                         Command::Disconnect => {
                             let _ = self
                                 .status_sender
-                                .send(Status::String(str!("Modbus RTU disconnected.")))
+                                .send(self.status(format!(
+                                    "{} disconnected.",
+                                    self.config.serial.protocol()
+                                )))
                                 .await;
                             let _ = self
                                 .log_sender
                                 .send(LogMsg::ok(&format!(
-                                    "Modbus RTU disconnected from {}",
-                                    self.config_as_str()
+                                    "{} disconnected from {}",
+                                    self.config.serial.protocol(),
+                                    self.config.serial.as_str()
                                 )))
                                 .await;
                             disconnect = true;
@@ -397,10 +444,10 @@ impl Client {
                         Command::Connect => {
                             reconnect = true;
                         }
-                        Command::WriteSingleCoil((slave, addr, coil, write_only)) => {
-                            context.set_slave(Slave(slave));
+                        Command::WriteSingleCoil((slave, table, addr, coil, write_only)) => {
+                            context.set_slave(Slave(slave.slave));
                             if let Err(e) = tokio::time::timeout(
-                                std::time::Duration::from_millis(timeout_ms),
+                                Duration::from_millis(timeout_ms),
                                 context.write_single_coil(addr, coil),
                             )
                             .await
@@ -420,22 +467,15 @@ impl Client {
                                     )))
                                     .await;
                                 if write_only {
-                                    let mut memory =
-                                        self.memory.lock().expect("Unable to lock memory");
-                                    memory
-                                        .write(
-                                            slave,
-                                            Range::new(addr, addr + 1),
-                                            &vec![if coil { 1 } else { 0 }],
-                                        )
-                                        .panic(|e| format!("Failed to write to memory ({})", e));
+                                    self.mirror(slave, table, addr, &[if coil { 1 } else { 0 }])
+                                        .await;
                                 }
                             }
                         }
-                        Command::WriteMultipleCoils((slave, addr, coils, write_only)) => {
-                            context.set_slave(Slave(slave));
+                        Command::WriteMultipleCoils((slave, table, addr, coils, write_only)) => {
+                            context.set_slave(Slave(slave.slave));
                             if let Err(e) = tokio::time::timeout(
-                                std::time::Duration::from_millis(timeout_ms),
+                                Duration::from_millis(timeout_ms),
                                 context.write_multiple_coils(addr, &coils),
                             )
                             .await
@@ -455,25 +495,18 @@ impl Client {
                                     )))
                                     .await;
                                 if write_only {
-                                    let mut memory =
-                                        self.memory.lock().expect("Unable to lock memory");
-                                    memory
-                                        .write(
-                                            slave,
-                                            Range::new(addr, addr + coils.len() as u16),
-                                            &coils
-                                                .iter()
-                                                .map(|c| if *c { 1 } else { 0 })
-                                                .collect::<Vec<u16>>(),
-                                        )
-                                        .panic(|e| format!("Failed to write to memory ({})", e));
+                                    let values = coils
+                                        .iter()
+                                        .map(|c| if *c { 1 } else { 0 })
+                                        .collect::<Vec<u16>>();
+                                    self.mirror(slave, table, addr, &values).await;
                                 }
                             }
                         }
-                        Command::WriteSingleRegister((slave, addr, value, write_only)) => {
-                            context.set_slave(Slave(slave));
+                        Command::WriteSingleRegister((slave, table, addr, value, write_only)) => {
+                            context.set_slave(Slave(slave.slave));
                             if let Err(e) = tokio::time::timeout(
-                                std::time::Duration::from_millis(timeout_ms),
+                                Duration::from_millis(timeout_ms),
                                 context.write_single_register(addr, value),
                             )
                             .await
@@ -493,18 +526,14 @@ impl Client {
                                     )))
                                     .await;
                                 if write_only {
-                                    let mut memory =
-                                        self.memory.lock().expect("Unable to lock memory");
-                                    memory
-                                        .write(slave, Range::new(addr, addr + 1), &vec![value])
-                                        .panic(|e| format!("Failed to write to memory ({})", e));
+                                    self.mirror(slave, table, addr, &[value]).await;
                                 }
                             }
                         }
-                        Command::WriteMultipleRegisters((slave, addr, vec, write_only)) => {
-                            context.set_slave(Slave(slave));
+                        Command::WriteMultipleRegisters((slave, table, addr, vec, write_only)) => {
+                            context.set_slave(Slave(slave.slave));
                             if let Err(e) = tokio::time::timeout(
-                                std::time::Duration::from_millis(timeout_ms),
+                                Duration::from_millis(timeout_ms),
                                 context.write_multiple_registers(addr, &vec),
                             )
                             .await
@@ -524,18 +553,193 @@ impl Client {
                                     )))
                                     .await;
                                 if write_only {
-                                    let mut memory =
-                                        self.memory.lock().expect("Unable to lock memory");
-                                    memory
-                                        .write(
+                                    self.mirror(slave, table, addr, &vec).await;
+                                }
+                            }
+                        }
+                        Command::MaskWriteRegister((
+                            slave,
+                            table,
+                            addr,
+                            and_mask,
+                            or_mask,
+                            write_only,
+                        )) => {
+                            context.set_slave(Slave(slave.slave));
+                            let failure = match tokio::time::timeout(
+                                Duration::from_millis(timeout_ms),
+                                context.masked_write_register(addr, and_mask, or_mask),
+                            )
+                            .await
+                            {
+                                Ok(Ok(Ok(()))) => {
+                                    let _ = self
+                                        .log_sender
+                                        .send(LogMsg::ok(&format!(
+                                            "Successfully masked address {addr} with AND {and_mask:#06X} and OR {or_mask:#06X}."
+                                        )))
+                                        .await;
+                                    if write_only {
+                                        let value = self
+                                            .memory
+                                            .lock()
+                                            .expect("Unable to lock memory")
+                                            .read(slave, table, &Range::new(addr, addr + 1))
+                                            .map(|v| *v[0])
+                                            .unwrap_or_default();
+                                        self.mirror(
                                             slave,
-                                            Range::new(addr, addr + vec.len() as u16),
-                                            &vec,
+                                            table,
+                                            addr,
+                                            &[frame::mask(value, and_mask, or_mask)],
                                         )
-                                        .panic(|e| format!("Failed to write to memory ({})", e));
+                                        .await;
+                                    }
+                                    None
+                                }
+                                Ok(Ok(Err(e))) => Some(format!("{e}")),
+                                Ok(Err(e)) => {
+                                    reconnect = true;
+                                    Some(format!("{e}"))
+                                }
+                                Err(e) => {
+                                    reconnect = true;
+                                    Some(format!("{e}"))
+                                }
+                            };
+                            if let Some(e) = failure {
+                                let _ = self
+                                    .log_sender
+                                    .send(LogMsg::err(&format!(
+                                        "Failed to mask address {addr} with AND {and_mask:#06X} and OR {or_mask:#06X} [{e}]."
+                                    )))
+                                    .await;
+                            }
+                        }
+                        Command::ReadDeviceIdentification(slave) => {
+                            context.set_slave(Slave(slave.slave));
+                            match ident::read(
+                                context,
+                                ReadCode::Extended,
+                                0,
+                                Duration::from_millis(timeout_ms),
+                            )
+                            .await
+                            {
+                                Ok(Ok(objects)) => {
+                                    for object in objects.iter() {
+                                        let _ = self
+                                            .log_sender
+                                            .send(LogMsg::ok(&format!(
+                                                "Slave: {}, {}",
+                                                slave,
+                                                ident::describe(object)
+                                            )))
+                                            .await;
+                                    }
+                                }
+                                Ok(Err(e)) => {
+                                    let _ = self
+                                        .log_sender
+                                        .send(LogMsg::err(&format!(
+                                            "Failed to read device identification of slave {slave} [{e}]."
+                                        )))
+                                        .await;
+                                }
+                                Err(e) => {
+                                    let _ = self
+                                        .log_sender
+                                        .send(LogMsg::err(&format!(
+                                            "Failed to read device identification of slave {slave} [{e}]."
+                                        )))
+                                        .await;
+                                    reconnect = true;
                                 }
                             }
                         }
+                        Command::ReadDiagnostics(slave) => {
+                            context.set_slave(Slave(slave.slave));
+                            match diag::report(context, Duration::from_millis(timeout_ms)).await {
+                                Ok(Ok(lines)) => {
+                                    for line in lines.iter() {
+                                        let _ = self
+                                            .log_sender
+                                            .send(LogMsg::ok(&format!(
+                                                "Slave: {}, {}",
+                                                slave, line
+                                            )))
+                                            .await;
+                                    }
+                                }
+                                Ok(Err(e)) => {
+                                    let _ = self
+                                        .log_sender
+                                        .send(LogMsg::err(&format!(
+                                            "Failed to read diagnostics of slave {slave} [{e}]."
+                                        )))
+                                        .await;
+                                }
+                                Err(e) => {
+                                    let _ = self
+                                        .log_sender
+                                        .send(LogMsg::err(&format!(
+                                            "Failed to read diagnostics of slave {slave} [{e}]."
+                                        )))
+                                        .await;
+                                    reconnect = true;
+                                }
+                            }
+                        }
+                        Command::ReadWriteMultipleRegisters((
+                            slave,
+                            table,
+                            read_addr,
+                            cnt,
+                            write_addr,
+                            vec,
+                            write_only,
+                        )) => {
+                            context.set_slave(Slave(slave.slave));
+                            let failure = match tokio::time::timeout(
+                                Duration::from_millis(timeout_ms),
+                                context.read_write_multiple_registers(
+                                    read_addr, cnt, write_addr, &vec,
+                                ),
+                            )
+                            .await
+                            {
+                                Ok(Ok(Ok(values))) => {
+                                    let _ = self
+                                        .log_sender
+                                        .send(LogMsg::ok(&format!(
+                                            "Successfully written address {write_addr} with values {vec:?} and read address {read_addr} with values {values:?}."
+                                        )))
+                                        .await;
+                                    if write_only {
+                                        self.mirror(slave, table, write_addr, &vec).await;
+                                    }
+                                    self.mirror(slave, table, read_addr, &values).await;
+                                    None
+                                }
+                                Ok(Ok(Err(e))) => Some(format!("{e}")),
+                                Ok(Err(e)) => {
+                                    reconnect = true;
+                                    Some(format!("{e}"))
+                                }
+                                Err(e) => {
+                                    reconnect = true;
+                                    Some(format!("{e}"))
+                                }
+                            };
+                            if let Some(e) = failure {
+                                let _ = self
+                                    .log_sender
+                                    .send(LogMsg::err(&format!(
+                                        "Failed to write address {write_addr} with values {vec:?} [{e}]."
+                                    )))
+                                    .await;
+                            }
+                        }
                     }
                 }
 
@@ -551,21 +755,25 @@ impl Client {
 
                 // Reset connection on error
                 if reconnect {
-                    let builder = self.create_serial_builder().await;
+                    let builder = self.config.serial.create_serial_builder();
                     let port = SerialStream::open(&builder)
                         .panic(|e| format!("Failed to open SerialStream ({e})"));
                     let slave = Slave(self.config.client_id);
-                    connection = Some(rtu::attach_slave(port, slave));
+                    connection = Some(self.config.serial.attach_slave(port, slave));
                     if connection.is_some() {
-                        let _ = self
-                            .status_sender
-                            .send(Status::String(str!("Modbus TCP connected.")))
-                            .await;
+                        let _ =
+                            self.status_sender
+                                .send(self.status(format!(
+                                    "{} connected.",
+                                    self.config.serial.protocol()
+                                )))
+                                .await;
                         let _ = self
                             .log_sender
                             .send(LogMsg::ok(&format!(
-                                "Modbus RTU reconnected successfully to {}",
-                                self.config_as_str()
+                                "{} reconnected successfully to {}",
+                                self.config.serial.protocol(),
+                                self.config.serial.as_str()
                             )))
                             .await;
                         if delay_after_connect > 0 {
@@ -585,28 +793,30 @@ impl Client {
                         let _ = self
                             .log_sender
                             .send(LogMsg::err(&format!(
-                                "Modbus RTU failed to reconnect to {}",
-                                self.config_as_str()
+                                "{} failed to reconnect to {}",
+                                self.config.serial.protocol(),
+                                self.config.serial.as_str()
                             )))
                             .await;
                     }
                 }
             } else if let Ok(Command::Connect) = self.cmd_receiver.try_recv() {
-                let builder = self.create_serial_builder().await;
+                let builder = self.config.serial.create_serial_builder();
                 let port = SerialStream::open(&builder)
                     .panic(|e| format!("Failed to open SerialStream ({e})"));
                 let slave = Slave(self.config.client_id);
-                connection = Some(rtu::attach_slave(port, slave));
+                connection = Some(self.config.serial.attach_slave(port, slave));
                 if connection.is_some() {
                     let _ = self
                         .status_sender
-                        .send(Status::String(str!("Modbus TCP connected.")))
+                        .send(self.status(format!("{} connected.", self.config.serial.protocol())))
                         .await;
                     let _ = self
                         .log_sender
                         .send(LogMsg::ok(&format!(
-                            "Modbus RTU connected successfully to {}",
-                            self.config_as_str()
+                            "{} connected successfully to {}",
+                            self.config.serial.protocol(),
+                            self.config.serial.as_str()
                         )))
                         .await;
                     if delay_after_connect > 0 {
@@ -624,8 +834,9 @@ impl Client {
                     let _ = self
                         .log_sender
                         .send(LogMsg::err(&format!(
-                            "Modbus RTU failed to connect to {}",
-                            self.config_as_str()
+                            "{} failed to connect to {}",
+                            self.config.serial.protocol(),
+                            self.config.serial.as_str()
                         )))
                         .await;
                 }
@@ -1,11 +1,18 @@
+pub mod ascii;
 pub mod client;
 pub mod server;
 
 use std::fmt::Display;
 
+use crate::frame::Encoding;
+use crate::oneshot::OneShot;
 use crate::util::str;
 
 use clap::{Args, ValueEnum};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_modbus::client::Context;
+use tokio_modbus::Slave;
+use tokio_serial::{DataBits, Parity, SerialPortBuilder, StopBits};
 
 #[derive(Clone, Debug, ValueEnum)]
 pub enum FlowControl {
@@ -24,8 +31,9 @@ impl Display for FlowControl {
     }
 }
 
+/// Settings of the serial port shared by all serial transports
 #[derive(Clone, Debug, Default, Args)]
-pub struct RtuConfig {
+pub struct SerialConfig {
     /// The device path to use for communication.
     pub path: String,
 
@@ -33,10 +41,6 @@ pub struct RtuConfig {
     #[arg(short, long, default_value_t = 115200)]
     pub baud_rate: u32,
 
-    /// The Modbus slave id to use.
-    #[arg(short, long, default_value_t = 1)]
-    pub client_id: u8,
-
     /// The Modbus parity bit [values: even, odd, none]
     #[arg(short, long)]
     pub parity: Option<String>,
@@ -52,4 +56,120 @@ pub struct RtuConfig {
     /// The Modbus flow control
     #[arg(short, long)]
     pub flow_control: Option<FlowControl>,
+
+    /// Use Modbus ASCII framing instead of RTU.
+    #[arg(long, default_value_t = false)]
+    pub ascii: bool,
+}
+
+#[derive(Clone, Debug, Default, Args)]
+pub struct RtuConfig {
+    #[command(flatten)]
+    pub serial: SerialConfig,
+
+    /// The Modbus slave id to use.
+    #[arg(short, long, default_value_t = 1)]
+    pub client_id: u8,
+
+    /// Execute a single operation instead of starting the application.
+    #[command(subcommand)]
+    pub oneshot: Option<OneShot>,
+}
+
+impl SerialConfig {
+    pub fn encoding(&self) -> Encoding {
+        if self.ascii {
+            Encoding::Ascii
+        } else {
+            Encoding::Rtu
+        }
+    }
+
+    /// Name of the protocol used in status and log messages
+    pub fn protocol(&self) -> &'static str {
+        match self.encoding() {
+            Encoding::Rtu => "Modbus RTU",
+            Encoding::Ascii => "Modbus ASCII",
+        }
+    }
+
+    /// Attach a client context to the port using the configured framing
+    pub fn attach_slave<T>(&self, transport: T, slave: Slave) -> Context
+    where
+        T: AsyncRead + AsyncWrite + std::fmt::Debug + Unpin + Send + 'static,
+    {
+        match self.encoding() {
+            Encoding::Rtu => tokio_modbus::client::rtu::attach_slave(transport, slave),
+            Encoding::Ascii => ascii::attach_slave(transport, slave),
+        }
+    }
+
+    /// Create the serial port builder for the configured port settings
+    pub fn create_serial_builder(&self) -> SerialPortBuilder {
+        let mut builder = tokio_serial::new(self.path.clone(), self.baud_rate);
+        let data_bits = self.data_bits.unwrap_or(8);
+        let stop_bits = self.stop_bits.unwrap_or(1);
+        let parity = self
+            .parity
+            .as_ref()
+            .unwrap_or(&"NONE".to_string())
+            .to_uppercase();
+        let flow_control = self.flow_control.as_ref().unwrap_or(&FlowControl::None);
+
+        builder = builder.data_bits(match data_bits {
+            5 => DataBits::Five,
+            6 => DataBits::Six,
+            7 => DataBits::Seven,
+            8 => DataBits::Eight,
+            _ => panic!("Invalid data bits specified."),
+        });
+
+        builder = builder.stop_bits(match stop_bits {
+            1 => StopBits::One,
+            2 => StopBits::Two,
+            _ => panic!("Invalid stop bits specified"),
+        });
+
+        if parity == "ODD" {
+            builder = builder.parity(Parity::Odd);
+        } else if parity == "EVEN" {
+            builder = builder.parity(Parity::Even);
+        } else if parity == "NONE" {
+            builder = builder.parity(Parity::None);
+        } else {
+            panic!("Invalid parity specified");
+        }
+
+        builder = builder.flow_control(match flow_control {
+            FlowControl::None => tokio_serial::FlowControl::None,
+            FlowControl::Software => tokio_serial::FlowControl::Software,
+            FlowControl::Hardware => tokio_serial::FlowControl::Hardware,
+        });
+
+        builder
+    }
+
+    /// Human readable summary of the port settings
+    pub fn as_str(&self) -> String {
+        let path = &self.path;
+        let baud_rate = self.baud_rate;
+        let data_bits = self.data_bits.unwrap_or(8);
+        let stop_bits = self.stop_bits.unwrap_or(1);
+        let parity = self
+            .parity
+            .as_ref()
+            .unwrap_or(&"NONE".to_string())
+            .to_uppercase();
+        let flow_control = self.flow_control.as_ref().unwrap_or(&FlowControl::None);
+        format!(
+            "{}, baud rate: {}, data bits: {}, parity: {}, stop bits: {}, flow control: {}{}",
+            path,
+            baud_rate,
+            data_bits,
+            parity,
+            stop_bits,
+            flow_control,
+            if self.ascii { ", ASCII" } else { "" }
+        )
+    }
 }
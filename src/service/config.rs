@@ -0,0 +1,141 @@
+use crate::connection::Endpoint;
+use crate::ident::DeviceIdentification;
+use crate::lua::CustomFunction;
+use crate::mem::file::FileConfig;
+use crate::service::fault::FaultRule;
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio_modbus::prelude::{ExceptionCode, SlaveId};
+
+/// Treatment of requests to slaves the server does not respond to
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum UnknownSlave {
+    /// Leave the request unanswered, the client runs into its timeout
+    #[default]
+    Ignore,
+    /// Respond with Gateway Target Device Failed To Respond
+    GatewayTargetDevice,
+    /// Respond with Gateway Path Unavailable
+    GatewayPathUnavailable,
+}
+
+impl UnknownSlave {
+    /// Exception the request is answered with, `None` if it stays unanswered
+    pub fn exception(&self) -> Option<ExceptionCode> {
+        match self {
+            UnknownSlave::Ignore => None,
+            UnknownSlave::GatewayTargetDevice => Some(ExceptionCode::GatewayTargetDevice),
+            UnknownSlave::GatewayPathUnavailable => Some(ExceptionCode::GatewayPathUnavailable),
+        }
+    }
+}
+
+/// Additional interface served next to the one given on the command line
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ServerEndpoint {
+    #[serde(flatten)]
+    pub endpoint: Endpoint,
+    /// Slaves answered on this endpoint, all if omitted
+    pub slave_ids: Option<Vec<SlaveId>>,
+}
+
+/// Options used if the configuration has no server section
+static DEFAULT: Lazy<ServerConfig> = Lazy::new(ServerConfig::default);
+
+/// Options only used in server mode
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ServerConfig {
+    /// Exception code returned if a request violates the access type of a definition
+    access_exception: Option<u8>,
+    /// Only accept requests to addresses covered by a register definition
+    strict: Option<bool>,
+    /// Additionally accept the ranges of `contiguous_memory` in strict mode
+    strict_contiguous_memory: Option<bool>,
+    /// Global switch for the fault injection rules
+    fault_injection: Option<bool>,
+    /// Fault injection rules applied to matching requests
+    faults: Option<Vec<FaultRule>>,
+    /// Additional endpoints sharing the memory of the server
+    endpoints: Option<Vec<ServerEndpoint>>,
+    /// Slaves the server responds to, all if omitted
+    slave_ids: Option<Vec<SlaveId>>,
+    /// Treatment of requests to all other slaves
+    unknown_slave: Option<UnknownSlave>,
+    /// Objects returned for Read Device Identification (FC 43 / MEI 14)
+    device_identification: Option<DeviceIdentification>,
+    /// Server id returned by Report Server ID (FC 17), defaults to the slave id
+    server_id: Option<u8>,
+    /// Files of the file record access (FC 20/21) preloaded from disk
+    files: Option<Vec<FileConfig>>,
+    /// Lua handlers of user-defined function codes
+    custom_functions: Option<Vec<CustomFunction>>,
+}
+
+impl ServerConfig {
+    /// Shared instance with the default options
+    pub fn defaults() -> &'static Self {
+        &DEFAULT
+    }
+
+    pub fn access_exception(&self) -> ExceptionCode {
+        self.access_exception
+            .map(ExceptionCode::new)
+            .unwrap_or(ExceptionCode::IllegalDataAddress)
+    }
+
+    pub fn strict(&self) -> bool {
+        self.strict.unwrap_or(false)
+    }
+
+    pub fn strict_contiguous_memory(&self) -> bool {
+        self.strict_contiguous_memory.unwrap_or(false)
+    }
+
+    pub fn fault_injection(&self) -> bool {
+        self.fault_injection.unwrap_or(true)
+    }
+
+    pub fn toggle_fault_injection(&mut self) {
+        self.fault_injection = Some(!self.fault_injection());
+    }
+
+    pub fn faults(&self) -> &[FaultRule] {
+        self.faults.as_deref().unwrap_or_default()
+    }
+
+    pub fn faults_mut(&mut self) -> &mut [FaultRule] {
+        self.faults.as_deref_mut().unwrap_or_default()
+    }
+
+    pub fn endpoints(&self) -> &[ServerEndpoint] {
+        self.endpoints.as_deref().unwrap_or_default()
+    }
+
+    /// Returns `true` if the server responds to requests to the slave
+    pub fn is_known_slave(&self, slave: SlaveId) -> bool {
+        self.slave_ids
+            .as_ref()
+            .is_none_or(|ids| ids.contains(&slave))
+    }
+
+    pub fn unknown_slave(&self) -> UnknownSlave {
+        self.unknown_slave.unwrap_or_default()
+    }
+
+    pub fn device_identification(&self) -> DeviceIdentification {
+        self.device_identification.clone().unwrap_or_default()
+    }
+
+    pub fn server_id(&self, slave: SlaveId) -> u8 {
+        self.server_id.unwrap_or(slave)
+    }
+
+    pub fn files(&self) -> &[FileConfig] {
+        self.files.as_deref().unwrap_or_default()
+    }
+
+    pub fn custom_functions(&self) -> &[CustomFunction] {
+        self.custom_functions.as_deref().unwrap_or_default()
+    }
+}
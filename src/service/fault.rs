@@ -0,0 +1,135 @@
+use crate::mem::memory::{Range, Table};
+use crate::mem::register::Address;
+
+use serde::{Deserialize, Serialize};
+use tokio_modbus::prelude::{ExceptionCode, SlaveId};
+use tokio_modbus::FunctionCode;
+
+/// Misbehavior applied to the response of a matching request
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum FaultAction {
+    /// Delay the response by the given amount of milliseconds
+    Delay(u64),
+    /// Do not respond at all
+    Drop,
+    /// Respond with the given exception code
+    Exception(u8),
+    /// Respond with an invalid checksum (serial transports only)
+    CorruptCrc,
+}
+
+impl std::fmt::Display for FaultAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            FaultAction::Delay(ms) => write!(f, "Delay {ms} ms"),
+            FaultAction::Drop => f.write_str("Drop"),
+            FaultAction::Exception(code) => write!(f, "Exception {}", ExceptionCode::new(*code)),
+            FaultAction::CorruptCrc => f.write_str("CorruptCrc"),
+        }
+    }
+}
+
+/// Fault injection rule of the server configuration
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct FaultRule {
+    name: Option<String>,
+    enabled: Option<bool>,
+    slave_id: Option<SlaveId>,
+    read_code: Option<u8>,
+    range: Option<Range<Address>>,
+    every: Option<usize>,
+    action: FaultAction,
+    #[serde(skip)]
+    hits: usize,
+}
+
+impl FaultRule {
+    pub fn name(&self, index: usize) -> String {
+        self.name.clone().unwrap_or(format!("#{}", index + 1))
+    }
+
+    pub fn action(&self) -> &FaultAction {
+        &self.action
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    pub fn toggle(&mut self) {
+        self.enabled = Some(!self.is_enabled());
+        self.hits = 0;
+    }
+
+    /// Check whether the rule covers the request. The address range is only compared if the
+    /// request targets one of the tables.
+    fn matches(&self, slave: SlaveId, target: Option<(Table, usize, usize)>) -> bool {
+        if self.slave_id.is_some_and(|s| s != slave) {
+            return false;
+        }
+        let table = self
+            .read_code
+            .map(|code| Table::from_function_code(FunctionCode::new(code)));
+        match target {
+            Some((t, start, end)) => {
+                table.is_none_or(|table| table == Some(t))
+                    && self
+                        .range
+                        .as_ref()
+                        .is_none_or(|r| r.start() < end && start < r.end())
+            }
+            None => table.is_none() && self.range.is_none(),
+        }
+    }
+
+    /// Count the request and return whether the rule has to be applied to it
+    fn trigger(&mut self, slave: SlaveId, target: Option<(Table, usize, usize)>) -> bool {
+        if !self.is_enabled() || !self.matches(slave, target) {
+            return false;
+        }
+        self.hits += 1;
+        self.every
+            .is_none_or(|n| n == 0 || self.hits.is_multiple_of(n))
+    }
+}
+
+/// Combined effect of all rules triggered by a single request
+#[derive(Debug, Default, PartialEq)]
+pub struct Injection {
+    pub names: Vec<String>,
+    pub delay_ms: u64,
+    pub drop: bool,
+    pub exception: Option<ExceptionCode>,
+    pub corrupt_crc: bool,
+}
+
+impl Injection {
+    pub fn collect(
+        rules: &mut [FaultRule],
+        slave: SlaveId,
+        target: Option<(Table, usize, usize)>,
+    ) -> Self {
+        let mut injection = Self::default();
+        for (i, rule) in rules.iter_mut().enumerate() {
+            if !rule.trigger(slave, target) {
+                continue;
+            }
+            injection
+                .names
+                .push(format!("{} ({})", rule.name(i), rule.action));
+            match rule.action {
+                FaultAction::Delay(ms) => injection.delay_ms += ms,
+                FaultAction::Drop => injection.drop = true,
+                FaultAction::Exception(code) => {
+                    injection.exception.get_or_insert(ExceptionCode::new(code));
+                }
+                FaultAction::CorruptCrc => injection.corrupt_crc = true,
+            }
+        }
+        injection
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
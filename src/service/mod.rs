@@ -0,0 +1,768 @@
+mod config;
+pub mod fault;
+
+pub use config::ServerConfig;
+
+use crate::capture::Capture;
+use crate::diag::{self, Counter, Counters};
+use crate::frame;
+use crate::gateway::Upstream;
+use crate::lua::Functions;
+use crate::mem::memory::{Memory, Range, Table};
+use crate::mem::register::AccessType;
+use crate::mem::{fifo, file};
+use crate::util::str;
+use crate::{AppConfig, LogMsg};
+
+use config::UnknownSlave;
+use fault::Injection;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::mpsc::Sender;
+use tokio_modbus::prelude::{ExceptionCode, Request, Response, SlaveId, SlaveRequest};
+use tokio_modbus::FunctionCode;
+
+/// Modbus service shared by all server transports
+///
+/// Every request is routed to the memory table that belongs to its function code, so coils,
+/// discrete inputs, input registers and holding registers never share the same cells.
+#[derive(Clone)]
+pub struct Service {
+    config: Arc<Mutex<AppConfig>>,
+    memory: Arc<Mutex<Memory>>,
+    log_sender: Sender<LogMsg>,
+    capture: Option<Arc<Mutex<Capture>>>,
+    upstream: Option<Arc<Upstream>>,
+    slaves: Option<Arc<Vec<SlaveId>>>,
+    counters: Arc<Counters>,
+    functions: Option<Arc<Functions>>,
+}
+
+/// Answer of the service to a single request
+pub struct Reply {
+    pub result: Result<Response, ExceptionCode>,
+    /// The transport shall send the response with an invalid checksum
+    pub corrupt_crc: bool,
+}
+
+/// Returns the table and address range a request operates on. The range of FC 23 spans both the
+/// read and the write range.
+fn target(request: &Request<'static>) -> Option<(Table, usize, usize)> {
+    let (table, addr, cnt) = match request {
+        Request::ReadCoils(addr, cnt) => (Table::Coils, *addr, *cnt as usize),
+        Request::ReadDiscreteInputs(addr, cnt) => (Table::DiscreteInputs, *addr, *cnt as usize),
+        Request::ReadInputRegisters(addr, cnt) => (Table::InputRegisters, *addr, *cnt as usize),
+        Request::ReadHoldingRegisters(addr, cnt) => (Table::HoldingRegisters, *addr, *cnt as usize),
+        Request::WriteSingleCoil(addr, _) => (Table::Coils, *addr, 1),
+        Request::WriteMultipleCoils(addr, coils) => (Table::Coils, *addr, coils.len()),
+        Request::WriteSingleRegister(addr, _) => (Table::HoldingRegisters, *addr, 1),
+        Request::WriteMultipleRegisters(addr, values) => {
+            (Table::HoldingRegisters, *addr, values.len())
+        }
+        Request::MaskWriteRegister(addr, _, _) => (Table::HoldingRegisters, *addr, 1),
+        Request::ReadWriteMultipleRegisters(read_addr, cnt, write_addr, values) => {
+            let addr = (*read_addr).min(*write_addr);
+            let end =
+                (*read_addr as usize + *cnt as usize).max(*write_addr as usize + values.len());
+            (Table::HoldingRegisters, addr, end - addr as usize)
+        }
+        _ => return None,
+    };
+    Some((table, addr as usize, addr as usize + cnt))
+}
+
+fn to_str(values: &[u16]) -> String {
+    let mut s = str!("[ ");
+    for i in 0..values.len() {
+        if i == values.len() - 1 {
+            s += &format!("{:#06X} ", values[i]);
+        } else {
+            s += &format!("{:#06X}, ", values[i]);
+        }
+    }
+    s + "]"
+}
+
+impl tokio_modbus::server::Service for Service {
+    type Request = SlaveRequest<'static>;
+    type Exception = ExceptionCode;
+    type Response = Option<Response>;
+    type Future = Pin<Box<dyn Future<Output = Result<Option<Response>, ExceptionCode>> + Send>>;
+
+    fn call(&self, req: Self::Request) -> Self::Future {
+        let service = self.clone();
+        Box::pin(async move {
+            match service.process(req).await {
+                Some(reply) => reply.result.map(Some),
+                None => Ok(None),
+            }
+        })
+    }
+}
+
+impl Service {
+    pub fn new(
+        config: Arc<Mutex<AppConfig>>,
+        memory: Arc<Mutex<Memory>>,
+        log_sender: Sender<LogMsg>,
+        capture: Option<Arc<Mutex<Capture>>>,
+    ) -> Self {
+        Self {
+            config,
+            memory,
+            log_sender,
+            capture,
+            upstream: None,
+            slaves: None,
+            counters: Arc::default(),
+            functions: None,
+        }
+    }
+
+    /// Forward all requests to the upstream device instead of answering them from memory
+    pub fn with_upstream(mut self, upstream: Upstream) -> Self {
+        self.upstream = Some(Arc::new(upstream));
+        self
+    }
+
+    /// Only respond to requests to the given slaves, all others are treated as unknown slaves
+    pub fn with_slaves(mut self, slaves: Vec<SlaveId>) -> Self {
+        self.slaves = Some(Arc::new(slaves));
+        self
+    }
+
+    /// Keep the diagnostic counters in the given set, e.g. a separate one per bus
+    pub fn with_counters(mut self, counters: Arc<Counters>) -> Self {
+        self.counters = counters;
+        self
+    }
+
+    /// Answer user-defined function codes with the given Lua handlers
+    pub fn with_functions(mut self, functions: Functions) -> Self {
+        self.functions = Some(Arc::new(functions));
+        self
+    }
+
+    /// Diagnostic counters of the service
+    pub fn counters(&self) -> &Counters {
+        &self.counters
+    }
+
+    /// Process the request including all configured fault injections. Returns `None` if the
+    /// request has to be left unanswered.
+    pub async fn process(&self, req: SlaveRequest<'static>) -> Option<Reply> {
+        let SlaveRequest { slave, request } = req;
+        let captured = self.capture.as_ref().map(|_| {
+            let range = target(&request).map(|(_, start, end)| (start, end));
+            (frame::encode_request(&request), range)
+        });
+        let known = self.unknown_slave(slave).is_none();
+        self.counters.increment(Counter::BusMessages);
+        if known {
+            self.counters.increment(Counter::ServerMessages);
+        }
+        let reply = self.reply(slave, request).await;
+        match reply.as_ref().map(|reply| reply.result.is_err()) {
+            None if known => self.counters.increment(Counter::ServerNoResponses),
+            Some(true) => self.counters.increment(Counter::BusExceptionErrors),
+            _ => {}
+        }
+        if let Some((pdu, range)) = captured {
+            self.capture(slave, &pdu, range, reply.as_ref());
+        }
+        reply
+    }
+
+    fn capture(
+        &self,
+        slave: SlaveId,
+        request: &[u8],
+        range: Option<(usize, usize)>,
+        reply: Option<&Reply>,
+    ) {
+        let Some(capture) = self.capture.as_ref() else {
+            return;
+        };
+        let response = reply.map(|r| frame::encode_response(request[0], &r.result));
+        let result = capture.lock().expect("Unable to lock capture").write(
+            slave,
+            request,
+            range,
+            response.as_deref(),
+        );
+        if let Err(e) = result {
+            let _ = self
+                .log_sender
+                .try_send(LogMsg::err(&format!("Failed to capture request ({e})")));
+        }
+    }
+
+    async fn reply(&self, slave: SlaveId, request: Request<'static>) -> Option<Reply> {
+        if let Some(unknown) = self.unknown_slave(slave) {
+            let exception = unknown.exception();
+            let _ = self.log_sender.try_send(LogMsg::info(&format!(
+                "Slave: {}, Unknown slave ({})",
+                slave,
+                exception.map_or(str!("No response"), |e| e.to_string())
+            )));
+            return exception.map(|e| Reply {
+                result: Err(e),
+                corrupt_crc: false,
+            });
+        }
+        let injection = self.inject(slave, &request);
+        if !injection.is_empty() {
+            let _ = self.log_sender.try_send(LogMsg::info(&format!(
+                "Slave: {}, Fault injected: {}",
+                slave,
+                injection.names.join(", ")
+            )));
+        }
+        if injection.delay_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(injection.delay_ms)).await;
+        }
+        if injection.drop {
+            return None;
+        }
+        let result = match (injection.exception, self.upstream.as_ref()) {
+            (Some(exception), _) => Err(exception),
+            (None, Some(upstream)) => self.forward(upstream, slave, request).await,
+            (None, None) => match self.functions.as_ref() {
+                Some(functions) if functions.handles(request.function_code().value()) => {
+                    self.custom(functions, slave, request).await?
+                }
+                _ => self.handle(slave, request),
+            },
+        };
+        Some(Reply {
+            result,
+            corrupt_crc: injection.corrupt_crc,
+        })
+    }
+
+    async fn forward(
+        &self,
+        upstream: &Upstream,
+        slave: SlaveId,
+        request: Request<'static>,
+    ) -> Result<Response, ExceptionCode> {
+        let pdu = frame::encode_request(&request);
+        match upstream.forward(slave, request).await {
+            Ok(result) => {
+                let _ = self.log_sender.try_send(LogMsg::info(&format!(
+                    "Slave: {}, Forwarded to {}: {} -> {}",
+                    slave,
+                    upstream.target().as_str(),
+                    frame::to_hex(&pdu),
+                    frame::to_hex(&frame::encode_response(pdu[0], &result))
+                )));
+                result
+            }
+            Err((exception, e)) => {
+                let _ = self.log_sender.try_send(LogMsg::err(&format!(
+                    "Slave: {}, Forwarded to {}: {} ({})",
+                    slave,
+                    upstream.target().as_str(),
+                    frame::to_hex(&pdu),
+                    e
+                )));
+                Err(exception)
+            }
+        }
+    }
+
+    /// Answer the request with the Lua handler of its function code. Returns `None` if the handler
+    /// leaves the request unanswered, failing handlers are answered with Server Device Failure.
+    async fn custom(
+        &self,
+        functions: &Functions,
+        slave: SlaveId,
+        request: Request<'static>,
+    ) -> Option<Result<Response, ExceptionCode>> {
+        let pdu = frame::encode_request(&request);
+        let result = match functions.call(slave, pdu.clone()).await {
+            Ok(answer) => answer
+                .map(|result| result.map(|rsp| Response::Custom(rsp[0], rsp[1..].to_vec().into()))),
+            Err(e) => {
+                let _ = self.log_sender.try_send(LogMsg::err(&format!(
+                    "Slave: {}, Custom function: {} ({})",
+                    slave,
+                    frame::to_hex(&pdu),
+                    e
+                )));
+                return Some(Err(ExceptionCode::ServerDeviceFailure));
+            }
+        };
+        let _ = self.log_sender.try_send(LogMsg::info(&format!(
+            "Slave: {}, Custom function: {} -> {}",
+            slave,
+            frame::to_hex(&pdu),
+            match result {
+                Some(ref result) => frame::to_hex(&frame::encode_response(pdu[0], result)),
+                None => str!("No response"),
+            }
+        )));
+        result
+    }
+
+    /// Returns the treatment of the slave if the service does not respond to it
+    fn unknown_slave(&self, slave: SlaveId) -> Option<UnknownSlave> {
+        let config = self.config.lock().expect("Unable to lock config");
+        let server = config.server();
+        let known =
+            server.is_known_slave(slave) && self.slaves.as_ref().is_none_or(|s| s.contains(&slave));
+        (!known).then(|| server.unknown_slave())
+    }
+
+    fn inject(&self, slave: SlaveId, request: &Request<'static>) -> Injection {
+        let mut config = self.config.lock().expect("Unable to lock config");
+        match config.server.as_mut() {
+            Some(server) if server.fault_injection() => {
+                Injection::collect(server.faults_mut(), slave, target(request))
+            }
+            _ => Injection::default(),
+        }
+    }
+
+    fn handle(&self, slave: SlaveId, request: Request<'static>) -> Result<Response, ExceptionCode> {
+        match request {
+            Request::ReadCoils(addr, cnt) => self
+                .read(slave, Table::Coils, "ReadCoils", addr, cnt)
+                .map(|v| Response::ReadCoils(v.into_iter().map(|b| b != 0).collect())),
+            Request::ReadDiscreteInputs(addr, cnt) => self
+                .read(
+                    slave,
+                    Table::DiscreteInputs,
+                    "ReadDiscreteInputs",
+                    addr,
+                    cnt,
+                )
+                .map(|v| Response::ReadDiscreteInputs(v.into_iter().map(|b| b != 0).collect())),
+            Request::ReadInputRegisters(addr, cnt) => self
+                .read(
+                    slave,
+                    Table::InputRegisters,
+                    "ReadInputRegisters",
+                    addr,
+                    cnt,
+                )
+                .map(Response::ReadInputRegisters),
+            Request::ReadHoldingRegisters(addr, cnt) => self
+                .read(
+                    slave,
+                    Table::HoldingRegisters,
+                    "ReadHoldingRegisters",
+                    addr,
+                    cnt,
+                )
+                .map(Response::ReadHoldingRegisters),
+            Request::WriteMultipleRegisters(addr, values) => self
+                .write(
+                    slave,
+                    Table::HoldingRegisters,
+                    "WriteMultipleRegisters",
+                    addr,
+                    &values,
+                )
+                .map(|_| Response::WriteMultipleRegisters(addr, values.len() as u16)),
+            Request::WriteSingleRegister(addr, value) => self
+                .write(
+                    slave,
+                    Table::HoldingRegisters,
+                    "WriteSingleRegister",
+                    addr,
+                    &[value],
+                )
+                .map(|_| Response::WriteSingleRegister(addr, value)),
+            Request::MaskWriteRegister(addr, and_mask, or_mask) => {
+                let (table, func) = (Table::HoldingRegisters, "MaskWriteRegister");
+                // The current value is never returned, so write-only registers can be masked
+                self.check_write(slave, table, func, addr, 1)?;
+                let mut memory = self.memory.lock().expect("Unable to lock memory");
+                let value = self.read_memory(&mut memory, slave, table, func, addr, 1)?[0];
+                let value = frame::mask(value, and_mask, or_mask);
+                self.write_memory(&mut memory, slave, table, func, addr, &[value])
+                    .map(|_| Response::MaskWriteRegister(addr, and_mask, or_mask))
+            }
+            Request::ReadDeviceIdentification(read_code, object_id) => {
+                let config = self.config.lock().expect("Unable to lock config");
+                let result = config
+                    .server()
+                    .device_identification()
+                    .respond(read_code, object_id);
+                let _ = self.log_sender.try_send(match result {
+                    Ok(_) => LogMsg::info(&format!(
+                        "Slave: {}, ReadDeviceIdentification: {:?} from {:#04X}",
+                        slave, read_code, object_id
+                    )),
+                    Err(e) => LogMsg::err(&format!(
+                        "Slave: {}, ReadDeviceIdentification: {:?} from {:#04X} ({})",
+                        slave, read_code, object_id, e
+                    )),
+                });
+                result
+            }
+            Request::Custom(diag::DIAGNOSTICS, data) => {
+                let result = self.counters.respond(&data);
+                let _ = self.log_sender.try_send(match result {
+                    Ok((sub, _)) => {
+                        LogMsg::info(&format!("Slave: {}, Diagnostics: {:?}", slave, sub))
+                    }
+                    Err(e) => LogMsg::err(&format!(
+                        "Slave: {}, Diagnostics: {} ({})",
+                        slave,
+                        frame::to_hex(&data),
+                        e
+                    )),
+                });
+                result.map(|(_, response)| response)
+            }
+            Request::Custom(file::READ_FILE_RECORD, data) => self
+                .read_file_record(slave, &data)
+                .map(|data| Response::Custom(file::READ_FILE_RECORD, data.into())),
+            Request::Custom(file::WRITE_FILE_RECORD, data) => self
+                .write_file_record(slave, &data)
+                .map(|_| Response::Custom(file::WRITE_FILE_RECORD, data.into_owned().into())),
+            Request::Custom(fifo::READ_FIFO_QUEUE, data) => self
+                .read_fifo_queue(slave, &data)
+                .map(|values| fifo::response(&values)),
+            Request::ReportServerId => {
+                let config = self.config.lock().expect("Unable to lock config");
+                let server = config.server();
+                let mut data = server.device_identification().summary().into_bytes();
+                // Byte count, server id and run indicator leave 249 bytes of the PDU
+                data.truncate(249);
+                let _ = self
+                    .log_sender
+                    .try_send(LogMsg::info(&format!("Slave: {}, ReportServerId", slave)));
+                Ok(diag::server_id_response(
+                    server.server_id(slave),
+                    true,
+                    &data,
+                ))
+            }
+            Request::ReadWriteMultipleRegisters(read_addr, cnt, write_addr, values) => {
+                let (table, func) = (Table::HoldingRegisters, "ReadWriteMultipleRegisters");
+                // Both ranges are checked before the memory is touched
+                self.check_write(slave, table, func, write_addr, values.len())?;
+                self.check_read(slave, table, func, read_addr, cnt as usize)?;
+                // The write is performed before the read without other requests in between
+                let mut memory = self.memory.lock().expect("Unable to lock memory");
+                self.write_memory(&mut memory, slave, table, func, write_addr, &values)?;
+                self.read_memory(&mut memory, slave, table, func, read_addr, cnt)
+                    .map(Response::ReadWriteMultipleRegisters)
+            }
+            Request::WriteMultipleCoils(addr, coils) => {
+                let values: Vec<u16> = coils.iter().map(|v| if *v { 1 } else { 0 }).collect();
+                self.write(slave, Table::Coils, "WriteMultipleCoils", addr, &values)
+                    .map(|_| Response::WriteMultipleCoils(addr, values.len() as u16))
+            }
+            Request::WriteSingleCoil(addr, coil) => {
+                let value = if coil { 1 } else { 0 };
+                self.write(slave, Table::Coils, "WriteSingleCoil", addr, &[value])
+                    .map(|_| Response::WriteSingleCoil(addr, coil))
+            }
+            _ => {
+                let _ = self
+                    .log_sender
+                    .try_send(LogMsg::err(
+                        &format!("Slave: {} (Illegal Function)", slave,),
+                    ));
+                Err(ExceptionCode::IllegalFunction)
+            }
+        }
+    }
+
+    /// Reject the request in strict mode if any address is not covered by the configuration
+    fn check_address(
+        &self,
+        slave: SlaveId,
+        table: Table,
+        func: &str,
+        addr: u16,
+        end: usize,
+    ) -> Result<(), ExceptionCode> {
+        let config = self.config.lock().expect("Unable to lock config");
+        let server = config.server();
+        if !server.strict() {
+            return Ok(());
+        }
+        let mut ranges: Vec<Range<usize>> = config
+            .definitions
+            .values()
+            .filter(|def| def.get_slave_id().unwrap_or(0) == slave && def.table() == table)
+            .map(|def| Range::new(def.get_range().start(), def.get_range().end()))
+            .collect();
+        if server.strict_contiguous_memory() {
+            ranges.extend(
+                config
+                    .contiguous_memory
+                    .iter()
+                    .filter(|mem| {
+                        mem.slave_id.unwrap_or(0) == slave
+                            && Table::from_function_code(FunctionCode::new(mem.read_code))
+                                == Some(table)
+                    })
+                    .map(|mem| Range::new(mem.range.start(), mem.range.end())),
+            );
+        }
+        if (addr as usize..end).all(|a| ranges.iter().any(|r| r.start() <= a && a < r.end())) {
+            Ok(())
+        } else {
+            let _ = self.log_sender.try_send(LogMsg::err(&format!(
+                "Slave: {}, {}: [{:#06X}, {:#06X}) (Address not defined)",
+                slave, func, addr, end
+            )));
+            Err(ExceptionCode::IllegalDataAddress)
+        }
+    }
+
+    /// Reject the request if it touches a definition with the given (forbidden) access type
+    fn check_access(
+        &self,
+        slave: SlaveId,
+        table: Table,
+        func: &str,
+        addr: u16,
+        end: usize,
+        forbidden: AccessType,
+    ) -> Result<(), ExceptionCode> {
+        let config = self.config.lock().expect("Unable to lock config");
+        let violation = config.definitions.iter().find(|(_, def)| {
+            def.get_slave_id().unwrap_or(0) == slave
+                && def.table() == table
+                && def.access_type() == forbidden
+                && def.get_range().start() < end
+                && (addr as usize) < def.get_range().end()
+        });
+        match violation {
+            Some((name, _)) => {
+                let exception = config.server().access_exception();
+                let _ = self.log_sender.try_send(LogMsg::err(&format!(
+                    "Slave: {}, {}: [{:#06X}, {:#06X}) (Access violation on {} register '{}', {})",
+                    slave, func, addr, end, forbidden, name, exception
+                )));
+                Err(exception)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Answer a Read File Record (FC 20) request, every sub-request must lie within its file
+    fn read_file_record(&self, slave: SlaveId, data: &[u8]) -> Result<Vec<u8>, ExceptionCode> {
+        let requests = file::parse_request(data, false).inspect_err(|e| {
+            let _ = self.log_sender.try_send(LogMsg::err(&format!(
+                "Slave: {}, ReadFileRecord: {} ({})",
+                slave,
+                frame::to_hex(data),
+                e
+            )));
+        })?;
+        let memory = self.memory.lock().expect("Unable to lock memory");
+        let mut records = Vec::with_capacity(requests.len());
+        for request in requests.iter() {
+            let range = request.range();
+            match memory.read_file(slave, request.file, range.clone()) {
+                Some(values) => {
+                    let _ = self.log_sender.try_send(LogMsg::info(&format!(
+                        "Slave: {}, ReadFileRecord: File {}, [{}, {}) = {}",
+                        slave,
+                        request.file,
+                        range.start,
+                        range.end,
+                        to_str(values)
+                    )));
+                    records.push(values.to_vec());
+                }
+                None => {
+                    let _ = self.log_sender.try_send(LogMsg::err(&format!(
+                        "Slave: {}, ReadFileRecord: File {}, [{}, {}) (Records not available)",
+                        slave, request.file, range.start, range.end
+                    )));
+                    return Err(ExceptionCode::IllegalDataAddress);
+                }
+            }
+        }
+        file::read_response(&records)
+    }
+
+    /// Answer a Write File Record (FC 21) request, files grow as required
+    fn write_file_record(&self, slave: SlaveId, data: &[u8]) -> Result<(), ExceptionCode> {
+        let requests = file::parse_request(data, true).inspect_err(|e| {
+            let _ = self.log_sender.try_send(LogMsg::err(&format!(
+                "Slave: {}, WriteFileRecord: {} ({})",
+                slave,
+                frame::to_hex(data),
+                e
+            )));
+        })?;
+        let mut memory = self.memory.lock().expect("Unable to lock memory");
+        for request in requests.iter() {
+            memory.write_file(slave, request.file, request.record as usize, &request.data);
+            let _ = self.log_sender.try_send(LogMsg::info(&format!(
+                "Slave: {}, WriteFileRecord: File {}, [{}, {}) = {}",
+                slave,
+                request.file,
+                request.range().start,
+                request.range().end,
+                to_str(&request.data)
+            )));
+        }
+        Ok(())
+    }
+
+    /// Answer a Read FIFO Queue (FC 24) request, all returned entries are removed from the queue
+    fn read_fifo_queue(&self, slave: SlaveId, data: &[u8]) -> Result<Vec<u16>, ExceptionCode> {
+        let addr = fifo::parse_request(data).inspect_err(|e| {
+            let _ = self.log_sender.try_send(LogMsg::err(&format!(
+                "Slave: {}, ReadFifoQueue: {} ({})",
+                slave,
+                frame::to_hex(data),
+                e
+            )));
+        })?;
+        let mut memory = self.memory.lock().expect("Unable to lock memory");
+        let (result, reason) = match memory.fifo(slave, addr).map(|queue| queue.len()) {
+            None => (Err(ExceptionCode::IllegalDataAddress), "No FIFO queue"),
+            Some(count) if count > fifo::MAX_COUNT => {
+                (Err(ExceptionCode::IllegalDataValue), "Too many entries")
+            }
+            Some(_) => (Ok(memory.pop_fifo(slave, addr).unwrap_or_default()), ""),
+        };
+        let _ = self.log_sender.try_send(match result {
+            Ok(ref values) => LogMsg::info(&format!(
+                "Slave: {}, ReadFifoQueue: {:#06X} = {}",
+                slave,
+                addr,
+                to_str(values)
+            )),
+            Err(e) => LogMsg::err(&format!(
+                "Slave: {}, ReadFifoQueue: {:#06X} ({}, {})",
+                slave, addr, reason, e
+            )),
+        });
+        result
+    }
+
+    /// Check the address map and reject reading write-only registers
+    fn check_read(
+        &self,
+        slave: SlaveId,
+        table: Table,
+        func: &str,
+        addr: u16,
+        cnt: usize,
+    ) -> Result<(), ExceptionCode> {
+        let end = addr as usize + cnt;
+        self.check_address(slave, table, func, addr, end)?;
+        self.check_access(slave, table, func, addr, end, AccessType::WriteOnly)
+    }
+
+    /// Check the address map and reject writing read-only registers
+    fn check_write(
+        &self,
+        slave: SlaveId,
+        table: Table,
+        func: &str,
+        addr: u16,
+        cnt: usize,
+    ) -> Result<(), ExceptionCode> {
+        let end = addr as usize + cnt;
+        self.check_address(slave, table, func, addr, end)?;
+        self.check_access(slave, table, func, addr, end, AccessType::ReadOnly)
+    }
+
+    fn read_memory(
+        &self,
+        memory: &mut Memory,
+        slave: SlaveId,
+        table: Table,
+        func: &str,
+        addr: u16,
+        cnt: u16,
+    ) -> Result<Vec<u16>, ExceptionCode> {
+        let end = addr as usize + cnt as usize;
+        memory
+            .read(slave, table, &Range::new(addr as usize, end))
+            .map(|v| v.into_iter().copied().collect::<Vec<_>>())
+            .map_err(|e| {
+                let _ = self.log_sender.try_send(LogMsg::err(&format!(
+                    "Slave: {}, {}: [{:#06X}, {:#06X}) ({})",
+                    slave, func, addr, end, e
+                )));
+                ExceptionCode::IllegalDataAddress
+            })
+            .inspect(|v| {
+                let _ = self.log_sender.try_send(LogMsg::info(&format!(
+                    "Slave: {}, {}: [{:#06X}, {:#06X}) = {}",
+                    slave,
+                    func,
+                    addr,
+                    end,
+                    to_str(v)
+                )));
+            })
+    }
+
+    fn write_memory(
+        &self,
+        memory: &mut Memory,
+        slave: SlaveId,
+        table: Table,
+        func: &str,
+        addr: u16,
+        values: &[u16],
+    ) -> Result<(), ExceptionCode> {
+        let end = addr as usize + values.len();
+        memory
+            .write(slave, table, Range::new(addr as usize, end), values)
+            .map(|_| ())
+            .map_err(|e| {
+                let _ = self.log_sender.try_send(LogMsg::err(&format!(
+                    "Slave: {}, {}: [{:#06X}, {:#06X}) ({})",
+                    slave, func, addr, end, e
+                )));
+                ExceptionCode::IllegalDataAddress
+            })
+            .inspect(|_| {
+                let _ = self.log_sender.try_send(LogMsg::info(&format!(
+                    "Slave: {}, {}: [{:#06X}, {:#06X}) = {}",
+                    slave,
+                    func,
+                    addr,
+                    end,
+                    to_str(values)
+                )));
+            })
+    }
+
+    fn read(
+        &self,
+        slave: SlaveId,
+        table: Table,
+        func: &str,
+        addr: u16,
+        cnt: u16,
+    ) -> Result<Vec<u16>, ExceptionCode> {
+        self.check_read(slave, table, func, addr, cnt as usize)?;
+        let mut memory = self.memory.lock().expect("Unable to lock memory");
+        self.read_memory(&mut memory, slave, table, func, addr, cnt)
+    }
+
+    fn write(
+        &self,
+        slave: SlaveId,
+        table: Table,
+        func: &str,
+        addr: u16,
+        values: &[u16],
+    ) -> Result<(), ExceptionCode> {
+        self.check_write(slave, table, func, addr, values.len())?;
+        let mut memory = self.memory.lock().expect("Unable to lock memory");
+        self.write_memory(&mut memory, slave, table, func, addr, values)
+    }
+}
@@ -1,25 +1,36 @@
-use crate::mem::memory::{Memory, Range};
+use crate::connection;
+use crate::diag;
+use crate::frame;
+use crate::ident;
+use crate::mem::fifo;
+use crate::mem::memory::{Memory, Range, Table, Unit};
 use crate::mem::register::AccessType;
 use crate::msg::LogMsg;
+use crate::record::Recorder;
 use crate::tcp::TcpConfig;
-use crate::util::{str, Expect};
+use crate::util::Expect;
 use crate::{AppConfig, Command, Status};
 
 use itertools::Itertools;
-use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, SystemTime};
 use tokio::sync::mpsc::{Receiver, Sender};
-use tokio_modbus::prelude::{Client as ModbusClient, Reader, SlaveContext, SlaveId, Writer};
+use tokio_modbus::client::Context;
+use tokio_modbus::prelude::{
+    Client as ModbusClient, ReadCode, Reader, SlaveContext, SlaveId, Writer,
+};
 use tokio_modbus::{FunctionCode, Slave};
 
 pub struct Client {
     tcp_config: TcpConfig,
+    app_config: Arc<Mutex<AppConfig>>,
     memory: Arc<Mutex<Memory>>,
+    connection: usize,
     operations: Vec<(SlaveId, FunctionCode, Range<u16>)>,
     status_sender: Sender<Status>,
     cmd_receiver: Receiver<Command>,
     log_sender: Sender<LogMsg>,
+    recorder: Option<Arc<Mutex<Recorder>>>,
 }
 
 impl Client {
@@ -30,24 +41,50 @@ impl Client {
         status_sender: Sender<Status>,
         cmd_receiver: Receiver<Command>,
         log_sender: Sender<LogMsg>,
+        recorder: Option<Arc<Mutex<Recorder>>>,
     ) -> Self {
-        let operations = Self::init(app_config);
         Self {
             tcp_config,
+            app_config,
             memory,
-            operations,
+            connection: 0,
+            operations: Vec::new(),
             status_sender,
             cmd_receiver,
             log_sender,
+            recorder,
         }
     }
 
-    fn init(app_config: Arc<Mutex<AppConfig>>) -> Vec<(SlaveId, FunctionCode, Range<u16>)> {
+    /// Status message of the connection
+    fn status(&self, msg: String) -> Status {
+        let config = self
+            .app_config
+            .lock()
+            .expect("Unable to lock configuration");
+        connection::status(&config, self.connection, msg)
+    }
+
+    /// Only poll the definitions of the named connection with the given index
+    pub fn with_connection(mut self, connection: usize) -> Self {
+        self.connection = connection;
+        self
+    }
+
+    /// Plan the read operations of all definitions of the connection
+    fn init(
+        app_config: Arc<Mutex<AppConfig>>,
+        connection: usize,
+    ) -> Vec<(SlaveId, FunctionCode, Range<u16>)> {
         let config = app_config.lock().expect("Unable to lock configuration");
         let sorted_defs = config
             .definitions
             .iter()
-            .filter(|d| !d.1.is_virtual() && d.1.access_type() != AccessType::WriteOnly)
+            .filter(|d| {
+                d.1.unit().connection == connection
+                    && !d.1.is_virtual()
+                    && d.1.access_type() != AccessType::WriteOnly
+            })
             .sorted_by(|a, b| {
                 a.1.get_slave_id()
                     .unwrap_or(1)
@@ -61,7 +98,7 @@ impl Client {
             .collect::<Vec<_>>();
 
         let is_allowed = |slave: SlaveId, fc: u8, addr: u16, end: usize| {
-            for mem in config.contiguous_memory.iter() {
+            for mem in config.contiguous_memory(connection).iter() {
                 if mem.slave_id.unwrap_or(1) == slave
                     && mem.read_code == fc
                     && addr as usize >= mem.range.start()
@@ -91,6 +128,7 @@ impl Client {
                     Some((slave, fc, range)) => {
                         if slave != def.get_slave_id().unwrap_or(1)
                             || fc != def.read_code()
+                            || def.is_fifo()
                             || def.get_range().start() + def.get_range().length() - range.start()
                                 > 126
                             || !is_allowed(
@@ -131,42 +169,77 @@ impl Client {
             .collect()
     }
 
+    /// Update the memory with the values written to a write-only register, which is never
+    /// polled, or read back after a write. Failures are logged.
+    async fn mirror(&self, unit: Unit, table: Table, addr: u16, values: &[u16]) {
+        let result = self
+            .memory
+            .lock()
+            .expect("Unable to lock memory")
+            .write(
+                unit,
+                table,
+                Range::new(addr, addr + values.len() as u16),
+                values,
+            )
+            .map(|_| ());
+        if let Err(e) = result {
+            let _ = self
+                .log_sender
+                .send(LogMsg::err(&format!("Failed to write to memory ({e})")))
+                .await;
+        }
+    }
+
+    async fn connect(&self, timeout_ms: u64) -> Option<Context> {
+        let timeout = Duration::from_millis(timeout_ms);
+        tokio::time::timeout(timeout, self.tcp_config.connect(Slave(0), timeout))
+            .await
+            .ok()
+            .and_then(|r| r.ok())
+    }
+
     pub async fn run(&mut self, delay_after_connect: u64, interval_ms: u64, timeout_ms: u64) {
-        let addr: SocketAddr = format!("{}:{}", self.tcp_config.ip, self.tcp_config.port)
-            .parse()
+        self.operations = Self::init(self.app_config.clone(), self.connection);
+        self.tcp_config
+            .addr()
             .panic(|e| format!("Failed to create SocketAddr ({e})"));
-        let mut connection = if let Ok(r) = tokio::time::timeout(
-            std::time::Duration::from_millis(timeout_ms),
-            tokio_modbus::client::tcp::connect(addr),
-        )
-        .await
-        {
-            r.ok()
-        } else {
-            None
-        };
+        if self.tcp_config.tls.tls {
+            self.tcp_config
+                .tls
+                .connector()
+                .panic(|e| format!("Invalid TLS configuration ({e})"));
+        }
+        let connect_timeout_ms = timeout_ms;
+        // The retries of UDP are part of a single operation
+        let timeout_ms = self.tcp_config.transport.operation_timeout_ms(timeout_ms);
+        let mut connection = self.connect(connect_timeout_ms).await;
         if connection.is_some() {
             let _ = self
                 .status_sender
-                .send(Status::String(str!("Modbus TCP connected.")))
+                .send(self.status(format!("{} connected.", self.tcp_config.protocol())))
                 .await;
             let _ = self
                 .log_sender
                 .send(LogMsg::ok(&format!(
-                    "Modbus TCP connected to {}:{}",
-                    self.tcp_config.ip, self.tcp_config.port
+                    "{} connected to {}:{}",
+                    self.tcp_config.protocol(),
+                    self.tcp_config.ip,
+                    self.tcp_config.port
                 )))
                 .await;
         } else {
             let _ = self
                 .status_sender
-                .send(Status::String(str!("Modbus TCP disconnected.")))
+                .send(self.status(format!("{} disconnected.", self.tcp_config.protocol())))
                 .await;
             let _ = self
                 .log_sender
                 .send(LogMsg::err(&format!(
-                    "Modbus TCP failed to connect to {}:{}",
-                    self.tcp_config.ip, self.tcp_config.port
+                    "{} failed to connect to {}:{}",
+                    self.tcp_config.protocol(),
+                    self.tcp_config.ip,
+                    self.tcp_config.port
                 )))
                 .await;
         };
@@ -249,6 +322,15 @@ impl Client {
                             )
                             .await
                         }
+                        FunctionCode::ReadFifoQueue => {
+                            context.set_slave(Slave(*slave));
+                            Ok(fifo::read(
+                                context,
+                                op.start() as u16,
+                                Duration::from_millis(timeout_ms),
+                            )
+                            .await)
+                        }
                         _ => panic!("Invalid function code in operation."),
                     };
                     if let Ok(Ok(Ok(vec))) = modbus_result {
@@ -259,17 +341,42 @@ impl Client {
                                 end = op.end()
                             )))
                             .await;
-                        let mut memory = self.memory.lock().expect("Unable to lock memory");
-                        memory
-                            .write(*slave, Range::new(op.start(), op.start() + vec.len()), &vec)
-                            .panic(|e| format!("Failed to write to memory ({})", e));
-                        drop(memory);
+                        {
+                            let mut memory = self.memory.lock().expect("Unable to lock memory");
+                            let unit = Unit::new(self.connection, *slave);
+                            if *fc == FunctionCode::ReadFifoQueue {
+                                memory.record_fifo(unit, op.start() as u16, &vec);
+                            } else {
+                                let table = Table::from_function_code(*fc)
+                                    .expect("Invalid function code in operation.");
+                                memory
+                                    .write(
+                                        unit,
+                                        table,
+                                        Range::new(op.start(), op.start() + vec.len()),
+                                        &vec,
+                                    )
+                                    .panic(|e| format!("Failed to write to memory ({})", e));
+                            }
+                        }
                         op_idx = if op_idx + 1 == self.operations.len() {
                             0
                         } else {
                             op_idx + 1
                         };
                         retries = 0;
+                        if op_idx == 0 {
+                            if let Some(Err(e)) = self.recorder.as_ref().map(|r| {
+                                r.lock()
+                                    .expect("Unable to lock recorder")
+                                    .complete(self.connection)
+                            }) {
+                                let _ = self
+                                    .log_sender
+                                    .send(LogMsg::err(&format!("Failed to record values ({e})")))
+                                    .await;
+                            }
+                        }
                     } else {
                         retries += 1;
                         if retries > 3 {
@@ -279,6 +386,20 @@ impl Client {
                                 op_idx + 1
                             };
                             retries = 0;
+                            if op_idx == 0 {
+                                if let Some(Err(e)) = self.recorder.as_ref().map(|r| {
+                                    r.lock()
+                                        .expect("Unable to lock recorder")
+                                        .complete(self.connection)
+                                }) {
+                                    let _ = self
+                                        .log_sender
+                                        .send(LogMsg::err(&format!(
+                                            "Failed to record values ({e})"
+                                        )))
+                                        .await;
+                                }
+                            }
                         }
 
                         let _ = self.log_sender
@@ -288,10 +409,13 @@ impl Client {
                                 end = op.end()
                             )))
                             .await;
-                        let _ = self
-                            .status_sender
-                            .send(Status::String(str!("Modbus TCP disconnected.")))
-                            .await;
+                        let _ =
+                            self.status_sender
+                                .send(self.status(format!(
+                                    "{} disconnected.",
+                                    self.tcp_config.protocol()
+                                )))
+                                .await;
 
                         if let Err(e) = modbus_result {
                             let _ = self.log_sender.send(LogMsg::err(&format!("{:?}", e))).await;
@@ -311,13 +435,18 @@ impl Client {
                         Command::Disconnect => {
                             let _ = self
                                 .status_sender
-                                .send(Status::String(str!("Modbus TCP disconnected.")))
+                                .send(self.status(format!(
+                                    "{} disconnected.",
+                                    self.tcp_config.protocol()
+                                )))
                                 .await;
                             let _ = self
                                 .log_sender
                                 .send(LogMsg::ok(&format!(
-                                    "Modbus TCP disconnected from {}:{}",
-                                    self.tcp_config.ip, self.tcp_config.port
+                                    "{} disconnected from {}:{}",
+                                    self.tcp_config.protocol(),
+                                    self.tcp_config.ip,
+                                    self.tcp_config.port
                                 )))
                                 .await;
                             disconnect = true;
@@ -325,10 +454,10 @@ impl Client {
                         Command::Connect => {
                             reconnect = true;
                         }
-                        Command::WriteSingleCoil((slave, addr, coil, write_only)) => {
-                            context.set_slave(Slave(slave));
+                        Command::WriteSingleCoil((slave, table, addr, coil, write_only)) => {
+                            context.set_slave(Slave(slave.slave));
                             if let Err(e) = tokio::time::timeout(
-                                std::time::Duration::from_millis(timeout_ms),
+                                Duration::from_millis(timeout_ms),
                                 context.write_single_coil(addr, coil),
                             )
                             .await
@@ -348,22 +477,15 @@ impl Client {
                                     )))
                                     .await;
                                 if write_only {
-                                    let mut memory =
-                                        self.memory.lock().expect("Unable to lock memory");
-                                    memory
-                                        .write(
-                                            slave,
-                                            Range::new(addr, addr + 1),
-                                            &vec![if coil { 1 } else { 0 }],
-                                        )
-                                        .panic(|e| format!("Failed to write to memory ({})", e));
+                                    self.mirror(slave, table, addr, &[if coil { 1 } else { 0 }])
+                                        .await;
                                 }
                             }
                         }
-                        Command::WriteMultipleCoils((slave, addr, coils, write_only)) => {
-                            context.set_slave(Slave(slave));
+                        Command::WriteMultipleCoils((slave, table, addr, coils, write_only)) => {
+                            context.set_slave(Slave(slave.slave));
                             if let Err(e) = tokio::time::timeout(
-                                std::time::Duration::from_millis(timeout_ms),
+                                Duration::from_millis(timeout_ms),
                                 context.write_multiple_coils(addr, &coils),
                             )
                             .await
@@ -384,25 +506,18 @@ impl Client {
                                     .await;
 
                                 if write_only {
-                                    let mut memory =
-                                        self.memory.lock().expect("Unable to lock memory");
-                                    memory
-                                        .write(
-                                            slave,
-                                            Range::new(addr, addr + coils.len() as u16),
-                                            &coils
-                                                .iter()
-                                                .map(|c| if *c { 1 } else { 0 })
-                                                .collect::<Vec<u16>>(),
-                                        )
-                                        .panic(|e| format!("Failed to write to memory ({})", e));
+                                    let values = coils
+                                        .iter()
+                                        .map(|c| if *c { 1 } else { 0 })
+                                        .collect::<Vec<u16>>();
+                                    self.mirror(slave, table, addr, &values).await;
                                 }
                             }
                         }
-                        Command::WriteSingleRegister((slave, addr, value, write_only)) => {
-                            context.set_slave(Slave(slave));
+                        Command::WriteSingleRegister((slave, table, addr, value, write_only)) => {
+                            context.set_slave(Slave(slave.slave));
                             if let Err(e) = tokio::time::timeout(
-                                std::time::Duration::from_millis(timeout_ms),
+                                Duration::from_millis(timeout_ms),
                                 context.write_single_register(addr, value),
                             )
                             .await
@@ -422,18 +537,14 @@ impl Client {
                                     )))
                                     .await;
                                 if write_only {
-                                    let mut memory =
-                                        self.memory.lock().expect("Unable to lock memory");
-                                    memory
-                                        .write(slave, Range::new(addr, addr + 1), &vec![value])
-                                        .panic(|e| format!("Failed to write to memory ({})", e));
+                                    self.mirror(slave, table, addr, &[value]).await;
                                 }
                             }
                         }
-                        Command::WriteMultipleRegisters((slave, addr, vec, write_only)) => {
-                            context.set_slave(Slave(slave));
+                        Command::WriteMultipleRegisters((slave, table, addr, vec, write_only)) => {
+                            context.set_slave(Slave(slave.slave));
                             if let Err(e) = tokio::time::timeout(
-                                std::time::Duration::from_millis(timeout_ms),
+                                Duration::from_millis(timeout_ms),
                                 context.write_multiple_registers(addr, &vec),
                             )
                             .await
@@ -453,16 +564,191 @@ impl Client {
                                     )))
                                     .await;
                                 if write_only {
-                                    let mut memory =
-                                        self.memory.lock().expect("Unable to lock memory");
-                                    memory
-                                        .write(
+                                    self.mirror(slave, table, addr, &vec).await;
+                                }
+                            }
+                        }
+                        Command::MaskWriteRegister((
+                            slave,
+                            table,
+                            addr,
+                            and_mask,
+                            or_mask,
+                            write_only,
+                        )) => {
+                            context.set_slave(Slave(slave.slave));
+                            let failure = match tokio::time::timeout(
+                                Duration::from_millis(timeout_ms),
+                                context.masked_write_register(addr, and_mask, or_mask),
+                            )
+                            .await
+                            {
+                                Ok(Ok(Ok(()))) => {
+                                    let _ = self
+                                        .log_sender
+                                        .send(LogMsg::ok(&format!(
+                                            "Successfully masked address {addr} with AND {and_mask:#06X} and OR {or_mask:#06X}."
+                                        )))
+                                        .await;
+                                    if write_only {
+                                        let value = self
+                                            .memory
+                                            .lock()
+                                            .expect("Unable to lock memory")
+                                            .read(slave, table, &Range::new(addr, addr + 1))
+                                            .map(|v| *v[0])
+                                            .unwrap_or_default();
+                                        self.mirror(
                                             slave,
-                                            Range::new(addr, addr + vec.len() as u16),
-                                            &vec,
+                                            table,
+                                            addr,
+                                            &[frame::mask(value, and_mask, or_mask)],
                                         )
-                                        .panic(|e| format!("Failed to write to memory ({})", e));
+                                        .await;
+                                    }
+                                    None
+                                }
+                                Ok(Ok(Err(e))) => Some(format!("{e}")),
+                                Ok(Err(e)) => {
+                                    reconnect = true;
+                                    Some(format!("{e}"))
+                                }
+                                Err(e) => {
+                                    reconnect = true;
+                                    Some(format!("{e}"))
+                                }
+                            };
+                            if let Some(e) = failure {
+                                let _ = self
+                                    .log_sender
+                                    .send(LogMsg::err(&format!(
+                                        "Failed to mask address {addr} with AND {and_mask:#06X} and OR {or_mask:#06X} [{e}]."
+                                    )))
+                                    .await;
+                            }
+                        }
+                        Command::ReadDeviceIdentification(slave) => {
+                            context.set_slave(Slave(slave.slave));
+                            match ident::read(
+                                context,
+                                ReadCode::Extended,
+                                0,
+                                Duration::from_millis(timeout_ms),
+                            )
+                            .await
+                            {
+                                Ok(Ok(objects)) => {
+                                    for object in objects.iter() {
+                                        let _ = self
+                                            .log_sender
+                                            .send(LogMsg::ok(&format!(
+                                                "Slave: {}, {}",
+                                                slave,
+                                                ident::describe(object)
+                                            )))
+                                            .await;
+                                    }
+                                }
+                                Ok(Err(e)) => {
+                                    let _ = self
+                                        .log_sender
+                                        .send(LogMsg::err(&format!(
+                                            "Failed to read device identification of slave {slave} [{e}]."
+                                        )))
+                                        .await;
+                                }
+                                Err(e) => {
+                                    let _ = self
+                                        .log_sender
+                                        .send(LogMsg::err(&format!(
+                                            "Failed to read device identification of slave {slave} [{e}]."
+                                        )))
+                                        .await;
+                                    reconnect = true;
+                                }
+                            }
+                        }
+                        Command::ReadDiagnostics(slave) => {
+                            context.set_slave(Slave(slave.slave));
+                            match diag::report(context, Duration::from_millis(timeout_ms)).await {
+                                Ok(Ok(lines)) => {
+                                    for line in lines.iter() {
+                                        let _ = self
+                                            .log_sender
+                                            .send(LogMsg::ok(&format!(
+                                                "Slave: {}, {}",
+                                                slave, line
+                                            )))
+                                            .await;
+                                    }
+                                }
+                                Ok(Err(e)) => {
+                                    let _ = self
+                                        .log_sender
+                                        .send(LogMsg::err(&format!(
+                                            "Failed to read diagnostics of slave {slave} [{e}]."
+                                        )))
+                                        .await;
+                                }
+                                Err(e) => {
+                                    let _ = self
+                                        .log_sender
+                                        .send(LogMsg::err(&format!(
+                                            "Failed to read diagnostics of slave {slave} [{e}]."
+                                        )))
+                                        .await;
+                                    reconnect = true;
+                                }
+                            }
+                        }
+                        Command::ReadWriteMultipleRegisters((
+                            slave,
+                            table,
+                            read_addr,
+                            cnt,
+                            write_addr,
+                            vec,
+                            write_only,
+                        )) => {
+                            context.set_slave(Slave(slave.slave));
+                            let failure = match tokio::time::timeout(
+                                Duration::from_millis(timeout_ms),
+                                context.read_write_multiple_registers(
+                                    read_addr, cnt, write_addr, &vec,
+                                ),
+                            )
+                            .await
+                            {
+                                Ok(Ok(Ok(values))) => {
+                                    let _ = self
+                                        .log_sender
+                                        .send(LogMsg::ok(&format!(
+                                            "Successfully written address {write_addr} with values {vec:?} and read address {read_addr} with values {values:?}."
+                                        )))
+                                        .await;
+                                    if write_only {
+                                        self.mirror(slave, table, write_addr, &vec).await;
+                                    }
+                                    self.mirror(slave, table, read_addr, &values).await;
+                                    None
+                                }
+                                Ok(Ok(Err(e))) => Some(format!("{e}")),
+                                Ok(Err(e)) => {
+                                    reconnect = true;
+                                    Some(format!("{e}"))
                                 }
+                                Err(e) => {
+                                    reconnect = true;
+                                    Some(format!("{e}"))
+                                }
+                            };
+                            if let Some(e) = failure {
+                                let _ = self
+                                    .log_sender
+                                    .send(LogMsg::err(&format!(
+                                        "Failed to write address {write_addr} with values {vec:?} [{e}]."
+                                    )))
+                                    .await;
                             }
                         }
                     }
@@ -481,67 +767,57 @@ impl Client {
                 // Reset connection on error
                 if reconnect {
                     tokio::time::sleep(std::time::Duration::from_secs(2)).await;
-                    connection = if let Ok(r) = tokio::time::timeout(
-                        std::time::Duration::from_millis(timeout_ms),
-                        tokio_modbus::client::tcp::connect(addr),
-                    )
-                    .await
-                    {
-                        r.ok()
-                    } else {
-                        None
-                    };
+                    connection = self.connect(connect_timeout_ms).await;
                     if connection.is_some() {
                         let _ = self
                             .status_sender
-                            .send(Status::String(str!("Modbus TCP connected.")))
+                            .send(self.status(format!("{} connected.", self.tcp_config.protocol())))
                             .await;
                         let _ = self
                             .log_sender
                             .send(LogMsg::ok(&format!(
-                                "Modbus TCP reconnected successfully to {}:{}",
-                                self.tcp_config.ip, self.tcp_config.port
+                                "{} reconnected successfully to {}:{}",
+                                self.tcp_config.protocol(),
+                                self.tcp_config.ip,
+                                self.tcp_config.port
                             )))
                             .await;
                     } else {
                         let _ = self
                             .log_sender
                             .send(LogMsg::err(&format!(
-                                "Modbus TCP failed to reconnect to {}:{}",
-                                self.tcp_config.ip, self.tcp_config.port
+                                "{} failed to reconnect to {}:{}",
+                                self.tcp_config.protocol(),
+                                self.tcp_config.ip,
+                                self.tcp_config.port
                             )))
                             .await;
                     }
                 }
             } else if let Ok(Command::Connect) = self.cmd_receiver.try_recv() {
-                connection = if let Ok(r) = tokio::time::timeout(
-                    std::time::Duration::from_millis(timeout_ms),
-                    tokio_modbus::client::tcp::connect(addr),
-                )
-                .await
-                {
-                    r.ok()
-                } else {
-                    None
-                };
+                connection = self.connect(connect_timeout_ms).await;
                 if connection.is_some() {
                     let _ = self
                         .status_sender
-                        .send(Status::String(str!("Modbus TCP connected.")))
+                        .send(self.status(format!("{} connected.", self.tcp_config.protocol())))
                         .await;
                     let _ = self
                         .log_sender
                         .send(LogMsg::ok(&format!(
-                            "Modbus TCP connected successfully to {}:{}",
-                            self.tcp_config.ip, self.tcp_config.port
+                            "{} connected successfully to {}:{}",
+                            self.tcp_config.protocol(),
+                            self.tcp_config.ip,
+                            self.tcp_config.port
                         )))
                         .await;
                 } else {
                     let _ = self
                         .log_sender
                         .send(LogMsg::err(&format!(
-                            "Modbus TCP failed to connect to {}:{}",
-                            self.tcp_config.ip, self.tcp_config.port
+                            "{} failed to connect to {}:{}",
+                            self.tcp_config.protocol(),
+                            self.tcp_config.ip,
+                            self.tcp_config.port
                         )))
                         .await;
                 }
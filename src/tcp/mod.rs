@@ -1,9 +1,53 @@
 pub mod client;
 pub mod server;
+pub mod tls;
 
+use crate::oneshot::OneShot;
+use crate::tcp::tls::TlsConfig;
+use crate::udp;
 use crate::util::str;
 
 use clap::Args;
+use std::fmt::{Debug, Display};
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio_modbus::client::Context;
+use tokio_modbus::Slave;
+
+/// Transport of the Modbus messages selected by the subcommand
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Transport {
+    /// Modbus application protocol header on a TCP connection (Modbus TCP)
+    #[default]
+    Tcp,
+    /// Serial RTU frames including the checksum tunneled over TCP
+    RtuOverTcp,
+    /// Modbus TCP frames in UDP datagrams, resent up to `retries` times on timeout
+    Udp { retries: u8 },
+}
+
+impl Transport {
+    /// Time limit of a single operation of the client, which includes all retries
+    pub fn operation_timeout_ms(&self, timeout_ms: u64) -> u64 {
+        match self {
+            Transport::Udp { retries } => timeout_ms * (*retries as u64 + 1),
+            _ => timeout_ms,
+        }
+    }
+}
+
+impl Display for Transport {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Transport::Tcp => fmt.write_str("Modbus TCP"),
+            Transport::RtuOverTcp => fmt.write_str("Modbus RTU over TCP"),
+            Transport::Udp { .. } => fmt.write_str("Modbus UDP"),
+        }
+    }
+}
 
 #[derive(Clone, Debug, Default, Args)]
 pub struct TcpConfig {
@@ -14,4 +58,74 @@ pub struct TcpConfig {
     /// The port to use for the service or the port to connect to on target host.
     #[arg(short, long, default_value_t = 502)]
     pub port: u16,
+
+    /// Transport selected by the subcommand
+    #[arg(skip)]
+    pub transport: Transport,
+
+    #[command(flatten)]
+    pub tls: TlsConfig,
+
+    /// Execute a single operation instead of starting the application.
+    #[command(subcommand)]
+    pub oneshot: Option<OneShot>,
+}
+
+impl TcpConfig {
+    pub fn addr(&self) -> std::io::Result<SocketAddr> {
+        format!("{}:{}", self.ip, self.port)
+            .parse()
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e))
+    }
+
+    /// Name of the protocol used in status and log messages
+    pub fn protocol(&self) -> String {
+        if self.tls.tls {
+            format!("{} (TLS)", self.transport)
+        } else {
+            self.transport.to_string()
+        }
+    }
+
+    fn attach_slave<T>(&self, stream: T, slave: Slave) -> Context
+    where
+        T: AsyncRead + AsyncWrite + Debug + Unpin + Send + 'static,
+    {
+        match self.transport {
+            Transport::RtuOverTcp => tokio_modbus::client::rtu::attach_slave(stream, slave),
+            _ => tokio_modbus::client::tcp::attach_slave(stream, slave),
+        }
+    }
+
+    /// Connect to the server using the configured transport. The timeout only applies to the
+    /// datagrams of UDP.
+    pub async fn connect(&self, slave: Slave, timeout: Duration) -> std::io::Result<Context> {
+        let addr = self.addr()?;
+        match self.transport {
+            Transport::Tcp | Transport::RtuOverTcp => {
+                let stream = TcpStream::connect(addr).await?;
+                if !self.tls.tls {
+                    return Ok(self.attach_slave(stream, slave));
+                }
+                let stream = self
+                    .tls
+                    .connector()?
+                    .connect(self.tls.server_name(&self.ip)?, stream)
+                    .await?;
+                Ok(self.attach_slave(stream, slave))
+            }
+            Transport::Udp { retries } => {
+                let local: SocketAddr = if addr.is_ipv4() {
+                    "0.0.0.0:0"
+                } else {
+                    "[::]:0"
+                }
+                .parse()
+                .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+                let socket = UdpSocket::bind(local).await?;
+                socket.connect(addr).await?;
+                Ok(udp::client::attach_slave(socket, slave, timeout, retries))
+            }
+        }
+    }
 }
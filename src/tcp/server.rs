@@ -1,297 +1,23 @@
-use crate::mem::memory::{Memory, Range};
-use crate::tcp::TcpConfig;
+use crate::frame::Encoding;
+use crate::rtu::server::serve;
+use crate::service::Service;
+use crate::tcp::tls::HANDSHAKE_TIMEOUT;
+use crate::tcp::{TcpConfig, Transport};
 use crate::util::str;
 use crate::util::Expect;
 use crate::LogMsg;
 use crate::Status;
 
 use std::net::SocketAddr;
-use std::{
-    future,
-    sync::{Arc, Mutex},
-};
-use tokio::net::TcpListener;
+use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::mpsc::Sender;
-use tokio_modbus::prelude::{ExceptionCode, Request, Response, SlaveRequest};
 use tokio_modbus::server::tcp::{accept_tcp_connection, Server as TcpServer};
-
-struct Service {
-    memory: Arc<Mutex<Memory>>,
-    log_sender: Sender<LogMsg>,
-}
-
-fn refs_to_str(values: &[&u16]) -> String {
-    let mut s = str!("[ ");
-    for i in 0..values.len() {
-        if i == values.len() - 1 {
-            s += &format!("{:#06X} ", values[i]);
-        } else {
-            s += &format!("{:#06X}, ", values[i]);
-        }
-    }
-    s + "]"
-}
-
-fn to_str(values: &[u16]) -> String {
-    let mut s = str!("[ ");
-    for i in 0..values.len() {
-        if i == values.len() - 1 {
-            s += &format!("{:#06X} ", values[i]);
-        } else {
-            s += &format!("{:#06X}, ", values[i]);
-        }
-    }
-    s + "]"
-}
-
-impl tokio_modbus::server::Service for Service {
-    type Request = SlaveRequest<'static>;
-    type Exception = ExceptionCode;
-    type Response = Response;
-    type Future = future::Ready<Result<Response, ExceptionCode>>;
-
-    fn call(&self, req: Self::Request) -> Self::Future {
-        let SlaveRequest { slave, request } = req;
-        match request {
-            Request::ReadCoils(addr, cnt) => future::ready(
-                self.memory
-                    .lock()
-                    .expect("Unable to lock memory")
-                    .read(slave, &Range::new(addr, addr + cnt))
-                    .map_err(|e| {
-                        let _ = self.log_sender.try_send(LogMsg::err(&format!(
-                            "Slave: {}, ReadCoils: [{:#06X}, {:#06X}) ({})",
-                            slave,
-                            addr,
-                            addr + cnt,
-                            e
-                        )));
-                        Self::Exception::IllegalDataAddress
-                    })
-                    .map(|v| {
-                        let _ = self.log_sender.try_send(LogMsg::info(&format!(
-                            "Slave: {}, ReadCoils: [{:#06X}, {:#06X}) = {}",
-                            slave,
-                            addr,
-                            addr + cnt,
-                            refs_to_str(&v)
-                        )));
-                        Response::ReadCoils(v.into_iter().map(|b| *b != 0).collect())
-                    }),
-            ),
-            Request::ReadDiscreteInputs(addr, cnt) => future::ready(
-                self.memory
-                    .lock()
-                    .expect("Unable to lock memory")
-                    .read(slave, &Range::new(addr, addr + cnt))
-                    .map_err(|e| {
-                        let _ = self.log_sender.try_send(LogMsg::err(&format!(
-                            "Slave: {}, ReadDiscreteInputs: [{:#06X}, {:#06X}) ({})",
-                            slave,
-                            addr,
-                            addr + cnt,
-                            e
-                        )));
-                        Self::Exception::IllegalDataAddress
-                    })
-                    .map(|v| {
-                        let _ = self.log_sender.try_send(LogMsg::info(&format!(
-                            "Slave: {}, ReadDiscreteInputs: [{:#06X}, {:#06X}) = {}",
-                            slave,
-                            addr,
-                            addr + cnt,
-                            refs_to_str(&v)
-                        )));
-                        Response::ReadDiscreteInputs(v.into_iter().map(|b| *b != 0).collect())
-                    }),
-            ),
-            Request::ReadInputRegisters(addr, cnt) => future::ready(
-                self.memory
-                    .lock()
-                    .expect("Unable to lock memory")
-                    .read(slave, &Range::new(addr, addr + cnt))
-                    .map_err(|e| {
-                        let _ = self.log_sender.try_send(LogMsg::err(&format!(
-                            "Slave: {}, ReadInputRegisters: [{:#06X}, {:#06X}) ({})",
-                            slave,
-                            addr,
-                            addr + cnt,
-                            e
-                        )));
-                        Self::Exception::IllegalDataAddress
-                    })
-                    .map(|v| {
-                        let _ = self.log_sender.try_send(LogMsg::info(&format!(
-                            "Slave: {}, ReadInputRegisters: [{:#06X}, {:#06X}) = {}",
-                            slave,
-                            addr,
-                            addr + cnt,
-                            refs_to_str(&v)
-                        )));
-                        Response::ReadInputRegisters(v.into_iter().copied().collect())
-                    }),
-            ),
-            Request::ReadHoldingRegisters(addr, cnt) => future::ready(
-                self.memory
-                    .lock()
-                    .expect("Unable to lock memory")
-                    .read(slave, &Range::new(addr, addr + cnt))
-                    .map_err(|e| {
-                        let _ = self.log_sender.try_send(LogMsg::err(&format!(
-                            "Slave: {}, ReadHoldingRegisters: [{:#06X}, {:#06X}) ({})",
-                            slave,
-                            addr,
-                            addr + cnt,
-                            e
-                        )));
-                        Self::Exception::IllegalDataAddress
-                    })
-                    .map(|v| -> Response {
-                        let _ = self.log_sender.try_send(LogMsg::info(&format!(
-                            "Slave: {}, ReadHoldingRegisters: [{:#06X}, {:#06X}) = {}",
-                            slave,
-                            addr,
-                            addr + cnt,
-                            refs_to_str(&v)
-                        )));
-                        Response::ReadHoldingRegisters(v.into_iter().copied().collect())
-                    }),
-            ),
-            Request::WriteMultipleRegisters(addr, values) => future::ready(
-                self.memory
-                    .lock()
-                    .expect("Unable to lock memory")
-                    .write(
-                        slave,
-                        Range::new(addr, addr + (values.len() as u16)),
-                        &values,
-                    )
-                    .map_err(|e| {
-                        let _ = self.log_sender.try_send(LogMsg::err(&format!(
-                            "Slave: {}, WriteMultipleRegisters: [{:#06X}, {:#06X}) ({})",
-                            slave,
-                            addr,
-                            addr as usize + values.len(),
-                            e
-                        )));
-                        Self::Exception::IllegalDataAddress
-                    })
-                    .map(|_| {
-                        let _ = self.log_sender.try_send(LogMsg::info(&format!(
-                            "Slave: {}, WriteMultipleRegisters: [{:#06X}, {:#06X}) = {}",
-                            slave,
-                            addr,
-                            addr as usize + values.len(),
-                            to_str(&values)
-                        )));
-                        Response::WriteMultipleRegisters(addr, values.len() as u16)
-                    }),
-            ),
-            Request::WriteSingleRegister(addr, value) => future::ready(
-                self.memory
-                    .lock()
-                    .expect("Unable to lock memory")
-                    .write(slave, Range::new(addr, addr + 1), &[value])
-                    .map_err(|e| {
-                        let _ = self.log_sender.try_send(LogMsg::err(&format!(
-                            "Slave: {}, WriteSingleRegister: [{:#06X}, {:#06X}) ({})",
-                            slave,
-                            addr,
-                            addr + 1,
-                            e
-                        )));
-                        Self::Exception::IllegalDataAddress
-                    })
-                    .map(|_| {
-                        let _ = self.log_sender.try_send(LogMsg::info(&format!(
-                            "Slave: {}, WriteSingleRegister: [{:#06X}, {:#06X}) = {}",
-                            slave,
-                            addr,
-                            addr + 1,
-                            value
-                        )));
-                        Response::WriteSingleRegister(addr, value)
-                    }),
-            ),
-            Request::WriteMultipleCoils(addr, coils) => {
-                let values: Vec<u16> = coils.iter().map(|v| if *v { 1 } else { 0 }).collect();
-                future::ready(
-                    self.memory
-                        .lock()
-                        .expect("Unable to lock memory")
-                        .write(slave, Range::new(addr, addr + values.len() as u16), &values)
-                        .map_err(|e| {
-                            let _ = self.log_sender.try_send(LogMsg::err(&format!(
-                                "Slave: {}, WriteMultipleCoils: [{:#06X}, {:#06X}) ({})",
-                                slave,
-                                addr,
-                                addr + values.len() as u16,
-                                e
-                            )));
-                            Self::Exception::IllegalDataAddress
-                        })
-                        .map(|_| {
-                            let _ = self.log_sender.try_send(LogMsg::info(&format!(
-                                "Slave: {}, WriteMultipleCoils: [{:#06X}, {:#06X}) = {}",
-                                slave,
-                                addr,
-                                addr + values.len() as u16,
-                                to_str(&values)
-                            )));
-                            Response::WriteMultipleCoils(addr, values.len() as u16)
-                        }),
-                )
-            }
-            Request::WriteSingleCoil(addr, coil) => {
-                let value = if coil { 1 } else { 0 };
-                future::ready(
-                    self.memory
-                        .lock()
-                        .expect("Unable to lock memory")
-                        .write(slave, Range::new(addr, addr + 1), &[value])
-                        .map_err(|e| {
-                            let _ = self.log_sender.try_send(LogMsg::err(&format!(
-                                "Slave: {}, WriteSingleCoil: [{:#06X}, {:#06X}) ({})",
-                                slave,
-                                addr,
-                                addr + 1,
-                                e
-                            )));
-                            Self::Exception::IllegalDataAddress
-                        })
-                        .map(|_| {
-                            let _ = self.log_sender.try_send(LogMsg::info(&format!(
-                                "Slave: {}, WriteSingleCoil: [{:#06X}, {:#06X}) = {}",
-                                slave,
-                                addr,
-                                addr + 1,
-                                value
-                            )));
-                            Response::WriteSingleCoil(addr, coil)
-                        }),
-                )
-            }
-            _ => {
-                let _ = self
-                    .log_sender
-                    .try_send(LogMsg::err(
-                        &format!("Slave: {} (Illegal Function)", slave,),
-                    ));
-                future::ready(Err(Self::Exception::IllegalFunction))
-            }
-        }
-    }
-}
-
-impl Service {
-    pub fn new(memory: Arc<Mutex<Memory>>, log_sender: Sender<LogMsg>) -> Self {
-        Self { memory, log_sender }
-    }
-}
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
 
 pub struct Server {
     config: TcpConfig,
-    memory: Arc<Mutex<Memory>>,
+    service: Service,
     status_sender: Sender<Status>,
     log_sender: Sender<LogMsg>,
 }
@@ -299,22 +25,39 @@ pub struct Server {
 impl Server {
     pub fn new(
         config: TcpConfig,
-        memory: Arc<Mutex<Memory>>,
+        service: Service,
         status_sender: Sender<Status>,
         log_sender: Sender<LogMsg>,
     ) -> Self {
         Self {
             config,
-            memory,
+            service,
             status_sender,
             log_sender,
         }
     }
 
+    async fn not_running(&self, msg: &str) {
+        let _ = self
+            .status_sender
+            .send(Status::String(str!("Server not running.")))
+            .await;
+        let _ = self.log_sender.send(LogMsg::err(msg)).await;
+    }
+
     pub async fn run(&self) {
         let addr: SocketAddr = format!("{}:{}", self.config.ip, self.config.port)
             .parse()
             .panic(|e| format!("Failed to create SocketAddr ({e})"));
+        let acceptor = match self.config.tls.tls.then(|| self.config.tls.acceptor()) {
+            Some(Ok(acceptor)) => Some(acceptor),
+            Some(Err(e)) => {
+                return self
+                    .not_running(&format!("Invalid TLS configuration ({e})"))
+                    .await;
+            }
+            None => None,
+        };
         if let Ok(listener) = TcpListener::bind(addr).await {
             let _ = self
                 .log_sender
@@ -323,37 +66,105 @@ impl Server {
                     self.config.ip, self.config.port
                 )))
                 .await;
+            if self.config.transport == Transport::RtuOverTcp {
+                return self.serve_rtu(listener, acceptor).await;
+            }
             let server = TcpServer::new(listener);
-            let new_request_handler = |_socket_addr| {
-                Ok(Some(Service::new(
-                    self.memory.clone(),
-                    self.log_sender.clone(),
-                )))
-            };
-            let on_connected = |stream, socket_addr| async move {
-                accept_tcp_connection(stream, socket_addr, new_request_handler)
-            };
             let on_process_log = self.log_sender.clone();
             let on_process_error = move |err| {
                 let _ = on_process_log
                     .try_send(LogMsg::err(&format!("Server processing failed. [{}]", err)));
             };
-            server
-                .serve(&on_connected, on_process_error)
-                .await
-                .panic(|e| format!("Serve server failed [{}]", e));
+            let result = match acceptor {
+                Some(acceptor) => {
+                    let acceptor = &acceptor;
+                    let on_connected = |stream, peer| async move {
+                        Ok(handshake(acceptor, stream, peer, &self.log_sender)
+                            .await
+                            .map(|stream| (self.service.clone(), stream)))
+                    };
+                    server.serve(&on_connected, on_process_error).await
+                }
+                None => {
+                    let new_request_handler = |_socket_addr| Ok(Some(self.service.clone()));
+                    let on_connected = |stream, socket_addr| async move {
+                        accept_tcp_connection(stream, socket_addr, new_request_handler)
+                    };
+                    server.serve(&on_connected, on_process_error).await
+                }
+            };
+            result.panic(|e| format!("Serve server failed [{}]", e));
         } else {
-            let _ = self
-                .status_sender
-                .send(Status::String(str!("Server not running.")))
-                .await;
-            let _ = self
-                .log_sender
-                .send(LogMsg::err(&format!(
-                    "Failed to bind to address {}:{}. Please restart.",
-                    self.config.ip, self.config.port
-                )))
-                .await;
+            self.not_running(&format!(
+                "Failed to bind to address {}:{}. Please restart.",
+                self.config.ip, self.config.port
+            ))
+            .await;
         }
     }
+
+    /// Accept connections exchanging serial RTU frames and serve each of them until closed
+    async fn serve_rtu(&self, listener: TcpListener, acceptor: Option<TlsAcceptor>) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    let service = self.service.clone();
+                    let log_sender = self.log_sender.clone();
+                    let acceptor = acceptor.clone();
+                    tokio::spawn(async move {
+                        let result = match acceptor {
+                            Some(acceptor) => match handshake(&acceptor, stream, peer, &log_sender)
+                                .await
+                            {
+                                Some(stream) => {
+                                    serve(stream, service, log_sender.clone(), Encoding::Rtu).await
+                                }
+                                None => return,
+                            },
+                            None => serve(stream, service, log_sender.clone(), Encoding::Rtu).await,
+                        };
+                        if let Err(e) = result {
+                            let _ = log_sender
+                                .send(LogMsg::err(&format!("Server processing failed. [{}]", e)))
+                                .await;
+                        }
+                        let _ = log_sender
+                            .send(LogMsg::info(&format!("Connection to {} closed.", peer)))
+                            .await;
+                    });
+                }
+                Err(e) => {
+                    let _ = self
+                        .log_sender
+                        .send(LogMsg::err(&format!(
+                            "Failed to accept connection. [{}]",
+                            e
+                        )))
+                        .await;
+                }
+            }
+        }
+    }
+}
+
+/// Establish the TLS session of an accepted connection. Failed handshakes are logged and the
+/// connection is dropped.
+async fn handshake(
+    acceptor: &TlsAcceptor,
+    stream: TcpStream,
+    peer: SocketAddr,
+    log_sender: &Sender<LogMsg>,
+) -> Option<TlsStream<TcpStream>> {
+    let failure = match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+        Ok(Ok(stream)) => return Some(stream),
+        Ok(Err(e)) => e.to_string(),
+        Err(_) => str!("Timeout"),
+    };
+    let _ = log_sender
+        .send(LogMsg::err(&format!(
+            "TLS handshake with {} failed. [{}]",
+            peer, failure
+        )))
+        .await;
+    None
 }
@@ -0,0 +1,125 @@
+use crate::util::str;
+
+use clap::Args;
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// Time a client may take to complete the TLS handshake
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Modbus/TCP Security (Modbus TCP inside a TLS session, usually on port 802)
+#[derive(Clone, Debug, Default, Args)]
+pub struct TlsConfig {
+    /// Secure the connection with TLS (Modbus/TCP Security).
+    #[arg(long, default_value_t = false)]
+    pub tls: bool,
+
+    /// CA certificate (PEM) used to verify the certificate of the peer.
+    #[arg(long, value_name = "FILE", requires = "tls")]
+    pub ca: Option<String>,
+
+    /// Certificate chain (PEM) presented to the peer. Required in server mode.
+    #[arg(long, value_name = "FILE", requires_all = ["tls", "key"])]
+    pub cert: Option<String>,
+
+    /// Private key (PEM) of the certificate.
+    #[arg(long, value_name = "FILE", requires_all = ["tls", "cert"])]
+    pub key: Option<String>,
+
+    /// Reject clients without a certificate signed by the CA (only server mode).
+    #[arg(long, default_value_t = false, requires = "ca")]
+    pub client_auth: bool,
+
+    /// Name expected in the certificate of the server. Defaults to the ip (only client mode).
+    #[arg(long, requires = "tls")]
+    pub server_name: Option<String>,
+}
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, msg)
+}
+
+fn load_certs(path: &str) -> std::io::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid(format!("Failed to read certificates from {path} ({e})")))?;
+    if certs.is_empty() {
+        return Err(invalid(format!("No certificate found in {path}")));
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &str) -> std::io::Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path)
+        .map_err(|e| invalid(format!("Failed to read private key from {path} ({e})")))
+}
+
+fn load_roots(path: &str) -> std::io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots
+            .add(cert)
+            .map_err(|e| invalid(format!("Invalid CA certificate in {path} ({e})")))?;
+    }
+    Ok(roots)
+}
+
+impl TlsConfig {
+    /// Client side of the session. The server is verified against the CA, the own certificate is
+    /// only presented if configured.
+    pub fn connector(&self) -> std::io::Result<TlsConnector> {
+        let ca = self.ca.as_ref().ok_or_else(|| {
+            invalid(str!(
+                "A CA certificate (--ca) is required to verify the server"
+            ))
+        })?;
+        let builder = ClientConfig::builder().with_root_certificates(load_roots(ca)?);
+        let config = match (&self.cert, &self.key) {
+            (Some(cert), Some(key)) => builder
+                .with_client_auth_cert(load_certs(cert)?, load_key(key)?)
+                .map_err(|e| invalid(format!("Invalid client certificate ({e})")))?,
+            _ => builder.with_no_client_auth(),
+        };
+        Ok(TlsConnector::from(Arc::new(config)))
+    }
+
+    /// Server side of the session. Client certificates are verified against the CA if given and
+    /// required if `client_auth` is set.
+    pub fn acceptor(&self) -> std::io::Result<TlsAcceptor> {
+        let (Some(cert), Some(key)) = (&self.cert, &self.key) else {
+            return Err(invalid(str!(
+                "A certificate (--cert) and private key (--key) are required in server mode"
+            )));
+        };
+        let builder = match &self.ca {
+            Some(ca) => {
+                let verifier = WebPkiClientVerifier::builder(Arc::new(load_roots(ca)?));
+                let verifier = if self.client_auth {
+                    verifier.build()
+                } else {
+                    verifier.allow_unauthenticated().build()
+                }
+                .map_err(|e| invalid(format!("Invalid CA certificate ({e})")))?;
+                ServerConfig::builder().with_client_cert_verifier(verifier)
+            }
+            None => ServerConfig::builder().with_no_client_auth(),
+        };
+        let config = builder
+            .with_single_cert(load_certs(cert)?, load_key(key)?)
+            .map_err(|e| invalid(format!("Invalid server certificate ({e})")))?;
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+
+    /// Name the certificate of the server is checked against
+    pub fn server_name(&self, ip: &str) -> std::io::Result<ServerName<'static>> {
+        let name = self.server_name.as_deref().unwrap_or(ip);
+        ServerName::try_from(name.to_string())
+            .map_err(|e| invalid(format!("Invalid server name {name} ({e})")))
+    }
+}
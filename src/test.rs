@@ -1,19 +1,58 @@
 #[cfg(test)]
 mod tests {
+    use crate::capture::{self, Capture};
+    use crate::frame;
+    use crate::gateway::{Target, Upstream};
+    #[cfg(feature = "f128")]
     use crate::mem::data::DataType;
-    use crate::mem::memory::{Memory, Range};
+    #[cfg(not(feature = "f128"))]
+    use crate::mem::datav2::DataType;
+    use crate::mem::memory::{Memory, Range, Table};
     use crate::mem::register::{AccessType, Definition, Handler};
+    use crate::record::{RecordFormat, Recorder};
+    use crate::service::Service;
     use crate::AppConfig;
+    use futures_util::FutureExt;
     use std::collections::HashMap;
     use std::sync::{Arc, Mutex};
+    use tokio::sync::mpsc::channel;
+    use tokio_modbus::prelude::{
+        ExceptionCode, Reader, Request, Response, Slave, SlaveId, SlaveRequest, Writer,
+    };
+    use tokio_modbus::server::Service as _;
+
+    /// Service of a server on the memory and configuration, the log is discarded
+    fn service(memory: Arc<Mutex<Memory>>, config: Arc<Mutex<AppConfig>>) -> Service {
+        let (log_sender, _) = channel(10);
+        Service::new(config, memory, log_sender, None)
+    }
+
+    /// Process a request that is answered without waiting
+    fn call(
+        service: &Service,
+        slave: SlaveId,
+        request: Request<'static>,
+    ) -> Result<Option<Response>, ExceptionCode> {
+        service
+            .call(SlaveRequest { slave, request })
+            .now_or_never()
+            .expect("Request still pending")
+    }
 
     #[test]
     fn overlap() {
         let mut memory = Memory::new();
-        memory.init(0, &[Range::new(0u16, 40u16)]);
+        memory.init(0, Table::HoldingRegisters, &[Range::new(0u16, 40u16)]);
         let values: Vec<u16> = (17..34).map(|x| x as u16).collect();
-        let _ = memory.write(0, Range::new(17u16, 34u16), &values);
-        let vals = memory.read(0, &Range::new(16u16, 35u16)).unwrap();
+        let _ = memory.write(
+            0,
+            Table::HoldingRegisters,
+            Range::new(17u16, 34u16),
+            &values,
+        );
+        let vals = memory
+            .read(0, Table::HoldingRegisters, &Range::new(16u16, 35u16))
+            .unwrap();
 
         assert_eq!(
             vals.into_iter().copied().collect::<Vec<_>>(),
@@ -24,10 +63,17 @@ mod tests {
     #[test]
     fn no_overlap() {
         let mut memory = Memory::new();
-        memory.init(0, &[Range::new(0u16, 40u16)]);
+        memory.init(0, Table::HoldingRegisters, &[Range::new(0u16, 40u16)]);
         let values: Vec<u16> = (17..34).map(|x| x as u16).collect();
-        let _ = memory.write(0, Range::new(17u16, 34u16), &values);
-        let vals = memory.read(0, &Range::new(16u16, 35u16)).unwrap();
+        let _ = memory.write(
+            0,
+            Table::HoldingRegisters,
+            Range::new(17u16, 34u16),
+            &values,
+        );
+        let vals = memory
+            .read(0, Table::HoldingRegisters, &Range::new(16u16, 35u16))
+            .unwrap();
 
         assert_eq!(
             vals.into_iter().copied().collect::<Vec<_>>(),
@@ -35,15 +81,46 @@ mod tests {
         )
     }
 
+    #[test]
+    fn separate_tables() {
+        let mut memory = Memory::new();
+        memory.init(0, Table::Coils, &[Range::new(0u16, 8u16)]);
+        memory.init(0, Table::HoldingRegisters, &[Range::new(0u16, 8u16)]);
+        let _ = memory.write(0, Table::Coils, Range::new(5u16, 6u16), &[1]);
+        let _ = memory.write(
+            0,
+            Table::HoldingRegisters,
+            Range::new(5u16, 6u16),
+            &[0x1234],
+        );
+
+        let coils = memory
+            .read(0, Table::Coils, &Range::new(5u16, 6u16))
+            .unwrap();
+        assert_eq!(coils.into_iter().copied().collect::<Vec<_>>(), vec![1]);
+        let registers = memory
+            .read(0, Table::HoldingRegisters, &Range::new(5u16, 6u16))
+            .unwrap();
+        assert_eq!(
+            registers.into_iter().copied().collect::<Vec<_>>(),
+            vec![0x1234]
+        );
+        let inputs = memory
+            .read(0, Table::InputRegisters, &Range::new(5u16, 6u16))
+            .unwrap();
+        assert_eq!(inputs.into_iter().copied().collect::<Vec<_>>(), vec![0]);
+    }
+
     #[test]
     fn register() {
         let mut memory = Memory::new();
-        memory.init(0, &[Range::new(0u16, 4096u16)]);
+        memory.init(0, Table::InputRegisters, &[Range::new(0u16, 4096u16)]);
         let memory = Arc::new(Mutex::new(memory));
         let mut definitions: HashMap<String, Definition> = HashMap::new();
         definitions.insert(
             "Name".to_owned(),
             Definition::new(
+                None,
                 None,
                 0,
                 2,
@@ -54,12 +131,1767 @@ mod tests {
                 None,
                 None,
                 None,
+                None,
+                None,
             ),
         );
         let config = Arc::new(Mutex::new(AppConfig::default()));
         let mut register = Handler::new(config, memory);
         register
-            .set_values(0, 1234, &[0x1234, 0x2345])
+            .set_values(0, Table::InputRegisters, 1234, &[0x1234, 0x2345])
             .expect("Set values failed");
     }
+
+    #[test]
+    fn access_violation() {
+        let mut memory = Memory::new();
+        memory.init(0, Table::HoldingRegisters, &[Range::new(0u16, 8u16)]);
+        let memory = Arc::new(Mutex::new(memory));
+        let mut config = AppConfig::default();
+        for (name, address, access) in [
+            ("ReadOnly", 0, AccessType::ReadOnly),
+            ("WriteOnly", 4, AccessType::WriteOnly),
+        ] {
+            config.definitions.insert(
+                name.to_owned(),
+                Definition::new(
+                    None,
+                    None,
+                    address,
+                    2,
+                    DataType::default(),
+                    0x03u8,
+                    access,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                ),
+            );
+        }
+        let (log_sender, _log_receiver) = channel(10);
+        let service = Service::new(Arc::new(Mutex::new(config)), memory, log_sender, None);
+        let call = |request| {
+            service
+                .call(SlaveRequest { slave: 0, request })
+                .now_or_never()
+                .expect("Request still pending")
+        };
+
+        assert_eq!(
+            call(Request::WriteSingleRegister(1, 0x1234)),
+            Err(ExceptionCode::IllegalDataAddress)
+        );
+        assert_eq!(
+            call(Request::ReadHoldingRegisters(3, 2)),
+            Err(ExceptionCode::IllegalDataAddress)
+        );
+        assert_eq!(
+            call(Request::ReadHoldingRegisters(0, 2)),
+            Ok(Some(Response::ReadHoldingRegisters(vec![0, 0])))
+        );
+        assert_eq!(
+            call(Request::WriteSingleRegister(5, 0x1234)),
+            Ok(Some(Response::WriteSingleRegister(5, 0x1234)))
+        );
+        assert_eq!(
+            call(Request::MaskWriteRegister(1, 0xFF00, 0x0012)),
+            Err(ExceptionCode::IllegalDataAddress)
+        );
+        assert_eq!(
+            call(Request::MaskWriteRegister(5, 0xFF00, 0x0012)),
+            Ok(Some(Response::MaskWriteRegister(5, 0xFF00, 0x0012)))
+        );
+    }
+
+    #[test]
+    fn strict_address_map() {
+        let memory = Arc::new(Mutex::new(Memory::new()));
+        let mut config = AppConfig::default();
+        config.definitions.insert(
+            "Name".to_owned(),
+            Definition::new(
+                None,
+                None,
+                2,
+                2,
+                DataType::default(),
+                0x03u8,
+                AccessType::ReadWrite,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            ),
+        );
+        config.server = Some(serde_json::from_str(r#"{ "strict": true }"#).unwrap());
+        memory
+            .lock()
+            .unwrap()
+            .init(0, Table::HoldingRegisters, &[Range::new(0u16, 8u16)]);
+        let (log_sender, _log_receiver) = channel(10);
+        let service = Service::new(Arc::new(Mutex::new(config)), memory, log_sender, None);
+        let call = |request| {
+            service
+                .call(SlaveRequest { slave: 0, request })
+                .now_or_never()
+                .expect("Request still pending")
+        };
+
+        assert_eq!(
+            call(Request::ReadHoldingRegisters(2, 2)),
+            Ok(Some(Response::ReadHoldingRegisters(vec![0, 0])))
+        );
+        assert_eq!(
+            call(Request::ReadHoldingRegisters(1, 2)),
+            Err(ExceptionCode::IllegalDataAddress)
+        );
+        assert_eq!(
+            call(Request::WriteSingleRegister(4, 0x1234)),
+            Err(ExceptionCode::IllegalDataAddress)
+        );
+        assert_eq!(
+            call(Request::ReadCoils(2, 1)),
+            Err(ExceptionCode::IllegalDataAddress)
+        );
+    }
+
+    #[test]
+    fn fault_injection() {
+        let mut memory = Memory::new();
+        memory.init(0, Table::HoldingRegisters, &[Range::new(0u16, 8u16)]);
+        memory.init(2, Table::HoldingRegisters, &[Range::new(0u16, 8u16)]);
+        let memory = Arc::new(Mutex::new(memory));
+        let config = AppConfig {
+            server: Some(
+            serde_json::from_str(
+                r#"{
+                    "faults": [
+                        { "slave_id": 0, "range": { "start": 4, "end": 6 }, "every": 2, "action": { "Exception": 4 } },
+                        { "slave_id": 2, "action": "Drop" }
+                    ]
+                }"#,
+            )
+            .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let config = Arc::new(Mutex::new(config));
+        let (log_sender, _log_receiver) = channel(10);
+        let service = Service::new(config.clone(), memory, log_sender, None);
+        let call = |slave, request| {
+            service
+                .call(SlaveRequest { slave, request })
+                .now_or_never()
+                .expect("Request still pending")
+        };
+
+        assert_eq!(
+            call(0, Request::ReadHoldingRegisters(0, 2)),
+            Ok(Some(Response::ReadHoldingRegisters(vec![0, 0])))
+        );
+        assert_eq!(
+            call(0, Request::ReadHoldingRegisters(4, 1)),
+            Ok(Some(Response::ReadHoldingRegisters(vec![0])))
+        );
+        assert_eq!(
+            call(0, Request::ReadHoldingRegisters(4, 1)),
+            Err(ExceptionCode::ServerDeviceFailure)
+        );
+        assert_eq!(call(2, Request::ReadHoldingRegisters(0, 1)), Ok(None));
+
+        config
+            .lock()
+            .unwrap()
+            .server
+            .as_mut()
+            .unwrap()
+            .toggle_fault_injection();
+        assert_eq!(
+            call(2, Request::ReadHoldingRegisters(0, 1)),
+            Ok(Some(Response::ReadHoldingRegisters(vec![0])))
+        );
+    }
+
+    #[test]
+    fn rtu_framing() {
+        let mut memory = Memory::new();
+        memory.init(1, Table::HoldingRegisters, &[Range::new(0u16, 8u16)]);
+        let _ = memory.write(
+            1,
+            Table::HoldingRegisters,
+            Range::new(2u16, 4u16),
+            &[0x1234, 0x5678],
+        );
+        let memory = Arc::new(Mutex::new(memory));
+        let config = AppConfig {
+            server: Some(
+                serde_json::from_str(
+                    r#"{ "faults": [ { "read_code": 4, "action": "CorruptCrc" } ] }"#,
+                )
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let (log_sender, _log_receiver) = channel(10);
+        let service = Service::new(
+            Arc::new(Mutex::new(config)),
+            memory,
+            log_sender.clone(),
+            None,
+        );
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async move {
+            let (client, server) = tokio::io::duplex(256);
+            tokio::spawn(crate::rtu::server::serve(
+                server,
+                service,
+                log_sender,
+                frame::Encoding::Rtu,
+            ));
+            let mut ctx = tokio_modbus::client::rtu::attach_slave(client, Slave(1));
+            assert_eq!(
+                ctx.read_holding_registers(2, 2).await.unwrap(),
+                Ok(vec![0x1234, 0x5678])
+            );
+            assert_eq!(
+                ctx.write_multiple_registers(0, &[1, 2]).await.unwrap(),
+                Ok(())
+            );
+            assert_eq!(ctx.read_holding_registers(8, 1).await.unwrap(), Ok(vec![0]));
+            let corrupted = tokio::time::timeout(
+                std::time::Duration::from_millis(200),
+                ctx.read_input_registers(0, 1),
+            )
+            .await;
+            assert!(!matches!(corrupted, Ok(Ok(Ok(_)))));
+        });
+    }
+
+    #[test]
+    fn headless() {
+        use crate::headless::{Headless, LogFormat};
+        use crate::LogMsg;
+
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let msg = LogMsg::info("Connected");
+        Headless::new(LogFormat::Plain).write(&msg, &mut out, &mut err);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("{} - Connected\n", msg.timestamp())
+        );
+
+        let mut out = Vec::new();
+        let json = Headless::new(LogFormat::Json);
+        json.write(&msg, &mut out, &mut err);
+        let line: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(line["timestamp"], msg.timestamp());
+        assert_eq!(line["level"], "info");
+        assert_eq!(line["message"], "Connected");
+        assert!(err.is_empty());
+
+        // Errors are only written to stderr
+        let mut out = Vec::new();
+        json.write(&LogMsg::err("Timeout"), &mut out, &mut err);
+        assert!(out.is_empty());
+        let line: serde_json::Value = serde_json::from_slice(&err).unwrap();
+        assert_eq!(line["level"], "err");
+        assert_eq!(line["message"], "Timeout");
+    }
+
+    #[test]
+    fn oneshot() {
+        use crate::oneshot::{self, Operation, Status, Target};
+        use std::time::Duration;
+        use tokio_modbus::prelude::ExceptionCode;
+
+        assert_eq!(oneshot::parse_address("0x4000"), Ok(0x4000));
+        assert_eq!(oneshot::parse_address("16"), Ok(16));
+        assert!(oneshot::parse_address("0xXY").is_err());
+        assert!(oneshot::parse_address("70000").is_err());
+        assert_eq!(oneshot::parse_raw("0x1234,12").unwrap(), vec![0x1234, 12]);
+        assert_eq!(
+            oneshot::parse_raw("1 0 true,false").unwrap(),
+            vec![1, 0, 1, 0]
+        );
+        assert!(oneshot::parse_raw("1,on").is_err());
+
+        let config = AppConfig {
+            definitions: HashMap::from([(
+                String::from("Voltage"),
+                serde_json::from_str(
+                    r#"{ "slave_id": 3, "address": 16, "length": 2, "type": "U32",
+                         "read_code": 4, "access": "ReadOnly" }"#,
+                )
+                .unwrap(),
+            )]),
+            ..Default::default()
+        };
+        let target = |fc: Option<u8>, r#type: Option<&str>, len: Option<u16>| Target {
+            name: None,
+            slave: None,
+            fc,
+            addr: Some(0x4000),
+            len,
+            r#type: r#type.map(String::from),
+            reverse: false,
+        };
+        let resolve = |target: Target| Operation::resolve(&target, &config, 1);
+
+        // The length defaults to the size of the type
+        let op = resolve(target(Some(3), Some("F32"), None)).unwrap();
+        assert_eq!(
+            (op.slave, op.table, op.addr),
+            (1, Table::HoldingRegisters, 0x4000)
+        );
+        assert_eq!(op.len, Some(2));
+        assert_eq!(
+            resolve(target(Some(3), Some("F64"), None)).unwrap().len,
+            Some(4)
+        );
+        assert_eq!(
+            resolve(target(Some(3), Some("F32"), Some(6))).unwrap().len,
+            Some(6)
+        );
+        assert_eq!(resolve(target(Some(1), None, None)).unwrap().len, None);
+        assert!(resolve(target(None, None, None)).is_err());
+        assert!(resolve(target(Some(8), None, None)).is_err());
+        assert!(resolve(Target {
+            addr: None,
+            ..target(Some(3), None, None)
+        })
+        .is_err());
+
+        // Explicit options take precedence over the definition
+        let named = |name: &str| Target {
+            name: Some(String::from(name)),
+            ..target(None, None, None)
+        };
+        let op = resolve(named("Voltage")).unwrap();
+        assert_eq!(
+            (op.slave, op.table, op.addr),
+            (3, Table::InputRegisters, 0x4000)
+        );
+        assert_eq!(op.len, Some(2));
+        assert!(resolve(named("Current")).is_err());
+
+        let op = resolve(target(Some(3), Some("F32"), None)).unwrap();
+        assert_eq!(oneshot::encode(&op, "1.5"), Ok(vec![0x3FC0, 0x0000]));
+        assert_eq!(
+            oneshot::decode(&op, &[0x3FC0, 0x0000]),
+            Ok(String::from("1.5"))
+        );
+        assert_eq!(
+            oneshot::decode(&op, &[0x3FC0]).unwrap_err().0,
+            Status::InvalidArguments
+        );
+        assert_eq!(
+            oneshot::encode(&op, "abc").unwrap_err().0,
+            Status::InvalidArguments
+        );
+        // Coils and untyped registers use the raw values
+        let op = resolve(target(Some(1), Some("F32"), None)).unwrap();
+        assert_eq!(oneshot::encode(&op, "1,0,1"), Ok(vec![1, 0, 1]));
+        let op = resolve(target(Some(3), None, None)).unwrap();
+        assert_eq!(oneshot::decode(&op, &[1, 0x10]), Ok(String::from("1 16")));
+        let op = Operation {
+            bit: Some(4),
+            ..resolve(target(Some(3), None, None)).unwrap()
+        };
+        assert_eq!(oneshot::decode(&op, &[0x0010]), Ok(String::from("1")));
+
+        // Exit codes of the operations
+        assert_eq!(
+            [
+                Status::Success,
+                Status::InvalidArguments,
+                Status::ConnectionFailed,
+                Status::Exception,
+                Status::Mismatch
+            ]
+            .map(|s| s as i32),
+            [0, 1, 2, 3, 4]
+        );
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let timeout = Duration::from_millis(10);
+        runtime.block_on(async {
+            assert_eq!(oneshot::call(timeout, async { Ok(Ok(5)) }).await, Ok(5));
+            assert_eq!(
+                oneshot::call::<()>(timeout, async {
+                    Ok(Err(ExceptionCode::IllegalDataAddress))
+                })
+                .await
+                .unwrap_err()
+                .0,
+                Status::Exception
+            );
+            assert_eq!(
+                oneshot::call::<()>(timeout, async {
+                    Err(std::io::Error::from(std::io::ErrorKind::BrokenPipe).into())
+                })
+                .await
+                .unwrap_err()
+                .0,
+                Status::ConnectionFailed
+            );
+            assert_eq!(
+                oneshot::call::<()>(timeout, std::future::pending())
+                    .await
+                    .unwrap_err(),
+                (Status::ConnectionFailed, String::from("Timeout"))
+            );
+        });
+    }
+
+    #[test]
+    fn record_csv() {
+        let mut memory = Memory::new();
+        memory.init(0, Table::HoldingRegisters, &[Range::new(0u16, 8u16)]);
+        let _ = memory.write(0, Table::HoldingRegisters, Range::new(2u16, 3u16), &[42]);
+        let memory = Arc::new(Mutex::new(memory));
+        let mut config = AppConfig::default();
+        config.definitions.insert(
+            "Name, with comma".to_owned(),
+            Definition::new(
+                None,
+                None,
+                2,
+                1,
+                DataType::default(),
+                0x03u8,
+                AccessType::ReadWrite,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            ),
+        );
+        let path = std::env::temp_dir().join(format!("record_{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+        let mut recorder = Recorder::new(
+            path,
+            RecordFormat::from_path(path),
+            Arc::new(Mutex::new(config)),
+            memory,
+        )
+        .unwrap();
+        recorder.record().unwrap();
+        recorder.record().unwrap();
+
+        let content = std::fs::read_to_string(path).unwrap();
+        let _ = std::fs::remove_file(path);
+        let lines: Vec<_> = content.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "timestamp,\"Name, with comma\"");
+        assert!(lines[1].ends_with(",42"));
+    }
+
+    #[test]
+    fn capture() {
+        let mut memory = Memory::new();
+        memory.init(1, Table::HoldingRegisters, &[Range::new(0u16, 8u16)]);
+        let memory = Arc::new(Mutex::new(memory));
+        let config = AppConfig {
+            server: Some(
+                serde_json::from_str(r#"{ "faults": [ { "read_code": 4, "action": "Drop" } ] }"#)
+                    .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let path = std::env::temp_dir().join(format!("capture_{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let (log_sender, _log_receiver) = channel(10);
+        let service = Service::new(
+            Arc::new(Mutex::new(config)),
+            memory,
+            log_sender,
+            Some(Arc::new(Mutex::new(Capture::new(path).unwrap()))),
+        );
+        let requests = [
+            Request::WriteMultipleRegisters(2, vec![0x1234, 0x5678].into()),
+            Request::ReadHoldingRegisters(2, 2),
+            Request::ReadInputRegisters(0, 1),
+            Request::ReadCoils(0, 1),
+        ];
+        for request in requests.iter() {
+            let _ = service
+                .process(SlaveRequest {
+                    slave: 1,
+                    request: request.clone(),
+                })
+                .now_or_never();
+        }
+
+        let entries = capture::load(path).unwrap();
+        let _ = std::fs::remove_file(path);
+        assert_eq!(entries.len(), requests.len());
+        for (entry, request) in entries.iter().zip(requests.iter()) {
+            let pdu = entry.request_pdu().unwrap();
+            assert_eq!(
+                &Request::try_from(tokio_modbus::bytes::Bytes::from(pdu)).unwrap(),
+                request
+            );
+            assert_eq!(entry.slave, 1);
+        }
+        assert_eq!(entries[0].range, Some((2, 4)));
+        assert_eq!(entries[0].response.as_deref(), Some("1000020002"));
+        assert_eq!(entries[1].response.as_deref(), Some("030412345678"));
+        assert_eq!(entries[2].response, None);
+        assert_eq!(entries[3].function, 0x01);
+        assert_eq!(
+            entries[3].response,
+            Some(frame::to_hex(&[0x01, 0x01, 0x00]))
+        );
+        assert_eq!(frame::from_hex("0304ABcd").unwrap(), vec![3, 4, 0xAB, 0xCD]);
+        assert!(frame::from_hex("030").is_err());
+        assert!(frame::from_hex("0é").is_err());
+    }
+
+    #[test]
+    fn gateway() {
+        let mut memory = Memory::new();
+        memory.init(1, Table::HoldingRegisters, &[Range::new(0u16, 8u16)]);
+        let memory = Arc::new(Mutex::new(memory));
+        let config = Arc::new(Mutex::new(AppConfig::default()));
+        let (log_sender, _log_receiver) = channel(10);
+        let device = Service::new(config.clone(), memory.clone(), log_sender.clone(), None);
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async move {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move {
+                let server = tokio_modbus::server::tcp::Server::new(listener);
+                let on_connected = |stream, socket_addr| {
+                    let device = device.clone();
+                    async move {
+                        tokio_modbus::server::tcp::accept_tcp_connection(
+                            stream,
+                            socket_addr,
+                            |_| Ok(Some(device.clone())),
+                        )
+                    }
+                };
+                server.serve(&on_connected, |_| {}).await
+            });
+            let timeout = std::time::Duration::from_millis(500);
+            let gateway = Service::new(
+                config.clone(),
+                Arc::new(Mutex::new(Memory::new())),
+                log_sender.clone(),
+                None,
+            )
+            .with_upstream(Upstream::new(Target::Tcp(addr), timeout));
+            let call = |request| {
+                let gateway = gateway.clone();
+                async move {
+                    gateway
+                        .process(SlaveRequest { slave: 1, request })
+                        .await
+                        .map(|reply| reply.result)
+                }
+            };
+            assert_eq!(
+                call(Request::WriteSingleRegister(3, 0x1234)).await,
+                Some(Ok(Response::WriteSingleRegister(3, 0x1234)))
+            );
+            assert_eq!(
+                call(Request::ReadHoldingRegisters(3, 1)).await,
+                Some(Ok(Response::ReadHoldingRegisters(vec![0x1234])))
+            );
+
+            let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let unreachable = Service::new(config, memory, log_sender, None).with_upstream(
+                Upstream::new(Target::Tcp(closed.local_addr().unwrap()), timeout),
+            );
+            drop(closed);
+            assert_eq!(
+                unreachable
+                    .process(SlaveRequest {
+                        slave: 1,
+                        request: Request::ReadHoldingRegisters(3, 1),
+                    })
+                    .await
+                    .map(|reply| reply.result),
+                Some(Err(ExceptionCode::GatewayPathUnavailable))
+            );
+        });
+    }
+
+    #[test]
+    fn rtu_over_tcp() {
+        use crate::tcp::server::Server;
+        use crate::tcp::{TcpConfig, Transport};
+        use crate::util::str;
+
+        let mut memory = Memory::new();
+        memory.init(1, Table::HoldingRegisters, &[Range::new(0u16, 8u16)]);
+        let memory = Arc::new(Mutex::new(memory));
+        let config = Arc::new(Mutex::new(AppConfig::default()));
+        let (log_sender, _log_receiver) = channel(10);
+        let (status_sender, _status_receiver) = channel(10);
+        let service = Service::new(config, memory, log_sender.clone(), None);
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let config = TcpConfig {
+            ip: str!("127.0.0.1"),
+            port,
+            transport: Transport::RtuOverTcp,
+            ..Default::default()
+        };
+        let server_config = config.clone();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async move {
+            tokio::spawn(async move {
+                Server::new(server_config, service, status_sender, log_sender)
+                    .run()
+                    .await
+            });
+            let timeout = std::time::Duration::from_millis(500);
+            let mut ctx = None;
+            for _ in 0..50 {
+                // The server might not be listening yet
+                ctx = config.connect(Slave(1), timeout).await.ok();
+                if ctx.is_some() {
+                    break;
+                }
+                tokio::time::sleep(timeout / 10).await;
+            }
+            let mut ctx = ctx.unwrap();
+            assert_eq!(
+                ctx.write_multiple_registers(2, &[0x1234, 0x5678])
+                    .await
+                    .unwrap(),
+                Ok(())
+            );
+            assert_eq!(
+                ctx.read_holding_registers(2, 2).await.unwrap(),
+                Ok(vec![0x1234, 0x5678])
+            );
+        });
+    }
+
+    #[test]
+    fn ascii_framing() {
+        assert_eq!(
+            frame::ascii::encode(1, &[0x03, 0x00, 0x0A, 0x00, 0x01], false),
+            b":0103000A0001F1\r\n".to_vec()
+        );
+        let mut buf = b"noise:0103000A0001F2\r\n:0103000A0001F1\r\n:01".to_vec();
+        assert_eq!(
+            frame::ascii::decode(&mut buf),
+            Some((1, vec![0x03, 0x00, 0x0A, 0x00, 0x01]))
+        );
+        assert_eq!(frame::ascii::decode(&mut buf), None);
+        assert_eq!(buf, b":01".to_vec());
+        // Non-ASCII characters are dropped like any other invalid frame
+        let mut buf = ":0é0\r\n:0103000A0001F1\r\n".as_bytes().to_vec();
+        assert_eq!(
+            frame::ascii::decode(&mut buf),
+            Some((1, vec![0x03, 0x00, 0x0A, 0x00, 0x01]))
+        );
+
+        let mut memory = Memory::new();
+        memory.init(1, Table::HoldingRegisters, &[Range::new(0u16, 8u16)]);
+        let memory = Arc::new(Mutex::new(memory));
+        let config = AppConfig {
+            server: Some(
+                serde_json::from_str(
+                    r#"{ "faults": [ { "read_code": 4, "action": { "Exception": 4 } } ] }"#,
+                )
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let (log_sender, _log_receiver) = channel(10);
+        let service = Service::new(
+            Arc::new(Mutex::new(config)),
+            memory,
+            log_sender.clone(),
+            None,
+        );
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async move {
+            let (client, server) = tokio::io::duplex(1024);
+            tokio::spawn(crate::rtu::server::serve(
+                server,
+                service,
+                log_sender,
+                frame::Encoding::Ascii,
+            ));
+            let mut ctx = crate::rtu::ascii::attach_slave(client, Slave(1));
+            assert_eq!(
+                ctx.write_multiple_registers(2, &[0x1234, 0x5678])
+                    .await
+                    .unwrap(),
+                Ok(())
+            );
+            assert_eq!(
+                ctx.read_holding_registers(2, 2).await.unwrap(),
+                Ok(vec![0x1234, 0x5678])
+            );
+            assert_eq!(
+                ctx.read_input_registers(0, 1).await.unwrap(),
+                Err(ExceptionCode::ServerDeviceFailure)
+            );
+        });
+    }
+
+    #[test]
+    fn udp_retries() {
+        let mut memory = Memory::new();
+        memory.init(1, Table::HoldingRegisters, &[Range::new(0u16, 8u16)]);
+        let memory = Arc::new(Mutex::new(memory));
+        let config = AppConfig {
+            server: Some(
+                serde_json::from_str(
+                    r#"{ "faults": [ { "read_code": 3, "every": 2, "action": "Drop" } ] }"#,
+                )
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let (log_sender, _log_receiver) = channel(10);
+        let service = Service::new(
+            Arc::new(Mutex::new(config)),
+            memory,
+            log_sender.clone(),
+            None,
+        );
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async move {
+            let server = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let addr = server.local_addr().unwrap();
+            tokio::spawn(crate::udp::server::serve(server, service, log_sender));
+            let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            socket.connect(addr).await.unwrap();
+            let timeout = std::time::Duration::from_millis(100);
+            let mut ctx = crate::udp::client::attach_slave(socket, Slave(1), timeout, 1);
+            assert_eq!(ctx.write_single_register(2, 0x1234).await.unwrap(), Ok(()));
+            // The second read is dropped once and answered after the retry
+            for _ in 0..2 {
+                assert_eq!(
+                    ctx.read_holding_registers(2, 1).await.unwrap(),
+                    Ok(vec![0x1234])
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn tls() {
+        use crate::tcp::server::Server;
+        use crate::tcp::tls::TlsConfig;
+        use crate::tcp::TcpConfig;
+        use crate::util::str;
+        use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, IsCa, KeyPair};
+
+        let dir = std::env::temp_dir().join(format!("tls_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = |name: &str, content: String| {
+            let path = dir.join(name);
+            std::fs::write(&path, content).unwrap();
+            Some(path.to_string_lossy().to_string())
+        };
+        let mut ca_params = CertificateParams::new(vec![]).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = CertifiedIssuer::self_signed(ca_params, KeyPair::generate().unwrap()).unwrap();
+        let issue = |name: &str| {
+            let key = KeyPair::generate().unwrap();
+            let cert = CertificateParams::new(vec![str!("127.0.0.1")])
+                .unwrap()
+                .signed_by(&key, &ca)
+                .unwrap();
+            (
+                file(&format!("{name}.pem"), cert.pem()),
+                file(&format!("{name}.key"), key.serialize_pem()),
+            )
+        };
+        let (server_cert, server_key) = issue("server");
+        let (client_cert, client_key) = issue("client");
+        let ca = file("ca.pem", ca.pem());
+
+        let mut memory = Memory::new();
+        memory.init(1, Table::HoldingRegisters, &[Range::new(0u16, 8u16)]);
+        let memory = Arc::new(Mutex::new(memory));
+        let config = Arc::new(Mutex::new(AppConfig::default()));
+        let (log_sender, _log_receiver) = channel(10);
+        let (status_sender, _status_receiver) = channel(10);
+        let service = Service::new(config, memory, log_sender.clone(), None);
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let server_config = TcpConfig {
+            ip: str!("127.0.0.1"),
+            port,
+            tls: TlsConfig {
+                tls: true,
+                ca: ca.clone(),
+                cert: server_cert,
+                key: server_key,
+                client_auth: true,
+                server_name: None,
+            },
+            ..Default::default()
+        };
+        let client_config = |cert, key| TcpConfig {
+            ip: str!("127.0.0.1"),
+            port,
+            tls: TlsConfig {
+                tls: true,
+                ca: ca.clone(),
+                cert,
+                key,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async move {
+            tokio::spawn(async move {
+                Server::new(server_config, service, status_sender, log_sender)
+                    .run()
+                    .await
+            });
+            let timeout = std::time::Duration::from_millis(500);
+            let config = client_config(client_cert, client_key);
+            let mut ctx = None;
+            for _ in 0..50 {
+                // The server might not be listening yet
+                ctx = config.connect(Slave(1), timeout).await.ok();
+                if ctx.is_some() {
+                    break;
+                }
+                tokio::time::sleep(timeout / 10).await;
+            }
+            let mut ctx = ctx.unwrap();
+            assert_eq!(ctx.write_single_register(2, 0x1234).await.unwrap(), Ok(()));
+            assert_eq!(
+                ctx.read_holding_registers(2, 1).await.unwrap(),
+                Ok(vec![0x1234])
+            );
+
+            // Clients without certificate are rejected by the server
+            let mut ctx = client_config(None, None)
+                .connect(Slave(1), timeout)
+                .await
+                .unwrap();
+            assert!(ctx.read_holding_registers(2, 1).await.is_err());
+        });
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn connections() {
+        use crate::connection::{self, Timing};
+        use crate::mem::memory::Unit;
+        use crate::tcp::TcpConfig;
+        use crate::util::str;
+        use crate::{Commands, Status};
+
+        let definition = |connection: &str| {
+            format!(
+                r#"{{ "slave_id": 1, "address": 0, "length": 1, "type": "U16", "read_code": 3,
+                     "access": "ReadWrite", "connection": "{connection}" }}"#
+            )
+        };
+        let content = format!(
+            r#"{{ "history_length": 50, "interval_ms": 500, "contiguous_memory": [],
+                 "connections": {{
+                     "meter": {{ "transport": "rtu", "path": "/dev/ttyUSB0", "baud_rate": 9600 }},
+                     "charger": {{ "transport": "tcp", "ip": "10.0.0.5", "interval_ms": 100 }}
+                 }},
+                 "definitions": {{ "meter": {}, "charger": {} }} }}"#,
+            definition("meter"),
+            definition("charger")
+        );
+        let path = std::env::temp_dir().join(format!("connections_{}.json", std::process::id()));
+        std::fs::write(&path, &content).unwrap();
+        let config = AppConfig::read(path.to_str().unwrap()).unwrap();
+        // Connections are numbered by name, 0 is the connection of the command line
+        assert_eq!(config.definitions["charger"].unit(), Unit::new(1, 1));
+        assert_eq!(config.definitions["meter"].unit(), Unit::new(2, 1));
+
+        let timing = Timing {
+            delay_after_connect_ms: 0,
+            interval_ms: 500,
+            timeout_ms: 1000,
+        };
+        let plan = connection::plan(&config, Commands::Tcp(TcpConfig::default()), timing);
+        assert_eq!(plan.len(), 2);
+        assert!(
+            matches!(plan[0], (1, Commands::Tcp(ref c), t) if c.ip == "10.0.0.5" && c.port == 502 && t.interval_ms == 100)
+        );
+        assert!(
+            matches!(plan[1], (2, Commands::Rtu(ref c), t) if c.serial.baud_rate == 9600 && t.interval_ms == 500)
+        );
+
+        // Equal slave ids of different devices do not share memory
+        let mut memory = Memory::new();
+        for def in config.definitions.values() {
+            memory.init(def.unit(), def.table(), &[def.get_range()]);
+        }
+        let range = Range::new(0u16, 1u16);
+        memory
+            .write(Unit::new(1, 1), Table::HoldingRegisters, range, &[7])
+            .unwrap();
+        assert_eq!(
+            memory
+                .read(Unit::new(2, 1), Table::HoldingRegisters, &range)
+                .unwrap(),
+            vec![&0]
+        );
+
+        // Status messages name the connection once several connections are configured
+        assert!(matches!(
+            connection::status(&config, 2, str!("Modbus RTU connected.")),
+            Status::String(ref s) if s == "[meter] Modbus RTU connected."
+        ));
+        assert!(matches!(
+            connection::status(&AppConfig::default(), 0, str!("Modbus TCP connected.")),
+            Status::String(ref s) if s == "Modbus TCP connected."
+        ));
+
+        // A single row is recorded once every connection completed its poll cycle
+        let record = std::env::temp_dir().join(format!("connections_{}.csv", std::process::id()));
+        let record = record.to_str().unwrap();
+        let _ = std::fs::remove_file(record);
+        let mut recorder = Recorder::new(
+            record,
+            RecordFormat::Csv,
+            Arc::new(Mutex::new(config.clone())),
+            Arc::new(Mutex::new(memory)),
+        )
+        .unwrap()
+        .with_connections(plan.len());
+        recorder.complete(1).unwrap();
+        recorder.complete(1).unwrap();
+        assert_eq!(std::fs::read_to_string(record).unwrap(), "");
+        recorder.complete(2).unwrap();
+        recorder.complete(2).unwrap();
+        let rows = std::fs::read_to_string(record).unwrap();
+        let _ = std::fs::remove_file(record);
+        // Header and the row of the completed cycle
+        assert_eq!(rows.lines().count(), 2);
+
+        std::fs::write(
+            &path,
+            content.replace(r#""connection": "meter""#, r#""connection": "inverter""#),
+        )
+        .unwrap();
+        assert!(AppConfig::read(path.to_str().unwrap()).is_err());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn endpoints() {
+        use crate::Commands;
+
+        let mut memory = Memory::new();
+        memory.init(1, Table::HoldingRegisters, &[Range::new(0u16, 4u16)]);
+        memory.init(2, Table::HoldingRegisters, &[Range::new(0u16, 4u16)]);
+        let memory = Arc::new(Mutex::new(memory));
+        let config = AppConfig {
+            server: Some(
+                serde_json::from_str(
+                    r#"{
+                        "endpoints": [
+                            { "transport": "tcp", "ip": "0.0.0.0", "port": 1502, "slave_ids": [1] },
+                            { "transport": "rtu", "path": "/dev/ttyUSB0", "baud_rate": 9600 }
+                        ]
+                    }"#,
+                )
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let endpoints = config.server.clone().unwrap().endpoints().to_vec();
+        assert_eq!(endpoints.len(), 2);
+        assert!(
+            matches!(endpoints[0].endpoint.command(), Commands::Tcp(ref c) if c.ip == "0.0.0.0" && c.port == 1502)
+        );
+        assert_eq!(endpoints[0].slave_ids, Some(vec![1]));
+        assert!(
+            matches!(endpoints[1].endpoint.command(), Commands::Rtu(ref c) if c.serial.baud_rate == 9600)
+        );
+        assert_eq!(endpoints[1].slave_ids, None);
+
+        let all = service(memory, Arc::new(Mutex::new(config)));
+        let filtered = all.clone().with_slaves(vec![1]);
+
+        // All endpoints share the same memory
+        assert_eq!(
+            call(&all, 1, Request::WriteSingleRegister(0, 0x1234)),
+            Ok(Some(Response::WriteSingleRegister(0, 0x1234)))
+        );
+        assert_eq!(
+            call(&filtered, 1, Request::ReadHoldingRegisters(0, 1)),
+            Ok(Some(Response::ReadHoldingRegisters(vec![0x1234])))
+        );
+        assert_eq!(
+            call(&all, 2, Request::ReadHoldingRegisters(0, 1)),
+            Ok(Some(Response::ReadHoldingRegisters(vec![0])))
+        );
+        assert_eq!(
+            call(&filtered, 2, Request::ReadHoldingRegisters(0, 1)),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn unknown_slaves() {
+        let mut memory = Memory::new();
+        memory.init(1, Table::HoldingRegisters, &[Range::new(0u16, 4u16)]);
+        let memory = Arc::new(Mutex::new(memory));
+        let config = AppConfig {
+            server: Some(serde_json::from_str(r#"{ "slave_ids": [1, 7] }"#).unwrap()),
+            ..Default::default()
+        };
+        let config = Arc::new(Mutex::new(config));
+        let service = service(memory, config.clone());
+
+        assert_eq!(
+            call(&service, 1, Request::ReadHoldingRegisters(0, 1)),
+            Ok(Some(Response::ReadHoldingRegisters(vec![0])))
+        );
+        // Storage of other slaves is never touched
+        assert_eq!(
+            call(&service, 2, Request::ReadHoldingRegisters(0, 1)),
+            Ok(None)
+        );
+        assert_eq!(
+            call(&service, 2, Request::WriteSingleRegister(0, 1)),
+            Ok(None)
+        );
+
+        config.lock().unwrap().server = Some(
+            serde_json::from_str(
+                r#"{ "slave_ids": [1], "unknown_slave": "GatewayPathUnavailable" }"#,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            call(&service, 7, Request::ReadHoldingRegisters(0, 1)),
+            Err(ExceptionCode::GatewayPathUnavailable)
+        );
+
+        // The slaves of an endpoint are further restricted by the configuration
+        let endpoint = service.clone().with_slaves(vec![1, 2]);
+        assert_eq!(
+            call(&endpoint, 2, Request::ReadHoldingRegisters(0, 1)),
+            Err(ExceptionCode::GatewayPathUnavailable)
+        );
+
+        // Requests to other slaves on a serial line stay silent
+        config.lock().unwrap().server =
+            Some(serde_json::from_str(r#"{ "slave_ids": [1] }"#).unwrap());
+        let pdu = frame::encode_request(&Request::ReadHoldingRegisters(0, 1));
+        let (mut client, server) = tokio::io::duplex(256);
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+            let (log_sender, _log_receiver) = channel(10);
+            tokio::spawn(crate::rtu::server::serve(
+                server,
+                service.clone(),
+                log_sender,
+                frame::Encoding::Rtu,
+            ));
+            client
+                .write_all(&frame::rtu::encode(2, &pdu, false))
+                .await
+                .unwrap();
+            let mut buf = [0u8; 16];
+            assert!(tokio::time::timeout(
+                std::time::Duration::from_millis(200),
+                client.read(&mut buf)
+            )
+            .await
+            .is_err());
+            client
+                .write_all(&frame::rtu::encode(1, &pdu, false))
+                .await
+                .unwrap();
+            let n = client.read(&mut buf).await.unwrap();
+            assert_eq!(
+                &buf[..n],
+                &frame::rtu::encode(1, &[0x03, 0x02, 0x00, 0x00], false)[..]
+            );
+        });
+    }
+
+    #[test]
+    fn read_write_multiple_registers() {
+        use crate::mem::memory::Unit;
+        use crate::mem::register::Register;
+        use crate::msg::Command;
+
+        let mut memory = Memory::new();
+        memory.init(1, Table::HoldingRegisters, &[Range::new(0u16, 8u16)]);
+        let memory = Arc::new(Mutex::new(memory));
+        let config = AppConfig {
+            definitions: HashMap::from([(
+                String::from("Secret"),
+                serde_json::from_str(
+                    r#"{ "slave_id": 1, "address": 6, "length": 1, "type": "U16",
+                         "read_code": 3, "access": "WriteOnly" }"#,
+                )
+                .unwrap(),
+            )]),
+            server: Some(
+                serde_json::from_str(
+                    r#"{ "faults": [ { "range": { "start": 4, "end": 5 }, "action": { "Exception": 4 } } ] }"#,
+                )
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        // Write-only register in the read range and a fault rule on the write range
+        let restricted = service(memory.clone(), Arc::new(Mutex::new(config)));
+        let service = service(memory.clone(), Arc::new(Mutex::new(AppConfig::default())));
+
+        // The write is performed before the read
+        assert_eq!(
+            call(
+                &service,
+                1,
+                Request::ReadWriteMultipleRegisters(1, 3, 2, vec![7, 8].into())
+            ),
+            Ok(Some(Response::ReadWriteMultipleRegisters(vec![0, 7, 8])))
+        );
+
+        // Nothing is written if the read range is rejected
+        assert!(call(
+            &restricted,
+            1,
+            Request::ReadWriteMultipleRegisters(6, 1, 0, vec![9].into())
+        )
+        .is_err());
+        assert_eq!(
+            call(&service, 1, Request::ReadHoldingRegisters(0, 1)),
+            Ok(Some(Response::ReadHoldingRegisters(vec![0])))
+        );
+        // Fault rules match the write range as well
+        assert_eq!(
+            call(
+                &restricted,
+                1,
+                Request::ReadWriteMultipleRegisters(0, 1, 4, vec![9].into())
+            ),
+            Err(ExceptionCode::ServerDeviceFailure)
+        );
+
+        let definition = |write_code: &str| -> Definition {
+            serde_json::from_str(&format!(
+                r#"{{ "slave_id": 1, "address": 4, "length": 2, "type": "U32", "read_code": 3,
+                     "access": "ReadWrite" {write_code} }}"#
+            ))
+            .unwrap()
+        };
+        let register = Register::new(&definition(r#", "write_code": 23"#), &memory);
+        assert!(matches!(
+            Command::write(&register, vec![0, 5]),
+            Ok(Command::ReadWriteMultipleRegisters((unit, Table::HoldingRegisters, 4, 2, 4, ref values, false)))
+                if unit == Unit::new(0, 1) && *values == vec![0, 5]
+        ));
+        let register = Register::new(&definition(""), &memory);
+        assert!(matches!(
+            Command::write(&register, vec![0, 5]),
+            Ok(Command::WriteMultipleRegisters((_, _, 4, _, false)))
+        ));
+        let register = Register::new(&definition(r#", "write_code": 3"#), &memory);
+        assert!(Command::write(&register, vec![0, 5]).is_err());
+
+        // The write code has to match the table of the read code
+        let other = |read_code: u8, write_code: &str| -> Definition {
+            serde_json::from_str(&format!(
+                r#"{{ "address": 4, "length": 1, "type": "U16", "read_code": {read_code},
+                     "access": "ReadWrite" {write_code} }}"#
+            ))
+            .unwrap()
+        };
+        assert!(definition(r#", "write_code": 23"#).is_writable());
+        assert!(definition("").is_writable());
+        assert!(!definition(r#", "write_code": 5"#).is_writable());
+        assert!(!other(4, r#", "write_code": 23"#).is_writable());
+        assert!(other(4, "").is_writable());
+        assert!(other(1, "").is_writable());
+        assert!(!other(1, r#", "write_code": 6"#).is_writable());
+        let path = std::env::temp_dir().join(format!("write_code_{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{ "history_length": 10, "interval_ms": 100, "contiguous_memory": [],
+                 "definitions": { "Input": { "address": 4, "length": 1, "type": "U16",
+                 "read_code": 4, "write_code": 23, "access": "ReadWrite" } } }"#,
+        )
+        .unwrap();
+        let result = AppConfig::read(&path.to_string_lossy());
+        let _ = std::fs::remove_file(path);
+        assert!(result.is_err_and(|e| e.to_string().contains("Invalid write code 23")));
+    }
+
+    #[test]
+    fn mask_write_register() {
+        use crate::mem::register::Register;
+        use crate::msg::Command;
+
+        let mut memory = Memory::new();
+        memory.init(1, Table::HoldingRegisters, &[Range::new(0u16, 4u16)]);
+        memory.init(1, Table::InputRegisters, &[Range::new(0u16, 4u16)]);
+        let _ = memory.write(1, Table::HoldingRegisters, Range::new(0u16, 1u16), &[0x12]);
+        let memory = Arc::new(Mutex::new(memory));
+        let service = service(memory.clone(), Arc::new(Mutex::new(AppConfig::default())));
+
+        // Example of the Modbus specification
+        assert_eq!(
+            call(&service, 1, Request::MaskWriteRegister(0, 0xF2, 0x25)),
+            Ok(Some(Response::MaskWriteRegister(0, 0xF2, 0x25)))
+        );
+        assert_eq!(
+            memory
+                .lock()
+                .unwrap()
+                .read(1, Table::HoldingRegisters, &Range::new(0u16, 1u16))
+                .unwrap(),
+            vec![&0x17]
+        );
+
+        let definition = |read_code: u8| -> Definition {
+            serde_json::from_str(&format!(
+                r#"{{ "slave_id": 1, "address": 0, "length": 2, "type": "U32",
+                     "read_code": {read_code}, "access": "WriteOnly" }}"#
+            ))
+            .unwrap()
+        };
+        let register = Register::new(&definition(3), &memory);
+        assert!(matches!(
+            Command::write_bit(&register, 3, true),
+            Ok(Command::MaskWriteRegister((_, _, 0, 0xFFF7, 0x0008, true)))
+        ));
+        assert!(matches!(
+            Command::write_bit(&register, 17, false),
+            Ok(Command::MaskWriteRegister((_, _, 1, 0xFFFD, 0x0000, true)))
+        ));
+        assert!(Command::write_bit(&register, 32, true).is_err());
+        let register = Register::new(&definition(4), &memory);
+        assert!(Command::write_bit(&register, 0, true).is_err());
+        assert!(frame::bit_masks(16, true).is_err());
+    }
+
+    #[test]
+    fn device_identification() {
+        use tokio_modbus::prelude::{ConformityLevel, ReadCode};
+
+        let config = AppConfig {
+            server: Some(
+                serde_json::from_str(
+                    r#"{ "device_identification": {
+                        "vendor_name": "ACME", "product_code": "X1", "major_minor_revision": "1.0",
+                        "model_name": "Model",
+                        "extended": [{ "id": 129, "value": "B" }, { "id": 128, "value": "A" }]
+                    } }"#,
+                )
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let config = Arc::new(Mutex::new(config));
+        let service = service(Arc::new(Mutex::new(Memory::new())), config.clone());
+        let identify = |read_code, object_id| match call(
+            &service,
+            1,
+            Request::ReadDeviceIdentification(read_code, object_id),
+        ) {
+            Ok(Some(Response::ReadDeviceIdentification(rsp))) => Ok(rsp),
+            Ok(rsp) => panic!("Unexpected response {rsp:?}"),
+            Err(e) => Err(e),
+        };
+        let ids = |rsp: &tokio_modbus::prelude::ReadDeviceIdentificationResponse| {
+            rsp.device_id_objects
+                .iter()
+                .map(|o| o.id)
+                .collect::<Vec<_>>()
+        };
+
+        let rsp = identify(ReadCode::Basic, 0).unwrap();
+        assert_eq!(ids(&rsp), vec![0, 1, 2]);
+        assert_eq!(
+            rsp.conformity_level,
+            ConformityLevel::ExtendedIdentification
+        );
+        assert!(!rsp.more_follows);
+        assert_eq!(
+            crate::ident::describe(&rsp.device_id_objects[0]),
+            "VendorName: ACME"
+        );
+        assert_eq!(
+            ids(&identify(ReadCode::Regular, 0).unwrap()),
+            vec![0, 1, 2, 5]
+        );
+        assert_eq!(
+            ids(&identify(ReadCode::Extended, 2).unwrap()),
+            vec![2, 5, 0x80, 0x81]
+        );
+        // Streams starting at an unknown object restart at the first object
+        assert_eq!(
+            ids(&identify(ReadCode::Basic, 0x42).unwrap()),
+            vec![0, 1, 2]
+        );
+        assert_eq!(
+            ids(&identify(ReadCode::Specific, 0x81).unwrap()),
+            vec![0x81]
+        );
+        assert_eq!(
+            identify(ReadCode::Specific, 0x03),
+            Err(ExceptionCode::IllegalDataAddress)
+        );
+
+        // Objects exceeding a single response are announced as more follows
+        let long = "X".repeat(200);
+        config.lock().unwrap().server = Some(
+            serde_json::from_str(&format!(
+                r#"{{ "device_identification": {{ "vendor_name": "{long}", "product_code": "{long}" }} }}"#
+            ))
+            .unwrap(),
+        );
+        let rsp = identify(ReadCode::Basic, 0).unwrap();
+        assert_eq!(ids(&rsp), vec![0]);
+        assert_eq!(rsp.conformity_level, ConformityLevel::BasicIdentification);
+        assert!(rsp.more_follows);
+        assert_eq!(rsp.next_object_id, 1);
+        assert_eq!(ids(&identify(ReadCode::Basic, 1).unwrap()), vec![1, 2]);
+    }
+
+    #[test]
+    fn diagnostics() {
+        use crate::diag::{self, Counter, SubFunction};
+        use tokio::io::AsyncWriteExt;
+        use tokio_modbus::prelude::SlaveContext;
+
+        let mut memory = Memory::new();
+        memory.init(1, Table::HoldingRegisters, &[Range::new(0u16, 4u16)]);
+        let memory = Arc::new(Mutex::new(memory));
+        let config = AppConfig {
+            server: Some(
+                serde_json::from_str(
+                    r#"{ "slave_ids": [1], "server_id": 66, "device_identification": {
+                        "vendor_name": "ACME", "product_code": "X1", "major_minor_revision": "1.0"
+                    } }"#,
+                )
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let service = service(memory, Arc::new(Mutex::new(config)));
+        let (log_sender, _log_receiver) = channel(10);
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async move {
+            let timeout = std::time::Duration::from_millis(200);
+            let (client, server) = tokio::io::duplex(256);
+            tokio::spawn(crate::rtu::server::serve(
+                server,
+                service.clone(),
+                log_sender.clone(),
+                frame::Encoding::Rtu,
+            ));
+            let mut ctx = tokio_modbus::client::rtu::attach_slave(client, Slave(1));
+            assert_eq!(ctx.read_holding_registers(0, 1).await.unwrap(), Ok(vec![0]));
+            assert_eq!(
+                diag::query(&mut ctx, SubFunction::ReturnQueryData, 0xA537, timeout)
+                    .await
+                    .unwrap(),
+                Ok(0xA537)
+            );
+            assert_eq!(
+                diag::query(&mut ctx, SubFunction::BusMessages, 1, timeout)
+                    .await
+                    .unwrap(),
+                Err(ExceptionCode::IllegalDataValue)
+            );
+            assert_eq!(
+                diag::report_server_id(&mut ctx, timeout).await.unwrap(),
+                Ok((66, true, b"ACME X1 1.0".to_vec()))
+            );
+            // Requests to other slaves are only seen on the bus
+            ctx.set_slave(Slave(2));
+            assert!(diag::report_server_id(&mut ctx, timeout).await.is_err());
+            ctx.set_slave(Slave(1));
+            assert_eq!(
+                diag::query(&mut ctx, SubFunction::BusMessages, 0, timeout)
+                    .await
+                    .unwrap(),
+                Ok(6)
+            );
+            let counters = service.counters();
+            assert_eq!(counters.get(Counter::ServerMessages), 5);
+            assert_eq!(counters.get(Counter::BusExceptionErrors), 1);
+            assert_eq!(counters.get(Counter::ServerNoResponses), 0);
+            assert_eq!(
+                diag::query(&mut ctx, SubFunction::ClearCounters, 0, timeout)
+                    .await
+                    .unwrap(),
+                Ok(0)
+            );
+            assert_eq!(counters.get(Counter::BusMessages), 0);
+
+            // A corrupted frame is discarded and counted as communication error
+            let (mut client, server) = tokio::io::duplex(256);
+            tokio::spawn(crate::rtu::server::serve(
+                server,
+                service.clone(),
+                log_sender,
+                frame::Encoding::Rtu,
+            ));
+            client
+                .write_all(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00])
+                .await
+                .unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+            assert_eq!(counters.get(Counter::BusCommunicationErrors), 1);
+            assert_eq!(counters.get(Counter::BusMessages), 0);
+        });
+    }
+
+    #[test]
+    fn file_records() {
+        use crate::mem::file::{self, FileFormat};
+        use std::borrow::Cow;
+
+        let path = std::env::temp_dir().join(format!("file_{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "# profile\n1, 2\n0x10\n\n").unwrap();
+        assert_eq!(file::load(path, FileFormat::Csv).unwrap(), vec![1, 2, 0x10]);
+        file::save(path, FileFormat::Binary, &[0x1234, 5]).unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), vec![0x12, 0x34, 0x00, 0x05]);
+        assert_eq!(
+            file::load(path, FileFormat::Binary).unwrap(),
+            vec![0x1234, 5]
+        );
+        let _ = std::fs::remove_file(path);
+
+        let mut memory = Memory::new();
+        memory.load_file(1, 4, vec![1, 2, 3]);
+        let service = service(
+            Arc::new(Mutex::new(memory)),
+            Arc::new(Mutex::new(AppConfig::default())),
+        );
+        let records = |function, data: &[u8]| {
+            call(
+                &service,
+                1,
+                Request::Custom(function, Cow::Owned(data.to_vec())),
+            )
+            .map(|rsp| match rsp {
+                Some(Response::Custom(_, data)) => data.to_vec(),
+                rsp => panic!("Unexpected response {rsp:?}"),
+            })
+        };
+
+        // Example of the Modbus specification (two records of file 4 and 3)
+        let write = [
+            0x0D, 0x06, 0x00, 0x03, 0x00, 0x07, 0x00, 0x03, 0x06, 0xAF, 0x04, 0xBE, 0x10, 0x0D,
+        ];
+        assert_eq!(records(0x15, &write), Ok(write.to_vec()));
+        let read = [
+            0x0E, 0x06, 0x00, 0x04, 0x00, 0x01, 0x00, 0x02, 0x06, 0x00, 0x03, 0x00, 0x07, 0x00,
+            0x02,
+        ];
+        assert_eq!(
+            records(0x14, &read),
+            Ok(vec![
+                0x0C, 0x05, 0x06, 0x00, 0x02, 0x00, 0x03, 0x05, 0x06, 0x06, 0xAF, 0x04, 0xBE,
+            ])
+        );
+        // Records beyond the end of the file
+        assert_eq!(
+            records(0x14, &[0x07, 0x06, 0x00, 0x04, 0x00, 0x02, 0x00, 0x02]),
+            Err(ExceptionCode::IllegalDataAddress)
+        );
+        // Invalid reference type and byte count
+        assert_eq!(
+            records(0x14, &[0x07, 0x05, 0x00, 0x04, 0x00, 0x00, 0x00, 0x01]),
+            Err(ExceptionCode::IllegalDataAddress)
+        );
+        assert_eq!(
+            records(0x14, &[0x08, 0x06, 0x00, 0x04, 0x00, 0x00, 0x00, 0x01]),
+            Err(ExceptionCode::IllegalDataValue)
+        );
+    }
+
+    #[test]
+    fn fifo_queue() {
+        use crate::mem::fifo;
+        use crate::mem::memory::Unit;
+        use crate::oneshot::{Operation, Target};
+        use std::borrow::Cow;
+
+        let mut memory = Memory::new();
+        memory.init_fifo(1, 0x04DE, &[0x01B8, 0x1284]);
+        assert!(memory.push_fifo(1, 0x04DE, &[0x0042]));
+        assert!(!memory.push_fifo(1, 0x04DE, &[0; fifo::MAX_COUNT - 2]));
+        assert!(!memory.push_fifo(1, 0x0001, &[0x0042]));
+        let memory = Arc::new(Mutex::new(memory));
+        let service = service(memory.clone(), Arc::new(Mutex::new(AppConfig::default())));
+        let read = |data: &[u8]| {
+            call(
+                &service,
+                1,
+                Request::Custom(fifo::READ_FIFO_QUEUE, Cow::Owned(data.to_vec())),
+            )
+            .map(|rsp| match rsp {
+                Some(Response::Custom(_, data)) => data.to_vec(),
+                rsp => panic!("Unexpected response {rsp:?}"),
+            })
+        };
+
+        // Example of the Modbus specification extended by the pushed entry
+        assert_eq!(
+            read(&[0x04, 0xDE]),
+            Ok(vec![
+                0x00, 0x08, 0x00, 0x03, 0x01, 0xB8, 0x12, 0x84, 0x00, 0x42
+            ])
+        );
+        // Read entries are removed from the queue
+        assert_eq!(read(&[0x04, 0xDE]), Ok(vec![0x00, 0x02, 0x00, 0x00]));
+        assert_eq!(memory.lock().unwrap().fifo(1, 0x04DE), Some(vec![]));
+        // Address without queue
+        assert_eq!(read(&[0x00, 0x01]), Err(ExceptionCode::IllegalDataAddress));
+
+        // The client only keeps the latest entries
+        let mut memory = Memory::new();
+        memory.record_fifo(Unit::new(1, 1), 0, &(0..20).collect::<Vec<_>>());
+        memory.record_fifo(Unit::new(1, 1), 0, &(20..40).collect::<Vec<_>>());
+        assert_eq!(
+            memory.fifo(Unit::new(1, 1), 0),
+            Some((9..40).collect::<Vec<_>>())
+        );
+
+        // The pointer address is a holding register without read back (FC 23), one-shot
+        // operations reject FC 24
+        let definition: Definition = serde_json::from_str(
+            r#"{ "slave_id": 1, "address": 1246, "length": 1, "type": "U16",
+                 "read_code": 24, "write_code": 23, "access": "ReadWrite" }"#,
+        )
+        .unwrap();
+        assert_eq!(definition.table(), Table::HoldingRegisters);
+        assert!(!definition.is_writable());
+        let config = AppConfig {
+            definitions: HashMap::from([(String::from("Queue"), definition)]),
+            ..Default::default()
+        };
+        let target = Target {
+            name: Some(String::from("Queue")),
+            slave: None,
+            fc: None,
+            addr: None,
+            len: None,
+            r#type: None,
+            reverse: false,
+        };
+        assert!(Operation::resolve(&target, &config, 1).is_err());
+    }
+
+    #[test]
+    fn custom_functions() {
+        use crate::lua::{CustomFunction, Functions};
+        use std::borrow::Cow;
+
+        let memory = Arc::new(Mutex::new(Memory::new()));
+        let config = Arc::new(Mutex::new(AppConfig::default()));
+        let (log_sender, _log_receiver) = channel(10);
+        let spawn = |functions: &str| {
+            let functions: Vec<CustomFunction> = serde_json::from_str(functions).unwrap();
+            Functions::spawn(
+                functions,
+                memory.clone(),
+                config.clone(),
+                log_sender.clone(),
+            )
+        };
+        assert!(spawn(r#"[{ "function_code": 3, "handler": "return nil" }]"#).is_err());
+        assert!(spawn(r#"[{ "function_code": 65, "handler": "return (" }]"#).is_err());
+        let functions = spawn(
+            r#"[
+                { "function_code": 65, "handler": "local pdu, slave = ...\nreturn pdu .. string.char(slave)" },
+                { "function_code": 66, "handler": "return 3" },
+                { "function_code": 67, "handler": "return nil" },
+                { "function_code": 100, "handler": "return { 0xE4, 0x06 }" },
+                { "function_code": 101, "handler": "error('unsupported')" }
+            ]"#,
+        )
+        .unwrap();
+        let service = service(memory.clone(), config.clone()).with_functions(functions);
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async move {
+            let call = |function, data: &[u8]| {
+                service.call(SlaveRequest {
+                    slave: 7,
+                    request: Request::Custom(function, Cow::Owned(data.to_vec())),
+                })
+            };
+            assert_eq!(
+                call(65, &[0x01, 0x02]).await,
+                Ok(Some(Response::Custom(65, vec![0x01, 0x02, 0x07].into())))
+            );
+            assert_eq!(call(66, &[]).await, Err(ExceptionCode::IllegalDataValue));
+            assert_eq!(call(67, &[]).await, Ok(None));
+            assert_eq!(call(100, &[]).await, Err(ExceptionCode::ServerDeviceBusy));
+            assert_eq!(
+                call(101, &[]).await,
+                Err(ExceptionCode::ServerDeviceFailure)
+            );
+            // Function codes without handler
+            assert_eq!(call(68, &[]).await, Err(ExceptionCode::IllegalFunction));
+        });
+    }
+
+    #[test]
+    fn bitfield() {
+        use crate::util::str;
+
+        let r#type: DataType = serde_json::from_str(
+            r#"{ "type": "Bitfield", "bits": { "0": "Fault", "3-5": "Mode", "17": "Remote" } }"#,
+        )
+        .unwrap();
+        let bits = r#type.bits().unwrap();
+        assert_eq!(bits.words(), 2);
+        assert_eq!(
+            r#type.as_str(&[0b0010_1001, 0x0002], 1.0).unwrap(),
+            (str!("0x00020029"), str!("Fault, Mode=5, Remote"))
+        );
+        assert_eq!(
+            r#type.as_str(&[0x0100, 0x0000], 1.0).unwrap().1,
+            "Mode=0, Bit 8"
+        );
+        assert_eq!(r#type.field(&[0b0010_1001, 0], "Mode"), Some(5));
+        assert_eq!(r#type.field(&[0b0010_1001, 0], "Remote"), Some(0));
+        assert_eq!(r#type.field(&[0b0010_1001, 0], "Unknown"), None);
+        assert_eq!(r#type.encode("0x20001").unwrap(), vec![0x0001, 0x0002]);
+        assert_eq!(r#type.encode("8").unwrap(), vec![0x0008, 0x0000]);
+        assert_eq!(
+            serde_json::to_value(&bits).unwrap(),
+            serde_json::json!({ "0": "Fault", "3-5": "Mode", "17": "Remote" })
+        );
+        assert!(serde_json::from_str::<DataType>(
+            r#"{ "type": "Bitfield", "bits": { "5-3": "Mode" } }"#
+        )
+        .is_err());
+        assert!(DataType::default().bits().is_none());
+    }
+
+    #[test]
+    fn bit_definition() {
+        use crate::mem::register::Register;
+        use crate::util::str;
+
+        let mut memory = Memory::new();
+        memory.init(
+            1,
+            Table::HoldingRegisters,
+            &[Range::new(0x100u16, 0x101u16)],
+        );
+        let _ = memory.write(
+            1,
+            Table::HoldingRegisters,
+            Range::new(0x100u16, 0x101u16),
+            &[0x00F0],
+        );
+        let memory = Arc::new(Mutex::new(memory));
+
+        let definition = |extra: &str| -> Definition {
+            serde_json::from_str(&format!(
+                r#"{{ "slave_id": 1, "address": "0x100", "length": 1, "type": "U16",
+                     "read_code": 3, "access": "ReadWrite"{extra} }}"#
+            ))
+            .unwrap()
+        };
+        let bit = definition(r#", "bit": 3"#);
+        assert!(bit.is_valid_bit());
+        assert_eq!(bit.scope(&[0x00F0]), vec![0]);
+        assert_eq!(definition(r#", "bit": 4"#).scope(&[0x00F0]), vec![1]);
+        assert_eq!(definition("").scope(&[0x00F0]), vec![0x00F0]);
+
+        // Only the bit is changed in memory
+        bit.write(&mut memory.lock().unwrap(), &[1]).unwrap();
+        assert!(bit.write(&mut memory.lock().unwrap(), &[2]).is_err());
+        definition(r#", "bit": 5"#)
+            .write(&mut memory.lock().unwrap(), &[0])
+            .unwrap();
+        assert_eq!(
+            memory
+                .lock()
+                .unwrap()
+                .read(1, Table::HoldingRegisters, &Range::new(0x100u16, 0x101u16))
+                .unwrap(),
+            vec![&0x00D8]
+        );
+        let register = Register::new(&bit, &memory);
+        assert_eq!(register.bit(), Some(3));
+        assert_eq!(register.raw(), &vec![0x00D8]);
+        assert_eq!(register.value().1, str!("1"));
+
+        // Bits of input registers are only changed in memory (server mode)
+        memory
+            .lock()
+            .unwrap()
+            .init(1, Table::InputRegisters, &[Range::new(0x100u16, 0x101u16)]);
+        let input_bit = serde_json::from_str::<Definition>(
+            r#"{ "slave_id": 1, "address": "0x100", "length": 1, "type": "U16",
+                 "read_code": 4, "access": "ReadOnly", "bit": 2 }"#,
+        )
+        .unwrap();
+        assert!(input_bit.is_valid_bit());
+        let input = Register::new(&input_bit, &memory);
+        assert!(crate::Command::write_bit(&input, 2, true).is_err());
+        let mut handler = Handler::new(Arc::new(Mutex::new(AppConfig::default())), memory.clone());
+        handler.set_bit(&input, 2, true).unwrap();
+        assert!(handler.set_bit(&input, 16, true).is_err());
+        assert!(memory
+            .lock()
+            .unwrap()
+            .write_bit(1, Table::InputRegisters, 0x100, 16, true)
+            .is_err());
+        assert_eq!(
+            memory
+                .lock()
+                .unwrap()
+                .read(1, Table::InputRegisters, &Range::new(0x100u16, 0x101u16))
+                .unwrap(),
+            vec![&0x0004]
+        );
+        assert_eq!(Register::new(&input_bit, &memory).value().1, str!("1"));
+
+        assert!(!definition(r#", "bit": 16"#).is_valid_bit());
+        assert!(!serde_json::from_str::<Definition>(
+            r#"{ "address": 0, "length": 2, "type": "U32", "read_code": 3,
+                 "access": "ReadWrite", "bit": 3 }"#
+        )
+        .unwrap()
+        .is_valid_bit());
+        assert!(!serde_json::from_str::<Definition>(
+            r#"{ "address": 0, "length": 1, "type": "U16", "read_code": 1,
+                 "access": "ReadWrite", "bit": 3 }"#
+        )
+        .unwrap()
+        .is_valid_bit());
+    }
 }
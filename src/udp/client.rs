@@ -0,0 +1,75 @@
+use crate::frame::{self, mbap};
+
+use async_trait::async_trait;
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::Instant;
+use tokio_modbus::client::{Client as ModbusClient, Context};
+use tokio_modbus::prelude::{Request, Response, SlaveContext};
+use tokio_modbus::Slave;
+
+/// Modbus TCP client sending every request as single datagram
+#[derive(Debug)]
+struct Client {
+    socket: UdpSocket,
+    slave: Slave,
+    timeout: Duration,
+    retries: u8,
+    transaction_id: u16,
+}
+
+/// Attach a Modbus client context to the connected socket. Requests without response within the
+/// timeout are resent up to `retries` times.
+pub fn attach_slave(socket: UdpSocket, slave: Slave, timeout: Duration, retries: u8) -> Context {
+    let client: Box<dyn ModbusClient> = Box::new(Client {
+        socket,
+        slave,
+        timeout,
+        retries,
+        transaction_id: 0,
+    });
+    Context::from(client)
+}
+
+impl SlaveContext for Client {
+    fn set_slave(&mut self, slave: Slave) {
+        self.slave = slave;
+    }
+}
+
+#[async_trait]
+impl ModbusClient for Client {
+    async fn call(&mut self, request: Request<'_>) -> tokio_modbus::Result<Response> {
+        let function = request.function_code().value();
+        let pdu = frame::encode_request(&request);
+        let mut buf = [0u8; mbap::MAX_ADU_SIZE];
+        for _ in 0..=self.retries {
+            self.transaction_id = self.transaction_id.wrapping_add(1);
+            self.socket
+                .send(&mbap::encode(self.transaction_id, self.slave.into(), &pdu))
+                .await?;
+            let deadline = Instant::now() + self.timeout;
+            // Late responses to previous datagrams are skipped by their transaction id
+            while let Ok(received) =
+                tokio::time::timeout_at(deadline, self.socket.recv(&mut buf)).await
+            {
+                match mbap::decode(&buf[..received?]) {
+                    Some((id, _, pdu)) if id == self.transaction_id => {
+                        return frame::decode_response(function, pdu)
+                    }
+                    _ => continue,
+                }
+            }
+        }
+        Err(Error::new(
+            ErrorKind::TimedOut,
+            format!("No response after {} retries", self.retries),
+        )
+        .into())
+    }
+
+    async fn disconnect(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+pub mod client;
+pub mod server;
+
+use crate::oneshot::OneShot;
+use crate::tcp::tls::TlsConfig;
+use crate::tcp::{TcpConfig, Transport};
+use crate::util::str;
+
+use clap::Args;
+
+#[derive(Clone, Debug, Default, Args)]
+pub struct UdpConfig {
+    /// The interface to use for the service or the ip to send the requests to in client mode.
+    #[arg(short, long, default_value_t = str!("127.0.0.1"))]
+    pub ip: String,
+
+    /// The port to use for the service or the port of the target host in client mode.
+    #[arg(short, long, default_value_t = 502)]
+    pub port: u16,
+
+    /// Number of times a request is resent if no response is received within the timeout (only client mode).
+    #[arg(long, default_value_t = 2)]
+    pub retries: u8,
+
+    /// Execute a single operation instead of starting the application.
+    #[command(subcommand)]
+    pub oneshot: Option<OneShot>,
+}
+
+impl UdpConfig {
+    /// Settings of the Modbus TCP framing used for the datagrams
+    pub fn connection(&self) -> TcpConfig {
+        TcpConfig {
+            ip: self.ip.clone(),
+            port: self.port,
+            transport: Transport::Udp {
+                retries: self.retries,
+            },
+            tls: TlsConfig::default(),
+            oneshot: self.oneshot.clone(),
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use crate::frame::{self, mbap};
+use crate::service::Service;
+use crate::tcp::TcpConfig;
+use crate::util::str;
+use crate::util::Expect;
+use crate::LogMsg;
+use crate::Status;
+
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::Sender;
+use tokio_modbus::bytes::Bytes;
+use tokio_modbus::prelude::{Request, SlaveRequest};
+
+pub struct Server {
+    config: TcpConfig,
+    service: Service,
+    status_sender: Sender<Status>,
+    log_sender: Sender<LogMsg>,
+}
+
+impl Server {
+    pub fn new(
+        config: TcpConfig,
+        service: Service,
+        status_sender: Sender<Status>,
+        log_sender: Sender<LogMsg>,
+    ) -> Self {
+        Self {
+            config,
+            service,
+            status_sender,
+            log_sender,
+        }
+    }
+
+    pub async fn run(&self) {
+        let addr = self
+            .config
+            .addr()
+            .panic(|e| format!("Failed to create SocketAddr ({e})"));
+        if let Ok(socket) = UdpSocket::bind(addr).await {
+            let _ = self
+                .log_sender
+                .send(LogMsg::ok(&format!(
+                    "Successfully binded to udp {}:{}.",
+                    self.config.ip, self.config.port
+                )))
+                .await;
+            if let Err(e) = serve(socket, self.service.clone(), self.log_sender.clone()).await {
+                let _ = self
+                    .status_sender
+                    .send(Status::String(str!("Server not running.")))
+                    .await;
+                let _ = self
+                    .log_sender
+                    .send(LogMsg::err(&format!("Server shut down unexpectedly ({e})")))
+                    .await;
+            }
+        } else {
+            let _ = self
+                .status_sender
+                .send(Status::String(str!("Server not running.")))
+                .await;
+            let _ = self
+                .log_sender
+                .send(LogMsg::err(&format!(
+                    "Failed to bind to udp address {}:{}. Please restart.",
+                    self.config.ip, self.config.port
+                )))
+                .await;
+        }
+    }
+}
+
+/// Answer every datagram to the peer it was received from. Requests are processed concurrently
+/// to keep delayed responses from blocking other peers.
+pub async fn serve(
+    socket: UdpSocket,
+    service: Service,
+    log_sender: Sender<LogMsg>,
+) -> std::io::Result<()> {
+    let socket = Arc::new(socket);
+    let mut buf = [0u8; mbap::MAX_ADU_SIZE];
+    loop {
+        let (len, peer) = socket.recv_from(&mut buf).await?;
+        let Some((transaction_id, slave, pdu)) = mbap::decode(&buf[..len]) else {
+            let _ = log_sender
+                .send(LogMsg::err(&format!(
+                    "Discarded invalid datagram from {} ({} bytes)",
+                    peer, len
+                )))
+                .await;
+            continue;
+        };
+        let function = pdu[0];
+        let request = match Request::try_from(Bytes::from(pdu)) {
+            Ok(request) => request,
+            Err(e) => {
+                let _ = log_sender
+                    .send(LogMsg::err(&format!(
+                        "Slave: {slave}, Invalid request ({e})"
+                    )))
+                    .await;
+                continue;
+            }
+        };
+        let socket = socket.clone();
+        let service = service.clone();
+        let log_sender = log_sender.clone();
+        tokio::spawn(async move {
+            if let Some(reply) = service.process(SlaveRequest { slave, request }).await {
+                let pdu = frame::encode_response(function, &reply.result);
+                if let Err(e) = socket
+                    .send_to(&mbap::encode(transaction_id, slave, &pdu), peer)
+                    .await
+                {
+                    let _ = log_sender
+                        .send(LogMsg::err(&format!("Failed to answer {peer} ({e})")))
+                        .await;
+                }
+            }
+        });
+    }
+}
@@ -1,5 +1,6 @@
+use crate::mem::bitfield;
 use crate::mem::register::Values::ValueDef;
-use crate::mem::register::{AccessType, Handler, Register};
+use crate::mem::register::{Handler, Register, Value, Values};
 use crate::util::str;
 use crate::widgets::{EditDialog, EditFieldType};
 use crate::{lua, AppConfig, Command, LogMsg, Status};
@@ -35,6 +36,9 @@ const REGISTER_INFO_TEXT: &str =
     "(q)uit | (k) up | (j) down | (h) left | (l) right | (g) top | (G) bottom | (t)heme | (f)ormat | (e)dit | (o)rder";
 const REGISTER_INFO_TEXT_EXT: &str = "(r)estart | (s)witch mode | (z) compact view";
 const REGISTER_INFO_TEXT_CLIENT: &str = " | (d)isconnect | (c)onnect";
+const REGISTER_INFO_TEXT_SERVER: &str = " | (1-9) toggle fault";
+const ENABLE_FAULTS: &str = " | (x) activate faults";
+const DISABLE_FAULTS: &str = " | (x) deactivate faults";
 const ENABLE_LUA: &str = " | (p) activate lua";
 const DISABLE_LUA: &str = " | (p) deactivate lua";
 const LOGGER_INFO_TEXT: &str = "(m) up | (n) down | (b) left | (,) right | (v) top | (V) bottom";
@@ -142,6 +146,7 @@ impl UiTable {
 pub enum Popup {
     None,
     Edit(Register),
+    EditBit(Register),
 }
 
 pub enum Order {
@@ -166,16 +171,16 @@ impl Order {
     fn apply(&self, a: &(&String, &Register), b: &(&String, &Register)) -> std::cmp::Ordering {
         match self {
             Order::Default => Ord::cmp(&a.1.get_index(), &b.1.get_index()),
-            Order::NameAsc => Ord::cmp(&a.1.slave_id(), &b.1.slave_id())
+            Order::NameAsc => Ord::cmp(&a.1.unit(), &b.1.unit())
                 .then(a.0.cmp(b.0))
                 .then(a.1.address().cmp(&b.1.address())),
-            Order::NameDesc => Ord::cmp(&a.1.slave_id(), &b.1.slave_id())
+            Order::NameDesc => Ord::cmp(&a.1.unit(), &b.1.unit())
                 .then(b.0.cmp(a.0))
                 .then(a.1.address().cmp(&b.1.address())),
-            Order::AddressAsc => Ord::cmp(&a.1.slave_id(), &b.1.slave_id())
+            Order::AddressAsc => Ord::cmp(&a.1.unit(), &b.1.unit())
                 .then(a.1.address().cmp(&b.1.address()))
                 .then(a.0.cmp(b.0)),
-            Order::AddressDesc => Ord::cmp(&a.1.slave_id(), &b.1.slave_id())
+            Order::AddressDesc => Ord::cmp(&a.1.unit(), &b.1.unit())
                 .then(b.1.address().cmp(&a.1.address()))
                 .then(a.0.cmp(b.0)),
         }
@@ -403,6 +408,59 @@ impl App {
             .set_highlight_color(PALETTES[self.color_index].c400);
     }
 
+    pub fn toggle_faults(&mut self) {
+        let mut config = self.config.lock().expect("Unable to lock configuration");
+        let server = config.server.get_or_insert_default();
+        server.toggle_fault_injection();
+        let msg = if server.fault_injection() {
+            "Fault injection activated"
+        } else {
+            "Fault injection deactivated"
+        };
+        self.log_entries.push(LogMsg::info(msg));
+    }
+
+    pub fn toggle_fault(&mut self, index: usize) {
+        let mut config = self.config.lock().expect("Unable to lock configuration");
+        let server = config.server.get_or_insert_default();
+        if let Some(rule) = server.faults_mut().get_mut(index) {
+            rule.toggle();
+            self.log_entries.push(LogMsg::info(&format!(
+                "Fault {} ({}) {}",
+                rule.name(index),
+                rule.action(),
+                if rule.is_enabled() {
+                    "activated"
+                } else {
+                    "deactivated"
+                }
+            )));
+        }
+    }
+
+    /// Short summary of the fault injection state for the status line
+    fn faults_status(&self) -> String {
+        let config = self.config.lock().expect("Unable to lock configuration");
+        let server = config.server();
+        let faults = server.faults();
+        if faults.is_empty() {
+            return str!("");
+        }
+        let active = faults
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| f.is_enabled())
+            .map(|(i, f)| f.name(i))
+            .join(", ");
+        if !server.fault_injection() {
+            str!(" | Faults: off")
+        } else if active.is_empty() {
+            str!(" | Faults: none active")
+        } else {
+            format!(" | Faults: {active}")
+        }
+    }
+
     pub fn switch(&mut self) {
         self.show_as_hex = !self.show_as_hex;
     }
@@ -419,6 +477,10 @@ impl App {
             KeyCode::Char('z') => {
                 self.is_compact = !self.is_compact;
             }
+            KeyCode::Char('x') if matches!(self.mode, Mode::Server) => self.toggle_faults(),
+            KeyCode::Char(c @ '1'..='9') if matches!(self.mode, Mode::Server) => {
+                self.toggle_fault(c as usize - '1' as usize)
+            }
             KeyCode::Char('o') => {
                 self.ordering = self.ordering.next();
             }
@@ -457,57 +519,63 @@ impl App {
             KeyCode::Char('v') => self.log_move_top(),
             KeyCode::Char('V') => self.log_move_bottom(),
             KeyCode::Enter | KeyCode::Char('e') => {
-                if let Some(i) = self.register_table.table_state.selected() {
-                    let entry = self
-                        .register_handler
-                        .values()
-                        .iter()
-                        .filter(|(n, _)| !n.starts_with("hide_"))
-                        .sorted_by(|a, b| self.ordering.apply(a, b))
-                        .enumerate()
-                        .filter_map(|(j, (name, r))| {
-                            if j == i {
-                                Some((name.clone(), (*r).clone()))
-                            } else {
-                                None
-                            }
-                        })
-                        .collect::<Vec<(String, Register)>>();
-                    if !entry.is_empty() {
-                        let entry = entry.first().expect("Unable to retrieve first entry");
+                if let Some((name, register)) = self.selected_register() {
+                    let toggles = flag_toggles(&register);
+                    if !toggles.is_empty() {
                         self.log_entries.push(LogMsg::info(&format!(
-                            "Start edit of register {entry:#06X} ({entry})",
-                            entry = entry.1.address()
+                            "Start flag edit of register {entry:#06X} ({entry})",
+                            entry = register.address()
                         )));
-                        self.edit_dialog
-                            .set(EditFieldType::Name, Some(entry.0.clone()), None);
-                        self.edit_dialog.set(
-                            EditFieldType::Register,
-                            Some(format!("{a:#06X} ({a})", a = entry.1.address())),
-                            None,
-                        );
-                        self.edit_dialog
-                            .limit_to(entry.1.values().clone().unwrap_or(vec![]));
-                        self.edit_dialog.set_description(entry.1.description());
-                        self.edit_dialog.set(
-                            EditFieldType::DataType,
-                            Some(entry.1.r#type().label().to_string()),
-                            None,
-                        );
-                        self.edit_dialog.set(
-                            EditFieldType::DataResolution,
-                            Some(format!("{}", entry.1.get_resolution())),
-                            None,
+                        self.show_edit_dialog(
+                            "Toggle Flag",
+                            name,
+                            &register,
+                            toggles,
+                            String::new(),
                         );
-                        let e = entry.1.value().clone();
-                        self.edit_dialog.set(
-                            EditFieldType::Value,
-                            None,
-                            Some(format!("{} ({})", e.0, e.1)),
-                        );
-                        self.edit_dialog.focus();
-                        self.popup = Popup::Edit(entry.1.clone());
+                        self.popup = Popup::EditBit(register);
+                        return Ok(LoopAction::Continue);
                     }
+                    self.edit_value(name, register);
+                }
+            }
+            KeyCode::Char('E') => {
+                // Raw value entry, also for bitfield registers with named flags
+                if let Some((name, register)) = self.selected_register() {
+                    self.edit_value(name, register);
+                }
+            }
+            KeyCode::Char('i') => {
+                if let (Some(sender), Some((_, register))) = (cmd_sender, self.selected_register())
+                {
+                    sender.blocking_send(Command::ReadDeviceIdentification(register.unit()))?
+                }
+            }
+            KeyCode::Char('a') => {
+                if let (Some(sender), Some((_, register))) = (cmd_sender, self.selected_register())
+                {
+                    sender.blocking_send(Command::ReadDiagnostics(register.unit()))?
+                }
+            }
+            KeyCode::Char('w') => {
+                if let Some((name, register)) = self.selected_register() {
+                    self.log_entries.push(LogMsg::info(&format!(
+                        "Start bit edit of register {entry:#06X} ({entry})",
+                        entry = register.address()
+                    )));
+                    let raw = register
+                        .raw()
+                        .iter()
+                        .map(|v| format!("{v:#018b}"))
+                        .join(" ");
+                    self.show_edit_dialog(
+                        "Edit Bit",
+                        name,
+                        &register,
+                        vec![],
+                        format!("<bit>=<0|1> ({raw})"),
+                    );
+                    self.popup = Popup::EditBit(register);
                 }
             }
             _ => {}
@@ -515,80 +583,143 @@ impl App {
         Ok(LoopAction::Continue)
     }
 
+    /// Register at the selected row of the table
+    fn selected_register(&self) -> Option<(String, Register)> {
+        let i = self.register_table.table_state.selected()?;
+        self.register_handler
+            .values()
+            .iter()
+            .filter(|(n, _)| !n.starts_with("hide_"))
+            .sorted_by(|a, b| self.ordering.apply(a, b))
+            .nth(i)
+            .map(|(name, register)| (name.clone(), register.clone()))
+    }
+
+    /// Open the edit dialog for a new value of the register
+    fn edit_value(&mut self, name: String, register: Register) {
+        self.log_entries.push(LogMsg::info(&format!(
+            "Start edit of register {entry:#06X} ({entry})",
+            entry = register.address()
+        )));
+        let e = register.value().clone();
+        self.show_edit_dialog(
+            "Edit Register",
+            name,
+            &register,
+            register.values().clone().unwrap_or(vec![]),
+            format!("{} ({})", e.0, e.1),
+        );
+        self.popup = Popup::Edit(register);
+    }
+
+    fn show_edit_dialog(
+        &mut self,
+        title: &str,
+        name: String,
+        register: &Register,
+        values: Vec<Values>,
+        placeholder: String,
+    ) {
+        self.edit_dialog.set_title(title);
+        self.edit_dialog.set(EditFieldType::Name, Some(name), None);
+        self.edit_dialog.set(
+            EditFieldType::Register,
+            Some(format!("{a:#06X} ({a})", a = register.address())),
+            None,
+        );
+        self.edit_dialog.limit_to(values);
+        self.edit_dialog.set_description(register.description());
+        self.edit_dialog.set(
+            EditFieldType::DataType,
+            Some(register.r#type().label().to_string()),
+            None,
+        );
+        self.edit_dialog.set(
+            EditFieldType::DataResolution,
+            Some(format!("{}", register.get_resolution())),
+            None,
+        );
+        self.edit_dialog
+            .set(EditFieldType::Value, None, Some(placeholder));
+        self.edit_dialog.focus();
+    }
+
+    /// Change a single bit of the register, with FC 22 in client mode
+    fn write_bit(
+        &mut self,
+        register: &Register,
+        input: &str,
+        cmd_sender: &Option<Sender<Command>>,
+    ) -> anyhow::Result<()> {
+        let (bit, set) = parse_bit(input)?;
+        self.set_bit(register, bit, set, cmd_sender)
+    }
+
+    /// Set or clear a single bit of the register, with FC 22 in client mode. In server mode only
+    /// the bit is changed in memory, which also applies to input registers.
+    fn set_bit(
+        &mut self,
+        register: &Register,
+        bit: u16,
+        set: bool,
+        cmd_sender: &Option<Sender<Command>>,
+    ) -> anyhow::Result<()> {
+        match cmd_sender {
+            Some(sender) => sender.blocking_send(Command::write_bit(register, bit, set)?)?,
+            None => self.register_handler.set_bit(register, bit, set)?,
+        }
+        Ok(())
+    }
+
     fn handle_event_edit_dialog(&mut self, key: KeyEvent, cmd_sender: &Option<Sender<Command>>) {
         match key.code {
             KeyCode::Enter => {
                 match self.popup {
                     Popup::Edit(ref register) => {
                         if let Some(input) = self.edit_dialog.get_input(EditFieldType::Value) {
+                            let register = register.clone();
                             match register.r#type().encode(&input) {
                                 Ok(v) => {
-                                    if v.len() > register.raw().len() {
+                                    if v.len() > register.length() as usize {
                                         self.log_entries.push(LogMsg::err(
                                                                 "Provided input requires a longer register as available.",
                                                             ));
+                                    } else if let Some(bit) = register.bit() {
+                                        // Registers scoped to a single bit only change that bit
+                                        match bitfield::flag(&v).and_then(|set| {
+                                            self.set_bit(&register, bit, set, cmd_sender)
+                                        }) {
+                                            Ok(()) => self.popup = Popup::None,
+                                            Err(e) => self
+                                                .log_entries
+                                                .push(LogMsg::err(&format!("{}", e))),
+                                        }
                                     } else if let Some(ref sender) = cmd_sender {
-                                        let write_only =
-                                            register.access_type() == AccessType::WriteOnly;
-
-                                        let command: Option<Command>;
-                                        match register.function_code() {
-                                            tokio_modbus::FunctionCode::ReadCoils
-                                            | tokio_modbus::FunctionCode::ReadDiscreteInputs => {
-                                                if register.length() == 1 {
-                                                    command = Some(Command::WriteSingleCoil((
-                                                        register.slave_id(),
-                                                        register.address(),
-                                                        v[0] != 0,
-                                                        write_only,
-                                                    )))
+                                        match Command::write(&register, v) {
+                                            Ok(c) => {
+                                                if let Err(e) = sender.blocking_send(c) {
+                                                    self.log_entries
+                                                        .push(LogMsg::err(&format!("{}", e)));
                                                 } else {
-                                                    command = Some(Command::WriteMultipleCoils((
-                                                        register.slave_id(),
-                                                        register.address(),
-                                                        v.iter().map(|e| *e != 0).collect(),
-                                                        write_only,
-                                                    )))
+                                                    self.popup = Popup::None;
                                                 }
                                             }
-                                            tokio_modbus::FunctionCode::ReadHoldingRegisters
-                                            | tokio_modbus::FunctionCode::ReadInputRegisters => {
-                                                if register.length() == 1 {
-                                                    command = Some(Command::WriteSingleRegister((
-                                                        register.slave_id(),
-                                                        register.address(),
-                                                        v[0],
-                                                        write_only,
-                                                    )))
-                                                } else {
-                                                    command =
-                                                        Some(Command::WriteMultipleRegisters((
-                                                            register.slave_id(),
-                                                            register.address(),
-                                                            v.clone(),
-                                                            write_only,
-                                                        )))
-                                                }
-                                            }
-                                            _ => unreachable!(
-                                                "Invalid read code {}!",
-                                                register.function_code()
-                                            ),
-                                        }
-
-                                        if let Some(c) = command {
-                                            if let Err(e) = sender.blocking_send(c) {
+                                            Err(e) => {
                                                 self.log_entries
                                                     .push(LogMsg::err(&format!("{}", e)));
-                                            } else {
-                                                self.popup = Popup::None;
                                             }
                                         }
-                                    } else if let Err(e) = self.register_handler.set_values(
-                                        register.slave_id(),
-                                        register.address(),
-                                        &v,
-                                    ) {
+                                    } else if let Err(e) = if register.is_fifo() {
+                                        // Entered values are queued in server mode
+                                        self.register_handler.push_fifo(&register, &v)
+                                    } else {
+                                        self.register_handler.set_values(
+                                            register.unit(),
+                                            register.table(),
+                                            register.address(),
+                                            &v,
+                                        )
+                                    } {
                                         self.log_entries.push(LogMsg::err(&format!("{}", e)));
                                         if let Some(ref mut f) = self.file {
                                             let _ = writeln!(f, "{}", e);
@@ -606,6 +737,15 @@ impl App {
                             }
                         }
                     }
+                    Popup::EditBit(ref register) => {
+                        if let Some(input) = self.edit_dialog.get_input(EditFieldType::Value) {
+                            let register = register.clone();
+                            match self.write_bit(&register, &input, cmd_sender) {
+                                Ok(()) => self.popup = Popup::None,
+                                Err(e) => self.log_entries.push(LogMsg::err(&format!("{}", e))),
+                            }
+                        }
+                    }
                     Popup::None => panic!("No popup value."),
                 };
             }
@@ -696,7 +836,9 @@ impl App {
                                 }
                                 Err(e) => return Err(e),
                             },
-                            Popup::Edit(_) => self.handle_event_edit_dialog(key, &cmd_sender),
+                            Popup::Edit(_) | Popup::EditBit(_) => {
+                                self.handle_event_edit_dialog(key, &cmd_sender)
+                            }
                         }
                     }
                 }
@@ -725,6 +867,42 @@ impl App {
     }
 }
 
+/// Selection of the flags of a bitfield register, every entry toggles its flag with an input of
+/// the bit edit (`<bit>=<0|1>`)
+fn flag_toggles(register: &Register) -> Vec<Values> {
+    let Some(bits) = register.r#type().bits() else {
+        return vec![];
+    };
+    bits.fields()
+        .iter()
+        .filter(|field| field.is_flag())
+        .filter_map(|field| {
+            let bit = register.r#type().raw_bit(field.start);
+            let set = bitfield::bit(register.raw(), bit)?;
+            Some(ValueDef(crate::mem::register::ValueDef {
+                name: format!("{} ({})", field.name, if set { "On" } else { "Off" }),
+                value: Value::Str(format!("{}={}", bit, !set as u8)),
+            }))
+        })
+        .collect()
+}
+
+/// Parse the input of the bit edit dialog, e.g. `3=1`
+fn parse_bit(input: &str) -> anyhow::Result<(u16, bool)> {
+    let (bit, value) = input
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("Invalid input {input}, expected <bit>=<0|1>"))?;
+    let bit = bit
+        .trim()
+        .parse::<u16>()
+        .map_err(|e| anyhow::anyhow!("Invalid bit {bit} ({e})"))?;
+    match value.trim() {
+        "1" | "true" => Ok((bit, true)),
+        "0" | "false" => Ok((bit, false)),
+        v => Err(anyhow::anyhow!("Invalid bit value {v}, expected 0 or 1")),
+    }
+}
+
 fn ui(f: &mut Frame, app: &mut App, status: String) {
     let rects = Layout::vertical([
         Constraint::Min(5),
@@ -744,7 +922,7 @@ fn ui(f: &mut Frame, app: &mut App, status: String) {
     render_log_footer(f, app, rects[3]);
 
     // Render popup
-    if let Popup::Edit(_) = app.popup {
+    if let Popup::Edit(_) | Popup::EditBit(_) = app.popup {
         app.edit_dialog.render_ref(f.area(), f.buffer_mut())
     }
 }
@@ -815,7 +993,10 @@ fn render_register(f: &mut Frame, app: &mut App, area: Rect) {
             };
             [
                 format!("{}", r.access_type()),
-                format!("{}", r.slave_id()),
+                match r.connection() {
+                    Some(connection) => format!("{} ({})", r.slave_id(), connection),
+                    None => format!("{}", r.slave_id()),
+                },
                 str!(n),
                 format!("{:#06X} ({})", r.address(), r.address()),
                 r.r#type().label().to_string(),
@@ -963,7 +1144,7 @@ fn render_register_footer(f: &mut Frame, app: &App, area: Rect, status: String)
     let message = if let Mode::Client = app.mode {
         Line::from(str!("CLIENT MODE: ") + &status + " | " + app.ordering.to_string())
     } else {
-        Line::from(str!("SERVER MODE") + " | " + app.ordering.to_string())
+        Line::from(str!("SERVER MODE") + &app.faults_status() + " | " + app.ordering.to_string())
     };
     let status_footer = Paragraph::new(message)
         .style(
@@ -975,7 +1156,17 @@ fn render_register_footer(f: &mut Frame, app: &App, area: Rect, status: String)
     let mut ext = if let Mode::Client = app.mode {
         str!(REGISTER_INFO_TEXT_CLIENT)
     } else {
-        str!("")
+        let config = app.config.lock().expect("Unable to lock configuration");
+        match config.server.as_ref() {
+            Some(server) if !server.faults().is_empty() => {
+                if server.fault_injection() {
+                    str!(REGISTER_INFO_TEXT_SERVER) + DISABLE_FAULTS
+                } else {
+                    str!(REGISTER_INFO_TEXT_SERVER) + ENABLE_FAULTS
+                }
+            }
+            _ => str!(""),
+        }
     };
 
     if app.exec_lua {
@@ -19,6 +19,7 @@ pub enum FieldType {
 }
 
 pub struct EditDialog {
+    title: String,
     bg_color: Color,
     name: InputField,
     register: InputField,
@@ -33,6 +34,7 @@ pub struct EditDialog {
 impl EditDialog {
     pub fn new(focus_color: Color, bg_color: Color) -> Self {
         Self {
+            title: str!("Edit Register"),
             bg_color,
             name: InputField::new()
                 .title(str!("Name"))
@@ -130,6 +132,10 @@ impl EditDialog {
         self.values = values;
     }
 
+    pub fn set_title(&mut self, title: &str) {
+        self.title = str!(title);
+    }
+
     pub fn set_description(&mut self, description: Option<String>) {
         self.description = description;
     }
@@ -194,7 +200,7 @@ impl WidgetRef for EditDialog {
 
         // Render boxed dialog
         let block = Block::bordered()
-            .title("Edit Register")
+            .title(self.title.as_str())
             .title_alignment(Alignment::Center)
             .bg(self.bg_color);
         let inner = block.inner(area);